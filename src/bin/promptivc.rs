@@ -1,11 +1,20 @@
 use std::io::{self, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use clap::{Parser, ValueEnum};
-use reqwest::Client;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use notify::Watcher;
 use serde_json::json;
 
-use promptivd::models::{InsertTextRequest, Placement, SessionPolicy, SourceInfo, TargetSpec};
+use promptivd::client::{ClientError, InsertClient, InsertResponse};
+use promptivd::error::ValidationError;
+use promptivd::models::{
+    code_fence_language, InsertMode, InsertTextRequest, Placement, SessionPolicy, SourceInfo,
+    TargetSpec,
+};
 
 #[derive(Debug, Copy, Clone, ValueEnum)]
 enum SessionPolicyArg {
@@ -27,6 +36,29 @@ impl From<SessionPolicyArg> for SessionPolicy {
     }
 }
 
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum ModeArg {
+    #[value(name = "append")]
+    Append,
+    #[value(name = "prepend")]
+    Prepend,
+    #[value(name = "replace")]
+    Replace,
+    #[value(name = "insert")]
+    Insert,
+}
+
+impl From<ModeArg> for InsertMode {
+    fn from(value: ModeArg) -> Self {
+        match value {
+            ModeArg::Append => InsertMode::Append,
+            ModeArg::Prepend => InsertMode::Prepend,
+            ModeArg::Replace => InsertMode::Replace,
+            ModeArg::Insert => InsertMode::Insert,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, ValueEnum)]
 enum PlacementArg {
     #[value(name = "top")]
@@ -42,7 +74,10 @@ impl From<PlacementArg> for Placement {
         match value {
             PlacementArg::Top => Placement::Top,
             PlacementArg::Bottom => Placement::Bottom,
-            PlacementArg::Cursor => Placement::Cursor,
+            PlacementArg::Cursor => Placement::Cursor {
+                offset: None,
+                replace_selection: false,
+            },
         }
     }
 }
@@ -51,14 +86,19 @@ impl From<PlacementArg> for Placement {
 #[command(name = "promptivc")]
 #[command(about = "CLI client for promptivd daemon")]
 #[command(version)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Server URL
-    #[arg(long, default_value = "http://127.0.0.1:8787")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Server URL, or `http+unix://` followed by the percent-encoded path of a Unix socket
+    #[arg(long, global = true, default_value = "http://127.0.0.1:8787")]
     server: String,
 
-    /// Source file path
+    /// Source file path. Repeat to send several files, which are then read and combined into
+    /// one snippet instead of taking TEXT or stdin
     #[arg(short = 'f', long)]
-    path: Option<PathBuf>,
+    path: Vec<PathBuf>,
 
     /// Client label
     #[arg(short, long, default_value = "CLI")]
@@ -68,12 +108,16 @@ struct Cli {
     #[arg(long)]
     stdin: bool,
 
+    /// Replace invalid UTF-8 sequences in stdin instead of rejecting the input
+    #[arg(long)]
+    lossy: bool,
+
     /// Text content (if not reading from stdin)
     #[arg(value_name = "TEXT")]
     content: Option<String>,
 
     /// Target provider
-    #[arg(long = "provider", value_name = "PROVIDER")]
+    #[arg(long = "provider", global = true, value_name = "PROVIDER")]
     target_provider: Option<String>,
 
     /// Session policy
@@ -84,31 +128,282 @@ struct Cli {
     #[arg(long = "placement", value_enum, value_name = "PLACEMENT")]
     placement: Option<PlacementArg>,
 
-    /// Show verbose output
+    /// Character offset into the input to insert at; implies --placement cursor
+    #[arg(long, value_name = "OFFSET")]
+    cursor_offset: Option<usize>,
+
+    /// Replace the current selection instead of inserting; implies --placement cursor
+    #[arg(long)]
+    replace_selection: bool,
+
+    /// How the text combines with existing content at the placement (default: append)
+    #[arg(long, value_enum, value_name = "MODE")]
+    mode: Option<ModeArg>,
+
+    /// Content type of the snippet (inferred from --path when omitted)
+    #[arg(long, value_name = "MIME")]
+    content_type: Option<String>,
+
+    /// Format wrapped around each snippet, with {path}, {content}, {timestamp} and {lang}
+    /// placeholders; write {{ or }} for a literal brace. Defaults to
+    /// "Snippet from {path}:\n{content}\n---\n"
+    #[arg(long, value_name = "FORMAT", value_parser = SnippetTemplate::parse)]
+    template: Option<SnippetTemplate>,
+
+    /// Send the content as-is instead of wrapping it in a template
+    #[arg(long, conflicts_with = "template")]
+    no_template: bool,
+
+    /// Dispatch priority; higher values are sent to the sink first
+    #[arg(long, value_name = "PRIORITY")]
+    priority: Option<u8>,
+
+    /// Keep running and resend the --path files whenever one of them changes
+    #[arg(long, requires = "path", conflicts_with_all = ["stdin", "content"])]
+    watch: bool,
+
+    /// With --watch, how long a file must stay unchanged before it is resent (milliseconds)
+    #[arg(long, value_name = "MS", default_value_t = 300, requires = "watch")]
+    debounce_ms: u64,
+
+    /// Print the daemon's JSON response on one line instead of a summary. Errors are printed as
+    /// a JSON object too, and diagnostics go to stderr
+    #[arg(long)]
+    json: bool,
+
+    /// Speak HTTP/2 to the daemon without negotiating it first, so requests share one
+    /// connection. Not supported with http+unix:// servers
+    #[arg(long, global = true)]
+    http2_prior_knowledge: bool,
+
+    /// Show verbose output, including whether each request reused a connection
     #[arg(short, long)]
     verbose: bool,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Validate a JSON request payload offline, without contacting the daemon
+    Validate {
+        /// Path to the JSON payload
+        #[arg(long, value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Measure insert throughput and latency against a running daemon
+    Bench(BenchArgs),
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct BenchArgs {
+    /// Number of concurrent workers
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Total number of inserts to send
+    #[arg(long, default_value_t = 100)]
+    requests: usize,
+
+    /// Size in bytes of each inserted text
+    #[arg(long, default_value_t = 256)]
+    text_size: usize,
+}
+
+#[derive(Debug)]
+struct BenchSummary {
+    requests: usize,
+    succeeded: usize,
+    failed: usize,
+    elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum PayloadError {
+    #[error("Invalid JSON: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Invalid(#[from] ValidationError),
+}
+
+#[derive(Debug, thiserror::Error)]
+enum InputError {
+    #[error("Failed to read stdin: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Input is not valid UTF-8 (invalid byte sequence at offset {offset}); pass --lossy to replace invalid sequences")]
+    InvalidUtf8 { offset: usize },
+
+    #[error("Failed to read {}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+
+    #[error("TEXT and --stdin cannot be used with several --path files")]
+    ContentWithFiles,
+
+    #[error("--cursor-offset and --replace-selection only apply to --placement cursor")]
+    CursorOptionsWithoutCursor,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+enum TemplateError {
+    #[error(
+        "Unknown placeholder {{{name}}}; expected {{path}}, {{content}}, {{timestamp}} or {{lang}}"
+    )]
+    UnknownPlaceholder { name: String },
+
+    #[error("Unclosed {{ at offset {offset}; write {{{{ for a literal brace")]
+    Unclosed { offset: usize },
+
+    #[error("Unmatched }} at offset {offset}; write }}}} for a literal brace")]
+    Unmatched { offset: usize },
+}
+
+/// Format the CLI has always wrapped snippets in.
+const DEFAULT_TEMPLATE: &str = "Snippet from {path}:\n{content}\n---\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Path,
+    Content,
+    Timestamp,
+    Lang,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Parsed `--template`, rendered once per snippet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SnippetTemplate {
+    segments: Vec<Segment>,
+}
+
+impl Default for SnippetTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TEMPLATE).expect("default template is valid")
+    }
+}
+
+impl SnippetTemplate {
+    fn parse(format: &str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.char_indices().peekable();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '{' if chars.next_if(|&(_, c)| c == '{').is_some() => literal.push('{'),
+                '}' if chars.next_if(|&(_, c)| c == '}').is_some() => literal.push('}'),
+                '{' => {
+                    let start = offset + 1;
+                    let end = format[start..]
+                        .find('}')
+                        .map(|len| start + len)
+                        .ok_or(TemplateError::Unclosed { offset })?;
+                    let placeholder = match &format[start..end] {
+                        "path" => Placeholder::Path,
+                        "content" => Placeholder::Content,
+                        "timestamp" => Placeholder::Timestamp,
+                        "lang" => Placeholder::Lang,
+                        name => {
+                            return Err(TemplateError::UnknownPlaceholder {
+                                name: name.to_string(),
+                            })
+                        }
+                    };
+                    while chars.next_if(|&(i, _)| i <= end).is_some() {}
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                '}' => return Err(TemplateError::Unmatched { offset }),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Renders the snippet of `content`, read from `path` or from stdin when unset. `{lang}` is
+    /// the code fence language inferred from the path's extension, empty if there is none.
+    fn render(&self, content: &str, path: Option<&Path>, timestamp: DateTime<Utc>) -> String {
+        let mut out = String::with_capacity(content.len() + 64);
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Placeholder(Placeholder::Path) => match path {
+                    Some(path) => out.push_str(&path.to_string_lossy()),
+                    None => out.push_str("<stdin>"),
+                },
+                Segment::Placeholder(Placeholder::Content) => out.push_str(content.trim()),
+                Segment::Placeholder(Placeholder::Timestamp) => {
+                    out.push_str(&timestamp.to_rfc3339())
+                }
+                Segment::Placeholder(Placeholder::Lang) => out.push_str(
+                    path.and_then(infer_content_type)
+                        .and_then(code_fence_language)
+                        .unwrap_or_default(),
+                ),
+            }
+        }
+        out
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+
+    match &cli.command {
+        Some(Command::Validate { file }) => return run_validate(file),
+        Some(Command::Bench(args)) => {
+            let client = insert_client(&cli.server, cli.http2_prior_knowledge)
+                .unwrap_or_else(|e| exit_with_error(cli.json, e));
+            let summary = run_bench(&client, args, cli.target_provider.clone()).await;
+            print!("{}", summary);
+            return Ok(());
+        }
+        None => {}
+    }
 
-    // Initialize logging if verbose
+    // Initialize logging if verbose, on stderr so stdout only carries the result
     if cli.verbose {
-        tracing_subscriber::fmt::init();
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .init();
     }
+    let json = cli.json;
 
-    // Get content from stdin or arguments
-    let content = if cli.stdin || cli.content.is_none() {
-        read_from_stdin()?
+    // Unset in raw mode, where content is sent unwrapped
+    let template = (!cli.no_template).then(|| cli.template.take().unwrap_or_default());
+
+    // Get content from stdin or arguments, or from the files themselves when there are several
+    // or they are watched
+    let text = if cli.watch || cli.path.len() > 1 {
+        if cli.content.is_some() || cli.stdin {
+            exit_with_error(json, InputError::ContentWithFiles);
+        }
+        combine_files(&cli.path, template.as_ref()).unwrap_or_else(|e| exit_with_error(json, e))
     } else {
-        cli.content.unwrap()
+        let content = match cli.content.take() {
+            Some(content) if !cli.stdin => content,
+            _ => read_from_stdin(cli.lossy).unwrap_or_else(|e| exit_with_error(json, e)),
+        };
+
+        if content.trim().is_empty() {
+            exit_with_error(json, "No content provided");
+        }
+        add_snippet_template(&content, cli.path.first(), template.as_ref())
     };
 
-    if content.trim().is_empty() {
-        eprintln!("Error: No content provided");
-        std::process::exit(1);
-    }
+    let placement = placement(cli.placement, cli.cursor_offset, cli.replace_selection)
+        .unwrap_or_else(|e| exit_with_error(json, e));
 
     // Build optional target specification if provider metadata is supplied
     let target = if cli.target_provider.is_some() || cli.session_policy.is_some() {
@@ -120,41 +415,81 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         None
     };
 
+    let mut metadata = json!({
+        "cli_version": env!("CARGO_PKG_VERSION"),
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    });
+    if cli.path.len() > 1 {
+        let files: Vec<String> = cli
+            .path
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        metadata["files"] = files.into();
+    }
+
     // Create the request
     let request = InsertTextRequest {
         schema_version: "1.0".to_string(),
         source: SourceInfo {
             client: "cli".to_string(),
             label: Some(cli.label),
-            path: cli.path.as_ref().map(|p| p.to_string_lossy().to_string()),
+            path: describe_paths(&cli.path),
         },
-        text: add_snippet_template(&content, cli.path.as_ref()),
-        placement: cli.placement.map(Into::into),
+        text,
+        placement,
+        mode: cli.mode.map(Into::into).unwrap_or_default(),
         target,
-        metadata: Some(json!({
-            "cli_version": env!("CARGO_PKG_VERSION"),
-            "timestamp": chrono::Utc::now().to_rfc3339()
-        })),
+        metadata: Some(metadata),
+        priority: cli.priority,
+        content_type: resolve_content_type(cli.content_type.clone(), &cli.path),
+        ..Default::default()
     };
 
     // Create HTTP client
-    let client = Client::new();
-    let request_builder = client
-        .post(format!("{}/v1/insert", cli.server))
-        .json(&request);
+    let client = insert_client(&cli.server, cli.http2_prior_knowledge)
+        .unwrap_or_else(|e| exit_with_error(json, e));
 
     if cli.verbose {
-        println!("Sending request to: {}/v1/insert", cli.server);
+        eprintln!("Sending request to: {}", client.insert_url());
     }
 
-    let response = request_builder.send().await?;
-    let status = response.status();
-    let body: serde_json::Value = response.json().await?;
+    if cli.watch {
+        let debounce = Duration::from_millis(cli.debounce_ms);
+        return run_watch(
+            &client,
+            request,
+            &cli.path,
+            template.as_ref(),
+            debounce,
+            json,
+            cli.verbose,
+        )
+        .await;
+    }
 
-    let job_id = body
-        .get("job_id")
-        .and_then(|v| v.as_str())
-        .unwrap_or("<unknown>");
+    let response = client
+        .insert(&request)
+        .await
+        .unwrap_or_else(|e| exit_with_error(json, e));
+    if cli.verbose {
+        report_connection(&response);
+    }
+    let status = response.status;
+    let job_id = response.job_id();
+    let body = &response.body;
+
+    if json {
+        println!("{}", body);
+        if !status.is_success() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    for warning in response.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
 
     if !status.is_success() {
         let error_message = body
@@ -163,6 +498,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .unwrap_or("Request failed");
         eprintln!("Job {} failed (status {})", job_id, status);
         eprintln!("Error: {}", error_message);
+        if cli.verbose {
+            if let Some(code) = body.get("code").and_then(|v| v.as_str()) {
+                eprintln!("Error code: {}", code);
+            }
+        }
         std::process::exit(1);
     }
 
@@ -180,30 +520,536 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn read_from_stdin() -> Result<String, io::Error> {
-    let mut buffer = String::new();
-    io::stdin().read_to_string(&mut buffer)?;
-    Ok(buffer)
+/// The client for the `--server` daemon, shared by every request the invocation sends.
+fn insert_client(server: &str, http2_prior_knowledge: bool) -> Result<InsertClient, ClientError> {
+    if http2_prior_knowledge {
+        InsertClient::http2_prior_knowledge(server)
+    } else {
+        Ok(InsertClient::new(server))
+    }
+}
+
+/// Tells on stderr whether `response` came over a connection an earlier request opened.
+fn report_connection(response: &InsertResponse) {
+    if let Some(reused) = response.reused_connection {
+        let connection = if reused { "reused" } else { "new" };
+        eprintln!(
+            "Job {} sent over a {} connection",
+            response.job_id(),
+            connection
+        );
+    }
+}
+
+/// Error object printed in place of a response body with `--json`.
+fn error_json(error: &dyn std::fmt::Display) -> serde_json::Value {
+    json!({"status": "error", "error": error.to_string()})
+}
+
+/// Reports `error` and exits nonzero: on stdout as a JSON line with `json`, so scripts always
+/// get JSON to parse, or on stderr otherwise.
+fn exit_with_error(json: bool, error: impl std::fmt::Display) -> ! {
+    if json {
+        println!("{}", error_json(&error));
+    } else {
+        eprintln!("Error: {}", error);
+    }
+    std::process::exit(1);
+}
+
+/// Sends `request` once, then again with the current contents of `paths` whenever one of them
+/// changes, until Ctrl-C. Each send prints one line, or the JSON response with `json`. Every send
+/// goes through `client`, so they share its kept-alive connection.
+async fn run_watch(
+    client: &InsertClient,
+    mut request: InsertTextRequest,
+    paths: &[PathBuf],
+    template: Option<&SnippetTemplate>,
+    debounce: Duration,
+    json: bool,
+    verbose: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let watched = paths
+        .iter()
+        .map(|path| path.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    let (changed_tx, mut changed_rx) = tokio::sync::mpsc::unbounded_channel();
+    let filter = watched.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        if !event.kind.is_access() && event.paths.iter().any(|p| filter.contains(p)) {
+            let _ = changed_tx.send(());
+        }
+    })?;
+    // Editors often save by replacing the file, which would end a watch on the file itself
+    let mut parents: Vec<&Path> = watched.iter().filter_map(|path| path.parent()).collect();
+    parents.sort();
+    parents.dedup();
+    for parent in parents {
+        watcher.watch(parent, notify::RecursiveMode::NonRecursive)?;
+    }
+    eprintln!(
+        "Watching {} for changes, press Ctrl-C to stop",
+        describe_paths(paths).unwrap_or_default()
+    );
+
+    let mut last_sent = None;
+    loop {
+        if last_sent.as_ref() != Some(&request.text) {
+            let result = client.insert(&request).await;
+            if let (true, Ok(response)) = (verbose, &result) {
+                report_connection(response);
+            }
+            match result {
+                Ok(response) if json => println!("{}", response.body),
+                Ok(response) if response.status.is_success() => {
+                    let status = response.body.get("status").and_then(|v| v.as_str());
+                    println!("Job {}: {}", response.job_id(), status.unwrap_or("ok"));
+                }
+                Ok(response) => {
+                    let error = response.body.get("error").and_then(|v| v.as_str());
+                    eprintln!(
+                        "Job {} failed (status {}): {}",
+                        response.job_id(),
+                        response.status,
+                        error.unwrap_or("Request failed")
+                    );
+                }
+                Err(e) if json => println!("{}", error_json(&e)),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+            last_sent = Some(request.text.clone());
+        }
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            changed = changed_rx.recv() => {
+                if changed.is_none() {
+                    return Ok(());
+                }
+            }
+        }
+        // A single save often fires several events; wait for them to settle
+        while let Ok(Some(())) = tokio::time::timeout(debounce, changed_rx.recv()).await {}
+
+        match combine_files(paths, template) {
+            Ok(text) => request.text = text,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        }
+        if let Some(metadata) = request.metadata.as_mut() {
+            metadata["timestamp"] = chrono::Utc::now().to_rfc3339().into();
+        }
+    }
+}
+
+async fn run_bench(
+    client: &InsertClient,
+    args: &BenchArgs,
+    provider: Option<String>,
+) -> BenchSummary {
+    let request = Arc::new(InsertTextRequest {
+        schema_version: "1.0".to_string(),
+        source: SourceInfo {
+            client: "cli-bench".to_string(),
+            label: Some("CLI bench".to_string()),
+            path: None,
+        },
+        text: "x".repeat(args.text_size.max(1)),
+        placement: None,
+        target: provider.map(|provider| TargetSpec {
+            provider: Some(provider),
+            session_policy: None,
+        }),
+        ..Default::default()
+    });
+
+    let next = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let workers: Vec<_> = (0..args.concurrency.max(1))
+        .map(|_| {
+            let client = client.clone();
+            let request = Arc::clone(&request);
+            let next = Arc::clone(&next);
+            let total = args.requests;
+            tokio::spawn(async move {
+                let mut results = Vec::new();
+                while next.fetch_add(1, Ordering::Relaxed) < total {
+                    let sent = Instant::now();
+                    let ok =
+                        matches!(client.insert(&request).await, Ok(r) if r.status.is_success());
+                    results.push((ok, sent.elapsed()));
+                }
+                results
+            })
+        })
+        .collect();
+
+    let mut summary = BenchSummary {
+        requests: args.requests,
+        succeeded: 0,
+        failed: 0,
+        elapsed: Duration::ZERO,
+        latencies: Vec::with_capacity(args.requests),
+    };
+    for worker in workers {
+        for (ok, latency) in worker.await.unwrap_or_default() {
+            if ok {
+                summary.succeeded += 1;
+            } else {
+                summary.failed += 1;
+            }
+            summary.latencies.push(latency);
+        }
+    }
+    summary.elapsed = started.elapsed();
+    summary.latencies.sort();
+    summary
+}
+
+impl BenchSummary {
+    /// Nearest-rank percentile over the sorted latencies.
+    fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = ((p / 100.0) * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl std::fmt::Display for BenchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let throughput = self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(f, "Requests:   {}", self.requests)?;
+        writeln!(f, "Succeeded:  {}", self.succeeded)?;
+        writeln!(f, "Failed:     {}", self.failed)?;
+        writeln!(f, "Elapsed:    {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "Throughput: {:.1} req/s", throughput)?;
+        for (label, p) in [("p50", 50.0), ("p95", 95.0), ("p99", 99.0)] {
+            writeln!(
+                f,
+                "Latency {}: {:.2}ms",
+                label,
+                self.percentile(p).as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn run_validate(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let json = std::fs::read_to_string(path)?;
+
+    match validate_payload(&json) {
+        Ok(_) => {
+            println!("{}: valid", path.display());
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("{}: invalid", path.display());
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
-fn add_snippet_template(content: &str, path: Option<&PathBuf>) -> String {
-    let path_str = path
-        .map(|p| p.to_string_lossy().to_string())
-        .unwrap_or_else(|| "<stdin>".to_string());
+fn validate_payload(json: &str) -> Result<InsertTextRequest, PayloadError> {
+    let request: InsertTextRequest = serde_json::from_str(json)?;
+    request.validate()?;
+    Ok(request)
+}
 
-    format!("Snippet from {}:\n{}\n---\n", path_str, content.trim())
+fn read_from_stdin(lossy: bool) -> Result<String, InputError> {
+    let mut buffer = Vec::new();
+    io::stdin().read_to_end(&mut buffer)?;
+    decode_input(buffer, lossy)
+}
+
+fn decode_input(bytes: Vec<u8>, lossy: bool) -> Result<String, InputError> {
+    if lossy {
+        return Ok(String::from_utf8_lossy(&bytes).into_owned());
+    }
+
+    String::from_utf8(bytes).map_err(|e| InputError::InvalidUtf8 {
+        offset: e.utf8_error().valid_up_to(),
+    })
+}
+
+/// Maps a file extension to one of the content types accepted by the daemon.
+fn infer_content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let content_type = match extension.as_str() {
+        "md" | "markdown" => "text/markdown",
+        "txt" => "text/plain",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "c" | "h" => "text/x-c",
+        "cc" | "cpp" | "cxx" | "hpp" => "text/x-c++",
+        "go" => "text/x-go",
+        "java" => "text/x-java",
+        "js" | "mjs" | "cjs" => "text/javascript",
+        "py" => "text/x-python",
+        "rs" => "text/x-rust",
+        "sh" | "bash" | "zsh" => "text/x-shellscript",
+        "ts" | "tsx" => "text/x-typescript",
+        "yaml" | "yml" => "text/x-yaml",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+/// `SourceInfo.path` for the given files: the path itself for one file, a count for several.
+fn describe_paths(paths: &[PathBuf]) -> Option<String> {
+    match paths {
+        [] => None,
+        [path] => Some(path.to_string_lossy().to_string()),
+        _ => Some(format!("{} files", paths.len())),
+    }
+}
+
+/// Content type inferred for the files, when they all agree on one.
+fn common_content_type(paths: &[PathBuf]) -> Option<&'static str> {
+    let (first, rest) = paths.split_first()?;
+    let content_type = infer_content_type(first)?;
+    rest.iter()
+        .all(|p| infer_content_type(p) == Some(content_type))
+        .then_some(content_type)
+}
+
+/// The request's content type: `--content-type` when given, or else the one inferred from the
+/// extensions of the `--path` files.
+fn resolve_content_type(explicit: Option<String>, paths: &[PathBuf]) -> Option<String> {
+    explicit.or_else(|| common_content_type(paths).map(String::from))
+}
+
+/// Placement requested on the command line, where the cursor options select cursor placement on
+/// their own but conflict with any other.
+fn placement(
+    arg: Option<PlacementArg>,
+    cursor_offset: Option<usize>,
+    replace_selection: bool,
+) -> Result<Option<Placement>, InputError> {
+    if cursor_offset.is_none() && !replace_selection {
+        return Ok(arg.map(Into::into));
+    }
+    match arg {
+        None | Some(PlacementArg::Cursor) => Ok(Some(Placement::Cursor {
+            offset: cursor_offset,
+            replace_selection,
+        })),
+        Some(_) => Err(InputError::CursorOptionsWithoutCursor),
+    }
+}
+
+/// Reads every file and joins their templated snippets, in the order given.
+fn combine_files(
+    paths: &[PathBuf],
+    template: Option<&SnippetTemplate>,
+) -> Result<String, InputError> {
+    let mut snippets = Vec::with_capacity(paths.len());
+    for path in paths {
+        let content = std::fs::read_to_string(path).map_err(|source| InputError::File {
+            path: path.clone(),
+            source,
+        })?;
+        snippets.push(add_snippet_template(&content, Some(path), template));
+    }
+    Ok(snippets.join("\n"))
+}
+
+/// Wraps `content` in `template`, or returns it unchanged without one.
+fn add_snippet_template(
+    content: &str,
+    path: Option<&PathBuf>,
+    template: Option<&SnippetTemplate>,
+) -> String {
+    match template {
+        Some(template) => template.render(content, path.map(PathBuf::as_path), Utc::now()),
+        None => content.to_string(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::routing::{get, post};
+    use clap::CommandFactory;
+    use futures_util::{SinkExt, StreamExt};
+    use promptivd::config::ServerConfig;
+    use promptivd::handlers::AppState;
+    use promptivd::websocket::{AckStatus, RelayMessage, SinkManager, SinkMessage};
+    use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+    /// Serves the insert and sink routes in-process with a sink that acks every job.
+    async fn spawn_daemon_with_auto_ack_sink() -> String {
+        let config = ServerConfig::default();
+        let sink_manager = Arc::new(SinkManager::new(config.clone()));
+        let app = axum::Router::new()
+            .route("/v1/insert", post(promptivd::handlers::insert_job))
+            .route("/v1/sink/ws", get(promptivd::handlers::websocket_handler))
+            .with_state(AppState::new(Arc::clone(&sink_manager)).unwrap());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let (ws, _) = connect_async(format!("ws://{}/v1/sink/ws", addr))
+            .await
+            .unwrap();
+        let (mut tx, mut rx) = ws.split();
+        let register = SinkMessage::Register {
+            schema_version: "1.0".to_string(),
+            version: "test".to_string(),
+            capabilities: vec!["insert".to_string()],
+            providers: vec!["chatgpt".to_string()],
+            provider_capabilities: None,
+            binary_frames: false,
+        };
+        tx.send(Message::Text(serde_json::to_string(&register).unwrap()))
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            while let Some(Ok(Message::Text(text))) = rx.next().await {
+                if let Ok(RelayMessage::InsertText { id, .. }) = serde_json::from_str(&text) {
+                    let ack = SinkMessage::Ack {
+                        schema_version: "1.0".to_string(),
+                        id,
+                        status: AckStatus::Ok,
+                        error: None,
+                        echo: None,
+                        warnings: Vec::new(),
+                        session_active: None,
+                    };
+                    let json = serde_json::to_string(&ack).unwrap();
+                    if tx.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        while !sink_manager.has_active_sink() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from([
+            "promptivc",
+            "bench",
+            "--server",
+            "http://localhost:9999",
+            "--provider",
+            "claude",
+            "--requests",
+            "3",
+        ])
+        .unwrap();
+        assert_eq!(cli.server, "http://localhost:9999");
+        assert_eq!(cli.target_provider.as_deref(), Some("claude"));
+        assert!(matches!(
+            cli.command,
+            Some(Command::Bench(BenchArgs { requests: 3, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_watch_requires_files_and_excludes_other_input() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["promptivc"], args].concat());
+
+        let cli = parse(&["--watch", "-f", "notes.md", "--debounce-ms", "50"]).unwrap();
+        assert!(cli.watch);
+        assert_eq!(cli.debounce_ms, 50);
+
+        for args in [
+            &["--watch"][..],
+            &["--watch", "-f", "notes.md", "--stdin"],
+            &["--watch", "-f", "notes.md", "some text"],
+            &["-f", "notes.md", "--debounce-ms", "50"],
+        ] {
+            assert!(parse(args).is_err(), "{:?} should be rejected", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bench_against_in_process_daemon() {
+        let server = spawn_daemon_with_auto_ack_sink().await;
+        let client = InsertClient::new(server);
+        let args = BenchArgs {
+            concurrency: 2,
+            requests: 5,
+            text_size: 32,
+        };
+
+        let summary = run_bench(&client, &args, Some("chatgpt".to_string())).await;
+
+        assert_eq!(summary.succeeded, 5);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(summary.latencies.len(), 5);
+        let output = summary.to_string();
+        assert!(output.contains("Succeeded:  5"));
+        assert!(output.contains("Latency p50:"));
+        assert!(output.contains("Latency p99:"));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_inserts_reuse_the_connection() {
+        let server = spawn_daemon_with_auto_ack_sink().await;
+        let request = InsertTextRequest {
+            schema_version: "1.0".to_string(),
+            source: SourceInfo {
+                client: "cli".to_string(),
+                label: None,
+                path: None,
+            },
+            text: "hello".to_string(),
+            ..Default::default()
+        };
+
+        for client in [
+            InsertClient::new(server.clone()),
+            InsertClient::http2_prior_knowledge(server.clone()).unwrap(),
+        ] {
+            let mut reused = Vec::new();
+            for _ in 0..3 {
+                let response = client.insert(&request).await.unwrap();
+                assert!(response.status.is_success(), "{}", response.body);
+                reused.push(response.reused_connection);
+            }
+            assert_eq!(reused, [Some(false), Some(true), Some(true)]);
+        }
+
+        assert!(matches!(
+            InsertClient::http2_prior_knowledge("http+unix://%2Ftmp%2Fpromptivd.sock"),
+            Err(ClientError::Http2OverUnix)
+        ));
+    }
+
+    #[test]
+    fn test_error_json_is_a_single_line_object() {
+        let error = error_json(&InputError::ContentWithFiles);
+
+        assert_eq!(error["status"], "error");
+        assert_eq!(error["error"], InputError::ContentWithFiles.to_string());
+        assert!(!error.to_string().contains('\n'));
+    }
 
     #[test]
     fn test_add_snippet_template() {
         let content = "Hello world";
         let path = Some(PathBuf::from("/test/file.txt"));
 
-        let result = add_snippet_template(content, path.as_ref());
+        let result =
+            add_snippet_template(content, path.as_ref(), Some(&SnippetTemplate::default()));
         assert!(result.contains("Snippet from /test/file.txt:"));
         assert!(result.contains("Hello world"));
         assert!(result.ends_with("---\n"));
@@ -212,7 +1058,233 @@ mod tests {
     #[test]
     fn test_add_snippet_template_no_path() {
         let content = "Hello world";
-        let result = add_snippet_template(content, None);
+        let result = add_snippet_template(content, None, Some(&SnippetTemplate::default()));
         assert!(result.contains("Snippet from <stdin>:"));
     }
+
+    #[test]
+    fn test_custom_template() {
+        let template =
+            SnippetTemplate::parse("```{lang} {{{path}}} at {timestamp}\n{content}\n```").unwrap();
+        let timestamp = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let result = template.render(
+            "  fn main() {}\n",
+            Some(Path::new("src/main.rs")),
+            timestamp,
+        );
+        assert_eq!(
+            result,
+            "```rust {src/main.rs} at 2024-05-01T12:00:00+00:00\nfn main() {}\n```"
+        );
+
+        let result = template.render("notes", None, timestamp);
+        assert!(result.starts_with("``` {<stdin>}"));
+    }
+
+    #[test]
+    fn test_no_template_sends_raw_content() {
+        let cli = Cli::try_parse_from(["promptivc", "--no-template", "hello"]).unwrap();
+        assert!(cli.no_template);
+        assert!(Cli::try_parse_from([
+            "promptivc",
+            "--no-template",
+            "--template",
+            "{content}",
+            "x"
+        ])
+        .is_err());
+
+        let content = "  indented\n";
+        assert_eq!(add_snippet_template(content, None, None), content);
+    }
+
+    #[test]
+    fn test_template_parse_errors() {
+        assert_eq!(
+            SnippetTemplate::parse("From {file}: {content}"),
+            Err(TemplateError::UnknownPlaceholder {
+                name: "file".to_string()
+            })
+        );
+        assert_eq!(
+            SnippetTemplate::parse("{content"),
+            Err(TemplateError::Unclosed { offset: 0 })
+        );
+        assert_eq!(
+            SnippetTemplate::parse("{content} }"),
+            Err(TemplateError::Unmatched { offset: 10 })
+        );
+
+        let err = Cli::try_parse_from(["promptivc", "--template", "{nope}", "x"])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Unknown placeholder {nope}"));
+    }
+
+    #[test]
+    fn test_infer_content_type() {
+        assert_eq!(
+            infer_content_type(Path::new("/src/main.rs")),
+            Some("text/x-rust")
+        );
+        assert_eq!(
+            infer_content_type(Path::new("README.MD")),
+            Some("text/markdown")
+        );
+        assert_eq!(infer_content_type(Path::new("Makefile")), None);
+        assert_eq!(infer_content_type(Path::new("archive.tar.gz")), None);
+
+        for path in ["a.md", "a.txt", "a.rs", "a.py", "a.ts", "a.yml", "a.cpp"] {
+            let content_type = infer_content_type(Path::new(path)).unwrap();
+            assert!(promptivd::models::ALLOWED_CONTENT_TYPES.contains(&content_type));
+        }
+    }
+
+    #[test]
+    fn test_cursor_options_select_cursor_placement() {
+        assert_eq!(
+            placement(Some(PlacementArg::Top), None, false).unwrap(),
+            Some(Placement::Top)
+        );
+        assert_eq!(placement(None, None, false).unwrap(), None);
+
+        let cursor = Some(Placement::Cursor {
+            offset: Some(4),
+            replace_selection: true,
+        });
+        assert_eq!(placement(None, Some(4), true).unwrap(), cursor);
+        assert_eq!(
+            placement(Some(PlacementArg::Cursor), Some(4), true).unwrap(),
+            cursor
+        );
+
+        assert!(matches!(
+            placement(Some(PlacementArg::Bottom), None, true),
+            Err(InputError::CursorOptionsWithoutCursor)
+        ));
+    }
+
+    #[test]
+    fn test_combine_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.rs", "b.rs", "c.md"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, format!("contents of {}\n", name)).unwrap();
+                path
+            })
+            .collect();
+
+        let template = SnippetTemplate::default();
+        let combined = combine_files(&paths, Some(&template)).unwrap();
+        let expected: Vec<String> = paths
+            .iter()
+            .map(|p| {
+                let content = std::fs::read_to_string(p).unwrap();
+                add_snippet_template(&content, Some(p), Some(&template))
+            })
+            .collect();
+        assert_eq!(combined, expected.join("\n"));
+
+        assert_eq!(describe_paths(&paths).as_deref(), Some("3 files"));
+        assert_eq!(
+            describe_paths(&paths[..1]),
+            Some(paths[0].to_string_lossy().to_string())
+        );
+        assert_eq!(describe_paths(&[]), None);
+
+        assert_eq!(common_content_type(&paths[..2]), Some("text/x-rust"));
+        assert_eq!(common_content_type(&paths), None);
+
+        let missing = dir.path().join("missing.rs");
+        let err = combine_files(&[paths[0].clone(), missing], None).unwrap_err();
+        assert!(err.to_string().contains("missing.rs"));
+    }
+
+    #[test]
+    fn test_explicit_content_type_overrides_inferred() {
+        let cli = Cli::try_parse_from([
+            "promptivc",
+            "--path",
+            "notes.md",
+            "--content-type",
+            "text/plain",
+            "hello",
+        ])
+        .unwrap();
+        assert_eq!(
+            resolve_content_type(cli.content_type, &cli.path).as_deref(),
+            Some("text/plain")
+        );
+
+        let cli = Cli::try_parse_from(["promptivc", "--path", "notes.md", "hello"]).unwrap();
+        assert_eq!(
+            resolve_content_type(cli.content_type, &cli.path).as_deref(),
+            Some("text/markdown")
+        );
+    }
+
+    #[test]
+    fn test_mode_flag() {
+        let mode = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["promptivc"], args, &["hello"]].concat()).unwrap();
+            cli.mode.map(InsertMode::from).unwrap_or_default()
+        };
+        assert_eq!(mode(&[]), InsertMode::Append);
+        assert_eq!(mode(&["--mode", "replace"]), InsertMode::Replace);
+        assert_eq!(mode(&["--mode", "prepend"]), InsertMode::Prepend);
+        assert!(Cli::try_parse_from(["promptivc", "--mode", "overwrite", "hello"]).is_err());
+    }
+
+    #[test]
+    fn test_decode_input_rejects_invalid_utf8() {
+        let bytes = b"caf\xc3\xa9 \xff\xfe".to_vec();
+
+        let err = decode_input(bytes, false).unwrap_err();
+        assert!(matches!(err, InputError::InvalidUtf8 { offset: 6 }));
+        assert!(err.to_string().contains("not valid UTF-8"));
+        assert!(err.to_string().contains("offset 6"));
+    }
+
+    #[test]
+    fn test_decode_input_lossy() {
+        let bytes = b"caf\xc3\xa9 \xff".to_vec();
+
+        let decoded = decode_input(bytes, true).unwrap();
+        assert_eq!(decoded, "caf\u{e9} \u{fffd}");
+    }
+
+    #[test]
+    fn test_validate_payload_valid() {
+        let json = r#"{
+            "schema_version": "1.0",
+            "source": {"client": "emacs", "label": null, "path": null},
+            "text": "Hello world",
+            "placement": {"type": "bottom"},
+            "target": null
+        }"#;
+
+        let request = validate_payload(json).unwrap();
+        assert_eq!(request.source.client, "emacs");
+    }
+
+    #[test]
+    fn test_validate_payload_missing_client() {
+        let json = r#"{
+            "schema_version": "1.0",
+            "source": {"client": "", "label": null, "path": null},
+            "text": "Hello world",
+            "placement": null,
+            "target": null
+        }"#;
+
+        assert!(matches!(
+            validate_payload(json),
+            Err(PayloadError::Invalid(ValidationError::MissingField { field })) if field == "source.client"
+        ));
+    }
 }
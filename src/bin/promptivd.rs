@@ -1,24 +1,44 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
 
 use axum::{
+    error_handling::HandleErrorLayer,
     extract::DefaultBodyLimit,
-    http::{HeaderValue, Method},
-    routing::{get, post},
+    handler::Handler,
+    http::Method,
+    middleware,
+    routing::{on, MethodFilter, MethodRouter},
     Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
 use tokio::signal;
+use tokio::sync::{mpsc, oneshot};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::ServiceBuilder;
 use tower_http::{
-    cors::CorsLayer,
+    cors::{AllowOrigin, CorsLayer},
     timeout::TimeoutLayer,
     trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
 };
-use tracing::{error, info, level_filters::LevelFilter};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{
+    layer::SubscriberExt, registry::LookupSpan, reload, util::SubscriberInitExt, Layer,
+};
 
-use promptivd::config::{AppConfig, ConfigError, LogFormat};
+use promptivd::config::{
+    AppConfig, ConfigError, ConfigFormat, ConfigSource, ExplainedValue, ListenSpec, LogFormat,
+    ServerConfig,
+};
 use promptivd::error::{AppError, AppResult};
-use promptivd::handlers::AppState;
+use promptivd::handlers::{check_client_auth, env_filter, AppState, LogReloadHandle};
+use promptivd::log_socket::LogSocketWriter;
+use promptivd::models::{RouteAuth, RouteInfo};
+use promptivd::queue::JobQueue;
 use promptivd::websocket::SinkManager;
 
 #[derive(Parser)]
@@ -34,7 +54,7 @@ struct Cli {
     #[arg(short, long, value_name = "LEVEL")]
     log_level: Option<String>,
 
-    /// Bind address
+    /// Bind address, replacing `server.bind_addrs`; `unix:/path` binds a Unix socket
     #[arg(short, long, value_name = "ADDR")]
     bind: Option<String>,
 
@@ -42,36 +62,84 @@ struct Cli {
     #[arg(long)]
     init_config: bool,
 
+    /// Format of the file written by --init-config (yaml, toml or json)
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "yaml",
+        requires = "init_config"
+    )]
+    format: ConfigFormat,
+
     /// Validate configuration and exit
     #[arg(long)]
     validate: bool,
+
+    /// Write the effective configuration, after file, env and CLI overrides, to FILE and exit.
+    /// The format follows the extension (.toml, .json or .yaml), defaulting to YAML.
+    #[arg(long, value_name = "FILE")]
+    export_config: Option<std::path::PathBuf>,
+
+    /// Replace secrets such as the admin token with a placeholder when exporting
+    #[arg(long, requires = "export_config")]
+    redact_secrets: bool,
+
+    /// Print every effective setting with the source (default, file, env or cli) that set it
+    #[arg(long)]
+    explain_config: bool,
 }
 
+/// Exit code when the listen address is already taken (sysexits `EX_UNAVAILABLE`).
+const EXIT_ADDR_IN_USE: u8 = 69;
+/// Exit code when binding the listen address is not permitted (sysexits `EX_NOPERM`).
+const EXIT_PERMISSION_DENIED: u8 = 77;
+/// Exit code when a second shutdown signal forces an immediate exit (128 + SIGINT).
+const EXIT_FORCED: i32 = 130;
+
 #[tokio::main]
-async fn main() -> AppResult<()> {
-    let cli = Cli::parse();
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
 
+fn exit_code(err: &AppError) -> u8 {
+    match err {
+        AppError::AddrInUse { .. } | AppError::SocketInUse { .. } => EXIT_ADDR_IN_USE,
+        AppError::BindPermissionDenied { .. } => EXIT_PERMISSION_DENIED,
+        _ => 1,
+    }
+}
+
+async fn run(cli: Cli) -> AppResult<()> {
     // Handle init-config command
     if cli.init_config {
-        return handle_init_config().await;
+        return handle_init_config(cli.format).await;
     }
 
-    // Load configuration
-    let mut config = AppConfig::from_file(cli.config.as_ref()).map_err(AppError::Config)?;
-
-    // Override config with CLI arguments
-    if let Some(log_level) = cli.log_level {
-        config.log_level = log_level;
+    if cli.explain_config {
+        for entry in explain_config(&cli)? {
+            println!("{} = {} ({})", entry.key, entry.value, entry.source);
+        }
+        return Ok(());
     }
 
-    if let Some(bind_addr) = cli.bind {
-        config.server.bind_addr = bind_addr.parse().map_err(|e| {
-            AppError::Config(ConfigError::Message(format!("Invalid bind address: {}", e)))
-        })?;
-    }
+    let config = load_config(&cli)?;
 
-    // Validate configuration
-    config.validate().map_err(AppError::Config)?;
+    if let Some(path) = &cli.export_config {
+        let exported = if cli.redact_secrets {
+            config.redacted()
+        } else {
+            config
+        };
+        exported.write_to_file(path)?;
+        println!("Exported configuration to: {}", path.display());
+        return Ok(());
+    }
 
     if cli.validate {
         println!("Configuration is valid");
@@ -79,56 +147,641 @@ async fn main() -> AppResult<()> {
     }
 
     // Initialize logging
-    init_logging(&config)?;
+    let log_reload = init_logging(&config)?;
 
     info!("Starting promptivd version {}", env!("CARGO_PKG_VERSION"));
     info!("Configuration loaded from: {:?}", cli.config);
-    info!("Server binding to: {}", config.server.bind_addr);
+    info!(
+        "Server binding to: {}",
+        describe_addrs(&config.server.bind_addrs)
+    );
 
     // Initialize components
-    let sink_manager = Arc::new(SinkManager::new(config.server.clone()));
+    let sink_manager = start_sink_manager(&config.server)?;
+    sink_manager
+        .jobs()
+        .spawn_eviction(config.server.job_retention);
 
     // Create application state
-    let state = AppState {
-        sink_manager: Arc::clone(&sink_manager),
-        config: config.server.clone(),
-    };
+    let state = AppState::new(Arc::clone(&sink_manager))
+        .map_err(AppError::Config)?
+        .with_log_reload(log_reload);
 
     // Create router
+    let drain_state = state.clone();
+    let reload_state = state.clone();
     let app = create_router(state, &config);
 
     // Create server
-    let listener = tokio::net::TcpListener::bind(&config.server.bind_addr)
-        .await
-        .map_err(AppError::Io)?;
+    let listeners = bind_all(&config.server.bind_addrs).await?;
+    let tls = match config.server.tls_paths().map_err(AppError::Config)? {
+        Some((cert, key)) => Some(RustlsConfig::from_pem_file(cert, key).await?),
+        None => None,
+    };
 
-    info!("Server started on {}", config.server.bind_addr);
+    info!(
+        tls = tls.is_some(),
+        "Server started on {}",
+        describe_addrs(&config.server.bind_addrs)
+    );
 
-    // Start server with graceful shutdown
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .map_err(AppError::Io)?;
+    // Start server with graceful shutdown; a second signal skips the drain
+    let (signal_tx, signal_rx) = mpsc::unbounded_channel();
+    tokio::spawn(forward_os_signals(signal_tx));
+    let (graceful_tx, graceful_rx) = oneshot::channel();
+    tokio::spawn(supervise_shutdown(signal_rx, graceful_tx, || {
+        std::process::exit(EXIT_FORCED)
+    }));
+
+    let (rebind_tx, rebind_rx) = mpsc::unbounded_channel();
+    let rebind_grace = config.server.rebind_drain_grace;
+    tokio::spawn(reload_on_hangup(
+        cli,
+        config.clone(),
+        reload_state,
+        rebind_tx,
+    ));
+
+    let drain_timeout = config.server.drain_timeout;
+    serve_with_rebind(app, tls, listeners, rebind_rx, rebind_grace, async move {
+        if graceful_rx.await.is_err() {
+            // Signal handling is gone; keep serving rather than shutting down unasked
+            std::future::pending::<()>().await;
+        }
+        // Sinks stay connected until accepted jobs are acked, so none are dropped
+        if !drain_state.drain(drain_timeout).await {
+            warn!(
+                pending_jobs = drain_state.sink_manager.jobs().pending(),
+                "Drain timed out; shutting down with jobs still pending"
+            );
+        }
+        sink_manager.close_all("Daemon shutting down").await;
+        #[cfg(feature = "sqlite")]
+        if let Some(history) = sink_manager.jobs().history() {
+            history.flush().await;
+        }
+    })
+    .await
+    .map_err(AppError::Io)?;
 
     info!("Server shutdown complete");
     Ok(())
 }
 
-fn create_router(state: AppState, config: &AppConfig) -> Router {
-    Router::new()
+/// An `axum::serve` task on one listener that can be told to stop accepting and drain.
+struct ServingListener {
+    stop: oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+impl ServingListener {
+    /// Serves plain HTTP, or HTTPS when `tls` is given.
+    fn spawn(listener: tokio::net::TcpListener, app: Router, tls: Option<RustlsConfig>) -> Self {
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = match tls {
+            None => tokio::spawn(async move {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(async move {
+                        let _ = stopped.await;
+                    })
+                    .await
+            }),
+            Some(tls) => tokio::spawn(async move {
+                let handle = axum_server::Handle::new();
+                tokio::spawn({
+                    let handle = handle.clone();
+                    async move {
+                        let _ = stopped.await;
+                        handle.graceful_shutdown(None);
+                    }
+                });
+                axum_server::from_tcp_rustls(listener.into_std()?, tls)
+                    .handle(handle)
+                    .serve(app.into_make_service())
+                    .await
+            }),
+        };
+        Self { stop, task }
+    }
+
+    /// Serves plain HTTP/1.1 on a Unix domain socket, removing the socket file once stopped.
+    /// TLS is never offered here, since the socket does not leave the host.
+    fn spawn_unix(listener: tokio::net::UnixListener, path: PathBuf, app: Router) -> Self {
+        let (stop, mut stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            // Connections hold a receiver each, so the sender learns when the last one is done
+            let (closing_tx, closing_rx) = tokio::sync::watch::channel(());
+            loop {
+                let stream = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            warn!(path = %path.display(), "Failed to accept connection: {}", e);
+                            continue;
+                        }
+                    },
+                    _ = &mut stopped => break,
+                };
+                let service = TowerToHyperService::new(app.clone());
+                let mut closing = closing_rx.clone();
+                tokio::spawn(async move {
+                    let connection = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .with_upgrades();
+                    tokio::pin!(connection);
+                    let result = tokio::select! {
+                        result = connection.as_mut() => result,
+                        _ = closing.changed() => {
+                            connection.as_mut().graceful_shutdown();
+                            connection.await
+                        }
+                    };
+                    if let Err(e) = result {
+                        debug!("Unix socket connection ended with an error: {}", e);
+                    }
+                });
+            }
+
+            drop(listener);
+            let _ = std::fs::remove_file(&path);
+            drop(closing_rx);
+            let _ = closing_tx.send(());
+            closing_tx.closed().await;
+            Ok(())
+        });
+        Self { stop, task }
+    }
+
+    /// Closes the listener and waits for in-flight requests to finish, for at most `grace`
+    /// when given.
+    async fn drain(self, grace: Option<std::time::Duration>) -> std::io::Result<()> {
+        let _ = self.stop.send(());
+        let mut task = self.task;
+        let joined = match grace {
+            Some(grace) => match tokio::time::timeout(grace, &mut task).await {
+                Ok(joined) => joined,
+                Err(_) => {
+                    warn!("Old listener still busy after the drain grace window; closing it");
+                    task.abort();
+                    return Ok(());
+                }
+            },
+            None => task.await,
+        };
+        joined.map_err(std::io::Error::other)?
+    }
+}
+
+/// Listener changes made by a SIGHUP: newly bound addresses to serve, and addresses to stop.
+struct Rebind {
+    added: Vec<(ListenSpec, BoundListener)>,
+    removed: Vec<ListenSpec>,
+}
+
+/// Serves `app` on `listeners` until `shutdown` resolves, then drains them all. A [`Rebind`]
+/// received on `rebinds` starts serving its added addresses immediately, while the removed ones
+/// stop accepting and drain in the background for up to `grace`.
+async fn serve_with_rebind(
+    app: Router,
+    tls: Option<RustlsConfig>,
+    listeners: Vec<(ListenSpec, BoundListener)>,
+    mut rebinds: mpsc::UnboundedReceiver<Rebind>,
+    grace: std::time::Duration,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> std::io::Result<()> {
+    let mut serving: Vec<(ListenSpec, ServingListener)> = listeners
+        .into_iter()
+        .map(|(spec, listener)| (spec, listener.serve(app.clone(), tls.clone())))
+        .collect();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            Some(rebind) = rebinds.recv() => {
+                for (spec, listener) in rebind.added {
+                    serving.push((spec, listener.serve(app.clone(), tls.clone())));
+                }
+                for spec in rebind.removed {
+                    let Some(i) = serving.iter().position(|(s, _)| *s == spec) else {
+                        continue;
+                    };
+                    let (_, previous) = serving.remove(i);
+                    tokio::spawn(async move {
+                        if let Err(e) = previous.drain(Some(grace)).await {
+                            warn!(addr = %spec, "Old listener failed while draining: {}", e);
+                        }
+                    });
+                }
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    let drains = serving
+        .into_iter()
+        .map(|(_, listener)| listener.drain(None));
+    futures_util::future::try_join_all(drains).await?;
+    Ok(())
+}
+
+/// Re-reads the configuration on SIGHUP. Settings that can change while running are swapped
+/// into `state`, and when `bind_addrs` changed the added addresses are bound and handed to the
+/// server along with the removed ones to stop. Changes to other settings are logged and wait
+/// for a restart.
+async fn reload_on_hangup(
+    cli: Cli,
+    mut config: AppConfig,
+    state: AppState,
+    rebinds: mpsc::UnboundedSender<Rebind>,
+) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("failed to install SIGHUP handler");
+
+    while hangup.recv().await.is_some() {
+        let next = match load_config(&cli) {
+            Ok(next) => next,
+            Err(e) => {
+                warn!("Ignoring SIGHUP, configuration failed to load: {}", e);
+                continue;
+            }
+        };
+        config = apply_reload(&state, &config, &next);
+
+        let (from, to) = (&config.server.bind_addrs, &next.server.bind_addrs);
+        let added: Vec<ListenSpec> = to.iter().filter(|s| !from.contains(s)).cloned().collect();
+        let removed: Vec<ListenSpec> = from.iter().filter(|s| !to.contains(s)).cloned().collect();
+        if added.is_empty() && removed.is_empty() {
+            info!("Received SIGHUP, bind addresses unchanged");
+            continue;
+        }
+
+        match bind_all(&added).await {
+            Ok(added) => {
+                info!(
+                    from = %describe_addrs(from),
+                    to = %describe_addrs(to),
+                    "Rebinding listeners"
+                );
+                if rebinds.send(Rebind { added, removed }).is_err() {
+                    break;
+                }
+                config.server.bind_addrs = next.server.bind_addrs.clone();
+                state.config.store(Arc::new(config.server.clone()));
+            }
+            Err(e) => warn!("Failed to rebind to {}: {}", describe_addrs(to), e),
+        }
+    }
+}
+
+/// Swaps the settings of `next` that can change while running into `state`, and returns the
+/// configuration now in effect. Changes to the other settings, except `bind_addrs`, are logged
+/// and ignored.
+fn apply_reload(state: &AppState, current: &AppConfig, next: &AppConfig) -> AppConfig {
+    let (mut applied, ignored) = current.reloaded(next);
+    for setting in &ignored {
+        warn!(setting = %setting, "Setting changed, but only takes effect after a restart");
+    }
+
+    if applied.log_level != current.log_level {
+        let reloaded = state.log_reload.as_ref().map(|handle| {
+            env_filter(&applied.log_level).and_then(|filter| {
+                handle.reload(filter).map_err(|e| {
+                    AppError::Config(ConfigError::Message(format!(
+                        "Failed to update log level: {}",
+                        e
+                    )))
+                })
+            })
+        });
+        if let Some(Err(e)) = reloaded {
+            warn!("Keeping log level {}: {}", current.log_level, e);
+            applied.log_level = current.log_level.clone();
+        }
+    }
+
+    state.config.store(Arc::new(applied.server.clone()));
+    info!(ignored = ignored.len(), "Configuration reloaded");
+    applied
+}
+
+/// Loads the config file and env overrides, applies CLI overrides and validates the result.
+fn load_config(cli: &Cli) -> AppResult<AppConfig> {
+    let mut config = AppConfig::from_file(cli.config.as_ref()).map_err(AppError::Config)?;
+
+    // Override config with CLI arguments
+    if let Some(log_level) = &cli.log_level {
+        config.log_level = log_level.clone();
+    }
+
+    if let Some(bind_addr) = &cli.bind {
+        let spec = bind_addr.parse().map_err(|e| {
+            AppError::Config(ConfigError::Message(format!("Invalid bind address: {}", e)))
+        })?;
+        config.server.bind_addrs = vec![spec];
+    }
+
+    config.validate().map_err(AppError::Config)?;
+    Ok(config)
+}
+
+/// Resolves the effective config as [`load_config`] does and attributes each value to the
+/// source that last set it.
+fn explain_config(cli: &Cli) -> AppResult<Vec<ExplainedValue>> {
+    let config = load_config(cli)?;
+    let mut provenance = AppConfig::provenance(cli.config.as_ref()).map_err(AppError::Config)?;
+    if cli.log_level.is_some() {
+        provenance.insert("log_level".to_string(), ConfigSource::Cli);
+    }
+    if cli.bind.is_some() {
+        provenance.insert("server.bind_addrs".to_string(), ConfigSource::Cli);
+    }
+
+    Ok(config.explain(&provenance))
+}
+
+/// Creates the sink manager, opening the persistent job queue and starting its replay task when
+/// `persist_queue` is on.
+fn start_sink_manager(config: &ServerConfig) -> AppResult<Arc<SinkManager>> {
+    let mut sink_manager = SinkManager::new(config.clone());
+    if config.persist_queue {
+        let path = config.resolved_queue_path().ok_or_else(|| {
+            AppError::Config(ConfigError::Message(
+                "Could not determine queue_path".to_string(),
+            ))
+        })?;
+        let queue = JobQueue::open(&path, config.max_queued_jobs)?;
+        info!(path = %path.display(), backlog = queue.len(), "Opened persistent job queue");
+        sink_manager = sink_manager.with_queue(queue);
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &config.sqlite_path {
+        let history = promptivd::history::JobHistory::open(path)?;
+        info!(path = %path.display(), "Opened SQLite job history");
+        sink_manager = sink_manager.with_job_history(history);
+    }
+
+    let sink_manager = Arc::new(sink_manager);
+    if sink_manager.queue().is_some() {
+        sink_manager.spawn_queue_replay();
+    }
+    Ok(sink_manager)
+}
+
+async fn bind_listener(addr: SocketAddr) -> AppResult<tokio::net::TcpListener> {
+    tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AddrInUse => AppError::AddrInUse { addr },
+            std::io::ErrorKind::PermissionDenied => AppError::BindPermissionDenied { addr },
+            _ => AppError::Io(e),
+        })
+}
+
+/// A listen address that is bound but not served yet.
+enum BoundListener {
+    Tcp(tokio::net::TcpListener),
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl BoundListener {
+    /// Binds `spec`. A Unix socket file left behind by a daemon that did not shut down cleanly
+    /// is replaced, but one that still accepts connections is reported as in use.
+    async fn bind(spec: &ListenSpec) -> AppResult<Self> {
+        let path = match spec {
+            ListenSpec::Tcp(addr) => return Ok(Self::Tcp(bind_listener(*addr).await?)),
+            ListenSpec::Unix(path) => path,
+        };
+
+        let listener = match tokio::net::UnixListener::bind(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                if tokio::net::UnixStream::connect(path).await.is_ok() {
+                    return Err(AppError::SocketInUse { path: path.clone() });
+                }
+                std::fs::remove_file(path)?;
+                tokio::net::UnixListener::bind(path)?
+            }
+            result => result?,
+        };
+        Ok(Self::Unix(listener, path.clone()))
+    }
+
+    /// Starts serving `app`. TCP listeners use `tls` when given; Unix sockets never do.
+    fn serve(self, app: Router, tls: Option<RustlsConfig>) -> ServingListener {
+        match self {
+            Self::Tcp(listener) => ServingListener::spawn(listener, app, tls),
+            Self::Unix(listener, path) => ServingListener::spawn_unix(listener, path, app),
+        }
+    }
+
+    /// Gives the address up without serving it, removing the socket file of a Unix listener.
+    fn close(self) {
+        if let Self::Unix(listener, path) = self {
+            drop(listener);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Binds every address in `specs`, or none of them: when one fails, those already bound are
+/// closed again.
+async fn bind_all(specs: &[ListenSpec]) -> AppResult<Vec<(ListenSpec, BoundListener)>> {
+    let mut bound = Vec::with_capacity(specs.len());
+    for spec in specs {
+        match BoundListener::bind(spec).await {
+            Ok(listener) => bound.push((spec.clone(), listener)),
+            Err(e) => {
+                for (_, listener) in bound {
+                    listener.close();
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(bound)
+}
+
+fn describe_addrs(specs: &[ListenSpec]) -> String {
+    specs
+        .iter()
+        .map(ListenSpec::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A route served by the daemon. The router and the `GET /v1/routes` table are both built from
+/// these, so the advertised table cannot drift from what is actually served.
+struct RouteSpec {
+    method: Method,
+    path: &'static str,
+    auth: RouteAuth,
+    handler: MethodRouter<AppState>,
+}
+
+impl RouteSpec {
+    fn new<H, T>(method: Method, path: &'static str, auth: RouteAuth, handler: H) -> Self
+    where
+        H: Handler<T, AppState>,
+        T: 'static,
+    {
+        let filter = MethodFilter::try_from(method.clone()).expect("unsupported route method");
+        Self {
+            method,
+            path,
+            auth,
+            handler: on(filter, handler),
+        }
+    }
+
+    fn info(&self, config: &ServerConfig) -> RouteInfo {
+        let protected = match self.auth {
+            RouteAuth::None => false,
+            RouteAuth::Client => config.auth_token.is_some(),
+            RouteAuth::Admin => config.admin_token.is_some(),
+        };
+
+        RouteInfo {
+            method: self.method.to_string(),
+            path: self.path.to_string(),
+            auth: self.auth,
+            protected,
+        }
+    }
+}
+
+fn route_specs(config: &AppConfig, state: &AppState) -> Vec<RouteSpec> {
+    use promptivd::handlers::{
+        cancel_job, check_batch_body, check_insert_body, get_job, handle_overload, health,
+        insert_batch, insert_job, job_stats, list_jobs, list_providers, list_routes, list_sinks,
+        metrics, ready, set_log_level, set_maintenance, start_drain, status, validate_insert,
+        websocket_handler,
+    };
+
+    // One limit shared by every insert route, so a batch cannot dispatch around it
+    let limit = GlobalConcurrencyLimitLayer::new(config.server.max_concurrent_requests);
+    let shed = || {
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_overload))
+            .load_shed()
+            .layer(limit.clone())
+    };
+
+    let mut insert = RouteSpec::new(Method::POST, "/v1/insert", RouteAuth::Client, insert_job);
+    insert.handler = insert.handler.layer(shed());
+    // The body limit follows `max_job_bytes` across reloads instead of the router's fixed one
+    insert.handler = insert.handler.layer(
+        ServiceBuilder::new()
+            .layer(DefaultBodyLimit::disable())
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                check_insert_body,
+            )),
+    );
+    let mut validate = RouteSpec::new(
+        Method::POST,
+        "/v1/insert/validate",
+        RouteAuth::Client,
+        validate_insert,
+    );
+    validate.handler = validate.handler.layer(shed());
+    validate.handler = validate.handler.layer(
+        ServiceBuilder::new()
+            .layer(DefaultBodyLimit::disable())
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                check_insert_body,
+            )),
+    );
+
+    let mut batch = RouteSpec::new(
+        Method::POST,
+        "/v1/insert/batch",
+        RouteAuth::Client,
+        insert_batch,
+    );
+    batch.handler = batch.handler.layer(shed());
+    batch.handler = batch.handler.layer(
+        ServiceBuilder::new()
+            .layer(DefaultBodyLimit::disable())
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                check_batch_body,
+            )),
+    );
+
+    vec![
         // API routes
-        .route("/v1/health", get(promptivd::handlers::health))
-        .route("/v1/providers", get(promptivd::handlers::list_providers))
-        .route("/v1/insert", post(promptivd::handlers::insert_job))
+        RouteSpec::new(Method::GET, "/v1/health", RouteAuth::None, health),
+        RouteSpec::new(Method::GET, "/v1/ready", RouteAuth::None, ready),
+        RouteSpec::new(
+            Method::GET,
+            "/v1/providers",
+            RouteAuth::None,
+            list_providers,
+        ),
+        RouteSpec::new(Method::GET, "/v1/status", RouteAuth::None, status),
+        RouteSpec::new(Method::GET, "/v1/sinks", RouteAuth::None, list_sinks),
+        RouteSpec::new(Method::GET, "/v1/jobs", RouteAuth::None, list_jobs),
+        RouteSpec::new(Method::GET, "/v1/jobs/:id", RouteAuth::None, get_job),
+        RouteSpec::new(Method::GET, "/v1/stats", RouteAuth::None, job_stats),
+        RouteSpec::new(
+            Method::DELETE,
+            "/v1/jobs/:id",
+            RouteAuth::Client,
+            cancel_job,
+        ),
+        RouteSpec::new(Method::GET, "/v1/routes", RouteAuth::None, list_routes),
+        RouteSpec::new(Method::GET, "/metrics", RouteAuth::None, metrics),
+        RouteSpec::new(Method::PUT, "/v1/loglevel", RouteAuth::Admin, set_log_level),
+        RouteSpec::new(
+            Method::POST,
+            "/v1/admin/drain",
+            RouteAuth::Admin,
+            start_drain,
+        ),
+        RouteSpec::new(
+            Method::POST,
+            "/v1/maintenance",
+            RouteAuth::Admin,
+            set_maintenance,
+        ),
+        insert,
+        validate,
+        batch,
         // WebSocket route for sink connections
-        .route("/v1/sink/ws", get(promptivd::handlers::websocket_handler))
+        RouteSpec::new(
+            Method::GET,
+            "/v1/sink/ws",
+            RouteAuth::Client,
+            websocket_handler,
+        ),
+    ]
+}
+
+fn create_router(mut state: AppState, config: &AppConfig) -> Router {
+    let specs = route_specs(config, &state);
+    state.routes = Arc::new(specs.iter().map(|spec| spec.info(&config.server)).collect());
+
+    let mut router = Router::new();
+    for spec in specs {
+        let handler = match spec.auth {
+            RouteAuth::Client => spec.handler.layer(middleware::from_fn_with_state(
+                state.clone(),
+                check_client_auth,
+            )),
+            // Admin handlers check their token themselves
+            RouteAuth::None | RouteAuth::Admin => spec.handler,
+        };
+        router = router.route(spec.path, handler);
+    }
+
+    router
         .with_state(state)
         // Request size limit
         .layer(DefaultBodyLimit::max(config.server.max_job_bytes))
         // Request timeout
         .layer(TimeoutLayer::new(std::time::Duration::from_secs(30)))
         // CORS
-        .layer(create_cors_layer())
+        .layer(create_cors_layer(&config.server))
         // Tracing
         .layer(
             TraceLayer::new_for_http()
@@ -137,60 +790,89 @@ fn create_router(state: AppState, config: &AppConfig) -> Router {
         )
 }
 
-fn create_cors_layer() -> CorsLayer {
+fn create_cors_layer(config: &ServerConfig) -> CorsLayer {
+    let origins = if config.cors_allow_any {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .cors_origins()
+                .expect("cors_allowed_origins are checked by AppConfig::validate"),
+        )
+    };
+
     CorsLayer::new()
-        .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-        .allow_origin("http://127.0.0.1:3000".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_origin(origins)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
         .allow_headers([
             axum::http::header::CONTENT_TYPE,
             axum::http::header::AUTHORIZATION,
+            promptivd::handlers::IDEMPOTENCY_KEY_HEADER,
         ])
-        .max_age(std::time::Duration::from_secs(86400))
+        .max_age(std::time::Duration::from_secs(config.cors_max_age_secs))
 }
 
-fn init_logging(config: &AppConfig) -> AppResult<()> {
-    let log_level = config.log_level.parse::<LevelFilter>().map_err(|e| {
-        promptivd::error::AppError::Config(ConfigError::Message(format!(
-            "Invalid log level '{}': {}",
-            config.log_level, e
+fn init_logging(config: &AppConfig) -> AppResult<LogReloadHandle> {
+    let (subscriber, reload_handle) = build_subscriber(config)?;
+    // Unlike `set_global_default`, this also forwards `log` records, e.g. from tungstenite
+    subscriber.try_init().map_err(|e| {
+        AppError::Config(ConfigError::Message(format!(
+            "Failed to install logger: {}",
+            e
         )))
     })?;
 
-    let env_filter = EnvFilter::builder()
-        .with_default_directive(log_level.into())
-        .from_env()
-        .map_err(|e| {
-            promptivd::error::AppError::Config(ConfigError::Message(format!(
-                "Failed to parse log filter: {}",
-                e
-            )))
-        })?;
+    Ok(reload_handle)
+}
+
+/// JSON layer for `log_socket_path`, shipped alongside console output regardless of
+/// `log_format`.
+fn socket_layer<S>(config: &AppConfig) -> Option<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    config.log_socket_path.as_ref().map(|path| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(LogSocketWriter::new(path))
+    })
+}
 
-    match config.log_format {
-        LogFormat::Json => {
+type BoxedSubscriber = Box<dyn tracing::Subscriber + Send + Sync>;
+
+fn build_subscriber(config: &AppConfig) -> AppResult<(BoxedSubscriber, LogReloadHandle)> {
+    let (filter_layer, reload_handle) = reload::Layer::new(env_filter(&config.log_level)?);
+
+    let subscriber: BoxedSubscriber = match config.log_format {
+        LogFormat::Json => Box::new(
             tracing_subscriber::registry()
-                .with(env_filter)
+                .with(filter_layer)
                 .with(tracing_subscriber::fmt::layer().json())
-                .init();
-        }
-        LogFormat::Pretty => {
+                .with(socket_layer(config)),
+        ),
+        LogFormat::Pretty => Box::new(
             tracing_subscriber::registry()
-                .with(env_filter)
+                .with(filter_layer)
                 .with(
                     tracing_subscriber::fmt::layer()
                         .with_target(false)
                         .compact(),
                 )
-                .init();
-        }
-    }
+                .with(socket_layer(config)),
+        ),
+    };
 
-    Ok(())
+    Ok((subscriber, reload_handle))
 }
 
-async fn handle_init_config() -> AppResult<()> {
-    match AppConfig::create_default_config_file() {
+async fn handle_init_config(format: ConfigFormat) -> AppResult<()> {
+    match AppConfig::create_default_config_file(format) {
         Ok(path) => {
             println!("Created default configuration file at: {}", path.display());
             Ok(())
@@ -202,27 +884,54 @@ async fn handle_init_config() -> AppResult<()> {
     }
 }
 
-async fn shutdown_signal() {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShutdownSignal {
+    Interrupt,
+    Terminate,
+}
+
+/// Forwards SIGINT and SIGTERM for as long as the daemon runs.
+async fn forward_os_signals(tx: mpsc::UnboundedSender<ShutdownSignal>) {
+    let mut interrupt = signal::unix::signal(signal::unix::SignalKind::interrupt())
+        .expect("failed to install SIGINT handler");
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("failed to install SIGTERM handler");
+
+    loop {
+        let signal = tokio::select! {
+            _ = interrupt.recv() => ShutdownSignal::Interrupt,
+            _ = terminate.recv() => ShutdownSignal::Terminate,
+        };
+        if tx.send(signal).is_err() {
+            break;
+        }
+    }
+}
 
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
+/// Starts a graceful shutdown on the first signal and calls `force` if another arrives before
+/// the drain finishes.
+async fn supervise_shutdown<F>(
+    mut signals: mpsc::UnboundedReceiver<ShutdownSignal>,
+    graceful: oneshot::Sender<()>,
+    force: F,
+) where
+    F: FnOnce(),
+{
+    let Some(first) = signals.recv().await else {
+        return;
     };
+    match first {
+        ShutdownSignal::Interrupt => info!("Received Ctrl+C, starting graceful shutdown"),
+        ShutdownSignal::Terminate => info!("Received SIGTERM, starting graceful shutdown"),
+    }
+    let _ = graceful.send(());
 
-    tokio::select! {
-        _ = ctrl_c => {
-            info!("Received Ctrl+C, starting graceful shutdown");
-        },
-        _ = terminate => {
-            info!("Received SIGTERM, starting graceful shutdown");
-        },
+    if let Some(second) = signals.recv().await {
+        error!(
+            signal = ?second,
+            "Received second shutdown signal, exiting immediately and aborting in-flight jobs"
+        );
+        force();
     }
 }
 
@@ -230,20 +939,128 @@ async fn shutdown_signal() {
 mod tests {
     use super::*;
     use axum::http::StatusCode;
+    use futures_util::{SinkExt, StreamExt};
+    use promptivd::jobs::{JobRecord, JobStats, JobStatus, JobSummary};
+    use promptivd::models::Placement;
+    use promptivd::websocket::{AckStatus, InsertTextPayload, RelayMessage, SinkMessage};
+    use serial_test::serial;
+    use std::collections::BTreeMap;
+    use std::net::SocketAddr;
+    use tokio_tungstenite::{connect_async, tungstenite::Message};
     use tower::ServiceExt;
+    use tracing::level_filters::LevelFilter;
+
+    type SinkStream = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
 
     fn create_test_config() -> AppConfig {
         AppConfig::default()
     }
 
+    /// Serves the full router on an ephemeral port and returns its address.
+    async fn spawn_server(config: AppConfig) -> (SocketAddr, Arc<SinkManager>) {
+        let (addr, state) = spawn_server_with_state(config).await;
+        (addr, state.sink_manager)
+    }
+
+    /// Like [`spawn_server`], returning the state the router serves from.
+    async fn spawn_server_with_state(config: AppConfig) -> (SocketAddr, AppState) {
+        let sink_manager = start_sink_manager(&config.server).unwrap();
+        let state = AppState::new(sink_manager).unwrap();
+        let app = create_router(state.clone(), &config);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        (addr, state)
+    }
+
+    /// Connects a sink to the server and waits until its registration is published.
+    async fn connect_sink(addr: SocketAddr, sink_manager: &SinkManager) -> SinkStream {
+        connect_sink_for(addr, sink_manager, "chatgpt").await
+    }
+
+    /// Connects a sink serving `provider` and waits until its registration is published.
+    async fn connect_sink_for(
+        addr: SocketAddr,
+        sink_manager: &SinkManager,
+        provider: &str,
+    ) -> SinkStream {
+        let (mut ws, _) = connect_async(format!("ws://{}/v1/sink/ws", addr))
+            .await
+            .unwrap();
+        let register = register_message(&[provider], "test");
+        ws.send(Message::Text(serde_json::to_string(&register).unwrap()))
+            .await
+            .unwrap();
+
+        while !sink_manager
+            .active_providers()
+            .await
+            .is_some_and(|providers| providers.iter().any(|p| p == provider))
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        ws
+    }
+
+    /// Builds a `register` frame for `providers` with the default schema and capabilities.
+    fn register_message(providers: &[&str], version: &str) -> SinkMessage {
+        SinkMessage::Register {
+            schema_version: "1.0".to_string(),
+            version: version.to_string(),
+            capabilities: vec!["insert".to_string()],
+            providers: providers.iter().map(|p| p.to_string()).collect(),
+            provider_capabilities: None,
+            binary_frames: false,
+        }
+    }
+
+    /// Builds a bare `ack` frame for `job_id` with no error, echo or warnings.
+    fn ack(job_id: &str, status: AckStatus) -> SinkMessage {
+        SinkMessage::Ack {
+            schema_version: "1.0".to_string(),
+            id: job_id.to_string(),
+            status,
+            error: None,
+            echo: None,
+            warnings: Vec::new(),
+            session_active: None,
+        }
+    }
+
+    /// Reads frames until the next insert_text job and returns its id and payload.
+    async fn next_job(sink: &mut SinkStream) -> (String, Box<InsertTextPayload>) {
+        loop {
+            let Message::Text(text) = sink.next().await.unwrap().unwrap() else {
+                continue;
+            };
+            if let Ok(RelayMessage::InsertText { id, payload, .. }) = serde_json::from_str(&text) {
+                return (id, payload);
+            }
+        }
+    }
+
+    fn insert_body() -> serde_json::Value {
+        serde_json::json!({
+            "schema_version": "1.0",
+            "source": {"client": "test", "label": null, "path": null},
+            "text": "hello",
+            "placement": null,
+            "target": null,
+        })
+    }
+
     fn create_test_state() -> AppState {
         let config = create_test_config();
         let sink_manager = Arc::new(SinkManager::new(config.server.clone()));
 
-        AppState {
-            sink_manager,
-            config: config.server,
-        }
+        AppState::new(sink_manager).unwrap()
     }
 
     #[tokio::test]
@@ -265,6 +1082,82 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[tokio::test]
+    async fn test_routes_table_matches_router_and_marks_admin_routes() {
+        let mut config = create_test_config();
+        config.server.admin_token = Some("secret".to_string());
+        let app = create_router(create_test_state(), &config);
+
+        let response = app
+            .clone()
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/v1/routes")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let routes: promptivd::models::RoutesResponse = serde_json::from_slice(&body).unwrap();
+
+        let loglevel = routes
+            .routes
+            .iter()
+            .find(|r| r.path == "/v1/loglevel")
+            .unwrap();
+        assert_eq!(loglevel.method, "PUT");
+        assert_eq!(loglevel.auth, RouteAuth::Admin);
+        assert!(loglevel.protected);
+        assert!(routes
+            .routes
+            .iter()
+            .filter(|r| r.auth == RouteAuth::None)
+            .all(|r| !r.protected));
+
+        // Every advertised route is actually served with the advertised method
+        for route in &routes.routes {
+            let uri = route
+                .path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with(':') {
+                        "x"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let response = app
+                .clone()
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(route.method.as_str())
+                        .uri(&uri)
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            // A handler may answer 404 for an unknown id, but only the router answers bare
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(
+                status != StatusCode::NOT_FOUND
+                    || serde_json::from_slice::<serde_json::Value>(&body).is_ok(),
+                "{}",
+                route.path
+            );
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{}", route.path);
+        }
+    }
+
     #[tokio::test]
     async fn test_providers_endpoint_no_sink() {
         let config = create_test_config();
@@ -284,6 +1177,2346 @@ mod tests {
         assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
     }
 
+    #[tokio::test]
+    async fn test_insert_sheds_requests_over_concurrency_limit() {
+        let mut config = create_test_config();
+        config.server.max_concurrent_requests = 1;
+        let (addr, sink_manager) = spawn_server(config).await;
+
+        // The sink never acks, so the first insert holds the only slot until it times out.
+        let _sink = connect_sink(addr, &sink_manager).await;
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/insert", addr);
+        let pending = tokio::spawn({
+            let client = client.clone();
+            let url = url.clone();
+            async move { client.post(url).json(&insert_body()).send().await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let response = client.post(&url).json(&insert_body()).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        // The other insert routes draw on the same slots
+        let response = client
+            .post(format!("{}/batch", url))
+            .json(&serde_json::json!([insert_body()]))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let response = client
+            .post(format!("{}/validate", url))
+            .json(&insert_body())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        // Exempt routes are unaffected by the limit.
+        let response = client
+            .get(format!("http://{}/v1/health", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        pending.abort();
+    }
+
+    #[tokio::test]
+    async fn test_control_ping_keeps_sink_alive() {
+        let mut config = create_test_config();
+        config.server.use_control_ping = true;
+        config.server.websocket_ping_interval = std::time::Duration::from_millis(50);
+        config.server.websocket_pong_timeout = std::time::Duration::from_millis(40);
+        config.server.websocket_max_missed_pings = 2;
+        let (addr, sink_manager) = spawn_server(config).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        sink.send(Message::Ping(b"probe".to_vec())).await.unwrap();
+
+        // The client answers control pings as it reads, which must count as liveness
+        let mut control_pings = 0;
+        let mut probe_answered = false;
+        let _ = tokio::time::timeout(std::time::Duration::from_millis(500), async {
+            while let Some(msg) = sink.next().await {
+                match msg.unwrap() {
+                    Message::Ping(_) => control_pings += 1,
+                    Message::Pong(payload) => probe_answered |= payload == b"probe",
+                    Message::Text(text) => assert!(
+                        !matches!(serde_json::from_str(&text), Ok(RelayMessage::Ping { .. })),
+                        "JSON ping sent despite use_control_ping"
+                    ),
+                    other => panic!("unexpected frame: {:?}", other),
+                }
+            }
+        })
+        .await;
+
+        assert!(control_pings >= 3, "only {} control pings", control_pings);
+        assert!(probe_answered);
+        assert!(sink_manager.has_active_sink());
+    }
+
+    #[tokio::test]
+    async fn test_superseded_sink_receives_close_frame() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+
+        let mut first = connect_sink(addr, &sink_manager).await;
+        let _second = connect_sink(addr, &sink_manager).await;
+
+        let mut notified = false;
+        let close = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while let Some(msg) = first.next().await {
+                match msg.unwrap() {
+                    Message::Text(text) => {
+                        notified |= matches!(
+                            serde_json::from_str(&text),
+                            Ok(RelayMessage::Superseded { .. })
+                        );
+                    }
+                    Message::Close(frame) => return frame,
+                    _ => {}
+                }
+            }
+            None
+        })
+        .await
+        .expect("superseded sink was not closed");
+
+        assert!(notified, "superseded notice was not sent before close");
+        let frame = close.expect("close frame carried no reason");
+        assert_eq!(frame.reason, "Superseded by new sink");
+    }
+
+    /// Collects formatted log output in memory.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn records(&self) -> Vec<serde_json::Value> {
+            let buf = self.0.lock().unwrap();
+            std::str::from_utf8(&buf)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_event_emitted_when_job_is_written() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(move || writer.clone()),
+        );
+        // The test runtime is single-threaded, so the send task logs through this subscriber
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let insert = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send()
+                .await
+                .unwrap()
+        });
+
+        let (job_id, _) = next_job(&mut sink).await;
+        let ack = ack(&job_id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+        assert!(insert.await.unwrap().status().is_success());
+
+        let relayed = logs
+            .records()
+            .into_iter()
+            .find(|r| {
+                r["fields"]["message"] == "Relayed message to sink"
+                    && r["fields"]["message_type"] == "insert_text"
+            })
+            .expect("no relay event for the job");
+        assert_eq!(relayed["fields"]["job_id"], job_id);
+        assert!(relayed["fields"]["bytes"].as_u64().unwrap() > 0);
+        assert!(!relayed.to_string().contains("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_job_logs_share_span_fields() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(move || writer.clone()),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let sink_id = sink_manager.list_sinks().await[0].connection.id;
+
+        let mut body = insert_body();
+        body["target"] = serde_json::json!({"provider": "chatgpt"});
+        let insert = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&body)
+                .send()
+                .await
+                .unwrap()
+        });
+
+        let (job_id, _) = next_job(&mut sink).await;
+        let ack = ack(&job_id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+        assert!(insert.await.unwrap().status().is_success());
+
+        let records = logs.records();
+        let delivered = records
+            .iter()
+            .find(|r| r["fields"]["message"] == "Job delivered successfully")
+            .expect("no delivery event for the job");
+        assert_eq!(delivered["span"]["job_id"], job_id);
+
+        let finished: Vec<_> = records
+            .iter()
+            .filter(|r| r["fields"]["message"] == "Job finished")
+            .collect();
+        assert_eq!(finished.len(), 1);
+        let finished = finished[0];
+        assert_eq!(finished["fields"]["status"], "ok");
+        assert!(finished["fields"]["elapsed_ms"].is_u64());
+        assert_eq!(finished["span"]["name"], "job");
+        assert_eq!(finished["span"]["job_id"], job_id);
+        assert_eq!(finished["span"]["provider"], "chatgpt");
+        assert_eq!(finished["span"]["sink_id"], sink_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_echo_job_round_trips_payload_summary() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let insert = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert?echo=true", addr))
+                .json(&insert_body())
+                .send()
+                .await
+                .unwrap()
+        });
+
+        // Ack the way promptivs does: immediately, with a summary instead of inserting
+        let (id, payload) = next_job(&mut sink).await;
+        assert!(payload.is_echo());
+        let mut ack = ack(&id, AckStatus::Ok);
+        if let SinkMessage::Ack { echo, .. } = &mut ack {
+            *echo = Some(payload.echo_summary());
+        }
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        let response = insert.await.unwrap();
+        assert!(response.status().is_success());
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["echo"]["text_bytes"], 5);
+        assert_eq!(body["echo"]["source"]["client"], "test");
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_socket_closed_after_registration_timeout() {
+        let mut config = create_test_config();
+        config.server.registration_timeout = std::time::Duration::from_millis(100);
+        let (addr, _sink_manager) = spawn_server(config).await;
+
+        let (mut ws, _) = connect_async(format!("ws://{}/v1/sink/ws", addr))
+            .await
+            .unwrap();
+
+        let close = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while let Some(msg) = ws.next().await {
+                if let Message::Close(frame) = msg.unwrap() {
+                    return frame;
+                }
+            }
+            None
+        })
+        .await
+        .expect("unregistered socket was not closed");
+
+        let frame = close.expect("close frame carried no reason");
+        assert_eq!(
+            frame.code,
+            tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Protocol
+        );
+        assert_eq!(frame.reason, "Registration timeout");
+    }
+
+    #[tokio::test]
+    async fn test_schema_mismatch_closes_with_registration_failed_code() {
+        let (addr, _sink_manager) = spawn_server(create_test_config()).await;
+        let (mut ws, _) = connect_async(format!("ws://{}/v1/sink/ws", addr))
+            .await
+            .unwrap();
+        let mut register = register_message(&["chatgpt"], "test");
+        if let SinkMessage::Register { schema_version, .. } = &mut register {
+            *schema_version = "2.0".to_string();
+        }
+        ws.send(Message::Text(serde_json::to_string(&register).unwrap()))
+            .await
+            .unwrap();
+
+        let close = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while let Some(msg) = ws.next().await {
+                if let Message::Close(frame) = msg.unwrap() {
+                    return frame;
+                }
+            }
+            None
+        })
+        .await
+        .expect("mismatched sink was not closed");
+
+        let frame = close.expect("close frame carried no reason");
+        assert_eq!(
+            u16::from(frame.code),
+            promptivd::websocket::REGISTRATION_FAILED_CLOSE_CODE
+        );
+        assert!(frame.reason.contains("Unsupported schema version 2.0"));
+    }
+
+    #[tokio::test]
+    async fn test_sink_warnings_returned_with_successful_insert() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let insert = tokio::spawn(async move {
+            let request: promptivd::models::InsertTextRequest =
+                serde_json::from_value(insert_body()).unwrap();
+            promptivd::client::InsertClient::new(format!("http://{}", addr))
+                .insert(&request)
+                .await
+                .unwrap()
+        });
+
+        let (id, _) = next_job(&mut sink).await;
+        let mut ack = ack(&id, AckStatus::Ok);
+        if let SinkMessage::Ack { warnings, .. } = &mut ack {
+            *warnings = vec!["Prompt truncated to 8k tokens".to_string()];
+        }
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        let response = insert.await.unwrap();
+        assert!(response.status.is_success());
+        assert_eq!(response.body["status"], "ok");
+        assert_eq!(response.warnings(), vec!["Prompt truncated to 8k tokens"]);
+    }
+
+    #[tokio::test]
+    async fn test_job_status_is_queryable_after_ack() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let sink_id = sink_manager.list_sinks().await[0].connection.id;
+
+        let insert = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send()
+                .await
+                .unwrap()
+        });
+
+        let (id, _) = next_job(&mut sink).await;
+        let get_job = |id: String| async move {
+            reqwest::get(format!("http://{}/v1/jobs/{}", addr, id))
+                .await
+                .unwrap()
+        };
+
+        let pending: JobRecord = get_job(id.clone()).await.json().await.unwrap();
+        assert_eq!(pending.status, JobStatus::Pending);
+        assert_eq!(pending.sink_id, Some(sink_id));
+
+        let mut ack = ack(&id, AckStatus::Retry);
+        if let SinkMessage::Ack { error, .. } = &mut ack {
+            *error = Some("Editor not focused".to_string());
+        }
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+        insert.await.unwrap();
+
+        let done: JobRecord = get_job(id).await.json().await.unwrap();
+        assert_eq!(done.status, JobStatus::Retry);
+        assert_eq!(done.sink_id, Some(sink_id));
+
+        let missing = get_job("unknown".to_string()).await;
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_stats_aggregate_recent_jobs() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let mut chatgpt = insert_body();
+        chatgpt["target"] = serde_json::json!({"provider": "chatgpt", "session_policy": null});
+        for (body, status) in [
+            (chatgpt.clone(), AckStatus::Ok),
+            (chatgpt.clone(), AckStatus::Ok),
+            (insert_body(), AckStatus::Failed),
+        ] {
+            let insert = tokio::spawn(async move {
+                reqwest::Client::new()
+                    .post(format!("http://{}/v1/insert", addr))
+                    .json(&body)
+                    .send()
+                    .await
+                    .unwrap()
+            });
+            let (id, _) = next_job(&mut sink).await;
+            sink.send(Message::Text(
+                serde_json::to_string(&ack(&id, status)).unwrap(),
+            ))
+            .await
+            .unwrap();
+            insert.await.unwrap();
+        }
+
+        // Left pending: counted in the totals but not in the finished-job figures
+        reqwest::Client::new()
+            .post(format!("http://{}/v1/insert?wait=false", addr))
+            .json(&chatgpt)
+            .send()
+            .await
+            .unwrap();
+        next_job(&mut sink).await;
+
+        let stats: JobStats = reqwest::get(format!("http://{}/v1/stats", addr))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(stats.window_secs, 600);
+        assert_eq!(stats.total_jobs, 4);
+        assert_eq!(
+            stats.by_status,
+            BTreeMap::from([
+                ("ok".to_string(), 2),
+                ("failed".to_string(), 1),
+                ("pending".to_string(), 1)
+            ])
+        );
+        assert_eq!(
+            stats.by_provider,
+            BTreeMap::from([("chatgpt".to_string(), 3)])
+        );
+        assert_eq!(stats.success_rate, Some(2.0 / 3.0));
+        assert!(stats.avg_duration_ms.is_some());
+        assert!(stats.p95_duration_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_are_listed_newest_first() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let mut ids = Vec::new();
+        for status in [AckStatus::Ok, AckStatus::Failed, AckStatus::Ok] {
+            let insert = tokio::spawn(async move {
+                reqwest::Client::new()
+                    .post(format!("http://{}/v1/insert", addr))
+                    .json(&insert_body())
+                    .send()
+                    .await
+                    .unwrap()
+            });
+            let (id, _) = next_job(&mut sink).await;
+            sink.send(Message::Text(
+                serde_json::to_string(&ack(&id, status)).unwrap(),
+            ))
+            .await
+            .unwrap();
+            insert.await.unwrap();
+            ids.push(id);
+        }
+
+        let list = |query: &'static str| async move {
+            reqwest::get(format!("http://{}/v1/jobs{}", addr, query))
+                .await
+                .unwrap()
+                .json::<Vec<JobSummary>>()
+                .await
+                .unwrap()
+        };
+
+        let all = list("").await;
+        let listed: Vec<&str> = all.iter().map(|job| job.id.as_str()).collect();
+        assert_eq!(listed, [&ids[2], &ids[1], &ids[0]]);
+        assert_eq!(all[0].source.as_deref(), Some("test"));
+        assert_eq!(all[0].inserted_chars, Some(5));
+
+        let ok = list("?status=ok&limit=1").await;
+        assert_eq!(ok.len(), 1);
+        assert_eq!(ok[0].id, ids[2]);
+    }
+
+    #[tokio::test]
+    async fn test_insert_without_wait_returns_before_ack() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/v1/insert?wait=false", addr))
+            .json(&insert_body())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "pending");
+        let job_id = body["job_id"].as_str().unwrap().to_string();
+
+        let get_job = || async {
+            reqwest::get(format!("http://{}/v1/jobs/{}", addr, job_id))
+                .await
+                .unwrap()
+                .json::<JobRecord>()
+                .await
+                .unwrap()
+        };
+        assert_eq!(get_job().await.status, JobStatus::Pending);
+
+        let (id, _) = next_job(&mut sink).await;
+        assert_eq!(id, job_id);
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while get_job().await.status == JobStatus::Pending {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("background job was never acked");
+        assert_eq!(get_job().await.status, JobStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pending_job() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let client = reqwest::Client::new();
+        let cancel = |job_id: &str| {
+            client
+                .delete(format!("http://{}/v1/jobs/{}", addr, job_id))
+                .send()
+        };
+
+        let response = client
+            .post(format!("http://{}/v1/insert?wait=false", addr))
+            .json(&insert_body())
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        let job_id = body["job_id"].as_str().unwrap().to_string();
+
+        // Once the sink has the job, it is told about the cancel
+        next_job(&mut sink).await;
+        let response = cancel(&job_id).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let notice = loop {
+            let Message::Text(text) = sink.next().await.unwrap().unwrap() else {
+                continue;
+            };
+            if let Ok(RelayMessage::Cancel { id, .. }) = serde_json::from_str(&text) {
+                break id;
+            }
+        };
+        assert_eq!(notice, job_id);
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while sink_manager.jobs().get(&job_id).unwrap().status == JobStatus::Pending {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("cancelled job stayed pending");
+        assert_eq!(
+            sink_manager.jobs().get(&job_id).unwrap().status,
+            JobStatus::Cancelled
+        );
+
+        let response = cancel(&job_id).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+        let response = cancel("no-such-job").await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_job_parked_behind_busy_sink() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let client = reqwest::Client::new();
+
+        let busy = SinkMessage::Busy {
+            schema_version: "1.0".to_string(),
+            reason: None,
+            resume_hint_ms: None,
+        };
+        sink.send(Message::Text(serde_json::to_string(&busy).unwrap()))
+            .await
+            .unwrap();
+        while sink_manager.sink_ready().await {
+            tokio::task::yield_now().await;
+        }
+
+        // Waits for the ack, since a background insert is only accepted once the sink has it
+        let job_id = "parked-job";
+        let insert = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .post(format!("http://{}/v1/insert", addr))
+                    .header("X-Request-Id", job_id)
+                    .json(&insert_body())
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        });
+        while sink_manager.jobs().get(job_id).is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        let response = client
+            .delete(format!("http://{}/v1/jobs/{}", addr, job_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            insert.await.unwrap().status(),
+            reqwest::StatusCode::CONFLICT
+        );
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while sink_manager.jobs().get(job_id).unwrap().status == JobStatus::Pending {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("cancelled job stayed parked");
+        let record = sink_manager.jobs().get(job_id).unwrap();
+        assert_eq!(record.status, JobStatus::Cancelled);
+        assert_eq!(record.sink_id, None);
+
+        // The withdrawn job never reaches the sink once it is ready again
+        let ready = SinkMessage::Ready {
+            schema_version: "1.0".to_string(),
+        };
+        sink.send(Message::Text(serde_json::to_string(&ready).unwrap()))
+            .await
+            .unwrap();
+        let delivered =
+            tokio::time::timeout(std::time::Duration::from_millis(200), next_job(&mut sink));
+        assert!(delivered.await.is_err(), "cancelled job was dispatched");
+    }
+
+    #[tokio::test]
+    async fn test_source_and_target_reach_sink() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let mut body = insert_body();
+        body["source"]["label"] = serde_json::json!("Editor");
+        body["target"] = serde_json::json!({
+            "provider": "chatgpt",
+            "session_policy": "start_fresh",
+        });
+        tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&body)
+                .send()
+                .await
+        });
+
+        let (_, payload) = next_job(&mut sink).await;
+        let target = payload.target.unwrap();
+        assert_eq!(target.provider.as_deref(), Some("chatgpt"));
+        assert_eq!(
+            target.session_policy,
+            Some(promptivd::models::SessionPolicy::StartFresh)
+        );
+        assert_eq!(payload.source.client, "test");
+        assert_eq!(payload.source.label.as_deref(), Some("Editor"));
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_rejected_entry_and_dispatches_the_rest() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let mut invalid = insert_body();
+        invalid["text"] = "".into();
+        let mut second = insert_body();
+        second["text"] = "second".into();
+        let batch = serde_json::json!([insert_body(), invalid, second]);
+        let response = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert/batch", addr))
+                .json(&batch)
+                .send(),
+        );
+
+        let mut texts = Vec::new();
+        for _ in 0..2 {
+            let (id, payload) = next_job(&mut sink).await;
+            texts.push(payload.text);
+            let ack = ack(&id, AckStatus::Ok);
+            sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+                .await
+                .unwrap();
+        }
+        assert_eq!(texts, ["hello", "second"]);
+
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::MULTI_STATUS);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["status"], "ok");
+        assert_eq!(results[0]["http_status"], 200);
+        assert_eq!(results[1]["status"], "rejected");
+        assert_eq!(results[1]["code"], "invalid_request");
+        assert_eq!(results[1]["http_status"], 400);
+        assert_eq!(results[2]["status"], "ok");
+        assert_ne!(results[0]["job_id"], results[2]["job_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_dedupe_dispatches_duplicates_once() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let mut other_text = insert_body();
+        other_text["text"] = "second".into();
+        let mut other_provider = insert_body();
+        other_provider["target"] = serde_json::json!({"provider": "chatgpt"});
+        let batch = serde_json::json!([
+            insert_body(),
+            other_text,
+            insert_body(),
+            other_provider,
+            insert_body(),
+        ]);
+        let response = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert/batch?dedupe=true", addr))
+                .json(&batch)
+                .send(),
+        );
+
+        let mut texts = Vec::new();
+        for _ in 0..3 {
+            let (id, payload) = next_job(&mut sink).await;
+            texts.push(payload.text);
+            let ack = ack(&id, AckStatus::Ok);
+            sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+                .await
+                .unwrap();
+        }
+        assert_eq!(texts, ["hello", "second", "hello"]);
+
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::MULTI_STATUS);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|r| r["status"] == "ok"));
+        assert_eq!(results[2], results[0]);
+        assert_eq!(results[4], results[0]);
+        assert_ne!(results[1]["job_id"], results[0]["job_id"]);
+        assert_ne!(results[3]["job_id"], results[0]["job_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_sink_dropped_mid_job_reports_disconnect() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let insert = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send(),
+        );
+        next_job(&mut sink).await;
+        drop(sink);
+
+        let response = insert.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "sink_disconnected");
+    }
+
+    #[tokio::test]
+    async fn test_reuse_only_requires_a_reported_session() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let client = reqwest::Client::new();
+        let insert = |policy: &str| {
+            let mut body = insert_body();
+            body["target"] = serde_json::json!({"provider": "chatgpt", "session_policy": policy});
+            tokio::spawn(
+                client
+                    .post(format!("http://{}/v1/insert", addr))
+                    .json(&body)
+                    .send(),
+            )
+        };
+        let session_ack = |id: String, active| {
+            let mut message = ack(&id, AckStatus::Ok);
+            if let SinkMessage::Ack { session_active, .. } = &mut message {
+                *session_active = active;
+            }
+            message
+        };
+
+        let rejected = insert("reuse_only").await.unwrap().unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::CONFLICT);
+        let body: serde_json::Value = rejected.json().await.unwrap();
+        assert_eq!(body["code"], "no_reusable_session");
+
+        let fresh = insert("start_fresh");
+        let (id, payload) = next_job(&mut sink).await;
+        assert!(payload.new_session);
+        sink.send(Message::Text(
+            serde_json::to_string(&session_ack(id, Some(true))).unwrap(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            fresh.await.unwrap().unwrap().status(),
+            reqwest::StatusCode::OK
+        );
+
+        // The sink reported a session, so reuse goes through until it reports it gone
+        let reused = insert("reuse_only");
+        let (id, payload) = next_job(&mut sink).await;
+        assert!(!payload.new_session);
+        sink.send(Message::Text(
+            serde_json::to_string(&session_ack(id, Some(false))).unwrap(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            reused.await.unwrap().unwrap().status(),
+            reqwest::StatusCode::OK
+        );
+
+        let rejected = insert("reuse_only").await.unwrap().unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_job_accepted_before_drain_is_still_acked() {
+        let mut config = create_test_config();
+        config.server.admin_token = Some("secret".to_string());
+        let (addr, sink_manager) = spawn_server(config).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let client = reqwest::Client::new();
+
+        let insert = tokio::spawn(
+            client
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send(),
+        );
+        let (id, _) = next_job(&mut sink).await;
+
+        let drain: promptivd::models::DrainResponse = client
+            .post(format!("http://{}/v1/admin/drain", addr))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(drain.draining);
+        assert_eq!(drain.pending_jobs, 1);
+
+        let rejected = client
+            .post(format!("http://{}/v1/insert", addr))
+            .json(&insert_body())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        let accepted = insert.await.unwrap().unwrap();
+        assert_eq!(accepted.status(), reqwest::StatusCode::OK);
+        assert!(
+            sink_manager
+                .wait_for_idle(std::time::Duration::from_secs(1))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_shutdown_drain_progress() {
+        let (addr, state) = spawn_server_with_state(create_test_config()).await;
+        let mut sink = connect_sink(addr, &state.sink_manager).await;
+        let client = reqwest::Client::new();
+        let status = || async {
+            client
+                .get(format!("http://{}/v1/status", addr))
+                .send()
+                .await
+                .unwrap()
+                .json::<promptivd::models::StatusResponse>()
+                .await
+                .unwrap()
+        };
+
+        let insert = tokio::spawn(
+            client
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send(),
+        );
+        let (id, _) = next_job(&mut sink).await;
+
+        let before = status().await;
+        assert!(!before.draining);
+        assert_eq!(before.pending_jobs, 1);
+        assert_eq!(before.drain_remaining_ms, None);
+
+        // Shutdown flips the drain state and waits for the pending job
+        let drain = tokio::spawn({
+            let state = state.clone();
+            async move { state.drain(std::time::Duration::from_secs(30)).await }
+        });
+        while !state.is_draining() {
+            tokio::task::yield_now().await;
+        }
+        let draining = status().await;
+        assert!(draining.draining);
+        assert_eq!(draining.pending_jobs, 1);
+        let remaining = draining.drain_remaining_ms.unwrap();
+        assert!(remaining > 0 && remaining <= 30_000);
+
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(
+            insert.await.unwrap().unwrap().status(),
+            reqwest::StatusCode::OK
+        );
+        assert!(drain.await.unwrap());
+
+        let drained = status().await;
+        assert!(drained.draining);
+        assert_eq!(drained.pending_jobs, 0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_received_and_dispatched_jobs() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let insert = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send(),
+        );
+        let (id, _) = next_job(&mut sink).await;
+        let mut ack = ack(&id, AckStatus::Failed);
+        if let SinkMessage::Ack { error, .. } = &mut ack {
+            *error = Some("Editor not found".to_string());
+        }
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+        insert.await.unwrap().unwrap();
+
+        let response = reqwest::get(format!("http://{}/metrics", addr))
+            .await
+            .unwrap();
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let text = response.text().await.unwrap();
+        assert!(text.contains("promptivd_jobs_received_total 1\n"));
+        assert!(text.contains("promptivd_jobs_dispatched_total{status=\"failed\"} 1\n"));
+        assert!(text.contains("promptivd_dispatch_duration_seconds_count 1\n"));
+        assert!(text.contains("promptivd_sink_connections 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_sink_traffic_and_reconnects_are_reported() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let first_id = sink_manager.list_sinks().await[0].connection.id;
+
+        let insert = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send(),
+        );
+        let (id, _) = next_job(&mut sink).await;
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+        insert.await.unwrap().unwrap();
+
+        let sinks: serde_json::Value = reqwest::get(format!("http://{}/v1/sinks", addr))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(sinks[0]["connected_since"].is_string());
+        assert!(sinks[0]["bytes_sent"].as_u64().unwrap() > 0);
+        assert!(sinks[0]["bytes_received"].as_u64().unwrap() > 0);
+        assert_eq!(sinks[0]["reconnect_count"], 0);
+
+        // The same sink coming back gets a new id but is recognized by version and providers
+        drop(sink);
+        let _sink = connect_sink(addr, &sink_manager).await;
+        let second_id = loop {
+            match sink_manager.list_sinks().await.first() {
+                Some(info) if info.connection.id != first_id => break info.connection.id,
+                _ => tokio::time::sleep(std::time::Duration::from_millis(5)).await,
+            }
+        };
+
+        let sinks: serde_json::Value = reqwest::get(format!("http://{}/v1/sinks", addr))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(sinks[0]["reconnect_count"], 1);
+        let text = reqwest::get(format!("http://{}/metrics", addr))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(text.contains(&format!(
+            "promptivd_sink_reconnects{{sink_id=\"{}\"}} 1\n",
+            second_id
+        )));
+        assert!(text.contains("# TYPE promptivd_sink_bytes_sent_total counter\n"));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_queues_jobs_until_it_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = create_test_config();
+        config.server.persist_queue = true;
+        config.server.queue_path = Some(dir.path().join("queue.jsonl"));
+        config.server.admin_token = Some("secret".to_string());
+        let (addr, sink_manager) = spawn_server(config).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let client = reqwest::Client::new();
+        let set_maintenance = |enabled: bool| {
+            client
+                .post(format!("http://{}/v1/maintenance", addr))
+                .bearer_auth("secret")
+                .json(&serde_json::json!({"enabled": enabled, "banner": "Back at noon"}))
+                .send()
+        };
+
+        let started: promptivd::models::MaintenanceResponse =
+            set_maintenance(true).await.unwrap().json().await.unwrap();
+        assert!(started.maintenance);
+        assert_eq!(started.banner.as_deref(), Some("Back at noon"));
+
+        let response = client
+            .post(format!("http://{}/v1/insert", addr))
+            .json(&insert_body())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "queued");
+        assert_eq!(body["banner"], "Back at noon");
+        let queued_id = body["job_id"].as_str().unwrap().to_string();
+
+        let status: promptivd::models::StatusResponse = client
+            .get(format!("http://{}/v1/status", addr))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(status.maintenance);
+        assert_eq!(status.maintenance_banner.as_deref(), Some("Back at noon"));
+
+        // The connected sink is not sent the job while maintenance is on
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), next_job(&mut sink))
+                .await
+                .is_err()
+        );
+        assert_eq!(sink_manager.queue().unwrap().len(), 1);
+
+        let ended: promptivd::models::MaintenanceResponse =
+            set_maintenance(false).await.unwrap().json().await.unwrap();
+        assert!(!ended.maintenance);
+        assert_eq!(ended.queued_jobs, 1);
+
+        let (id, payload) = next_job(&mut sink).await;
+        assert_eq!(id, queued_id);
+        assert_eq!(payload.text, "hello");
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while !sink_manager.queue().unwrap().is_empty() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_jobs_queued_without_sink_are_replayed_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = create_test_config();
+        config.server.persist_queue = true;
+        config.server.queue_path = Some(dir.path().join("queue.jsonl"));
+        let (addr, sink_manager) = spawn_server(config).await;
+        let client = reqwest::Client::new();
+
+        let mut queued_ids = Vec::new();
+        for text in ["first", "second"] {
+            let mut body = insert_body();
+            body["text"] = text.into();
+            let response = client
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["status"], "queued");
+            queued_ids.push(body["job_id"].as_str().unwrap().to_string());
+        }
+        assert_eq!(sink_manager.queue().unwrap().len(), 2);
+
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        for (expected_id, expected_text) in queued_ids.iter().zip(["first", "second"]) {
+            let (id, payload) = next_job(&mut sink).await;
+            assert_eq!(&id, expected_id);
+            assert_eq!(payload.text, expected_text);
+
+            let ack = ack(&id, AckStatus::Ok);
+            sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+                .await
+                .unwrap();
+        }
+
+        // Each job leaves the queue only after its ack, so the queue empties last
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while !sink_manager.queue().unwrap().is_empty() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_binary_frames_carry_jobs_and_acks_once_negotiated() {
+        use promptivd::websocket::{decode_binary, encode_binary};
+
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let (mut sink, _) = connect_async(format!("ws://{}/v1/sink/ws", addr))
+            .await
+            .unwrap();
+        let mut register = register_message(&["chatgpt"], "test");
+        if let SinkMessage::Register { binary_frames, .. } = &mut register {
+            *binary_frames = true;
+        }
+        sink.send(Message::Text(serde_json::to_string(&register).unwrap()))
+            .await
+            .unwrap();
+
+        let Message::Text(policy) = sink.next().await.unwrap().unwrap() else {
+            panic!("policy must be a text frame");
+        };
+        assert!(matches!(
+            serde_json::from_str(&policy).unwrap(),
+            RelayMessage::Policy {
+                binary_frames: true,
+                ..
+            }
+        ));
+        while !sink_manager.has_active_sink() {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        let insert = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send(),
+        );
+        let id = loop {
+            if let Message::Binary(bytes) = sink.next().await.unwrap().unwrap() {
+                if let RelayMessage::InsertText { id, payload, .. } = decode_binary(&bytes).unwrap()
+                {
+                    assert_eq!(payload.text, "hello");
+                    break id;
+                }
+            }
+        };
+
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Binary(encode_binary(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        let response = insert.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_provider_default_metadata_merged_into_job() {
+        let mut config = create_test_config();
+        config.server.default_metadata = serde_json::json!({"team": "docs", "max_tokens": 1024})
+            .as_object()
+            .unwrap()
+            .clone();
+        config.server.provider_default_metadata.insert(
+            "chatgpt".to_string(),
+            serde_json::json!({"max_tokens": 8192, "model": "gpt"}),
+        );
+        let (addr, sink_manager) = spawn_server(config).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let mut body = insert_body();
+        body["target"] = serde_json::json!({"provider": "chatgpt"});
+        body["metadata"] = serde_json::json!({"model": "caller"});
+        tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&body)
+                .send()
+                .await
+        });
+
+        let (_, payload) = next_job(&mut sink).await;
+        assert_eq!(
+            payload.metadata,
+            Some(serde_json::json!({"model": "caller", "max_tokens": 8192, "team": "docs"}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_job_held_while_sink_busy_is_delivered_after_ready() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let busy = SinkMessage::Busy {
+            schema_version: "1.0".to_string(),
+            reason: Some("Reloading content scripts".to_string()),
+            resume_hint_ms: Some(500),
+        };
+        sink.send(Message::Text(serde_json::to_string(&busy).unwrap()))
+            .await
+            .unwrap();
+        while sink_manager.sink_ready().await {
+            tokio::task::yield_now().await;
+        }
+
+        let insert = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send()
+                .await
+                .unwrap()
+        });
+
+        // Nothing but pings may reach the sink while it is busy
+        let held = tokio::time::timeout(std::time::Duration::from_millis(200), next_job(&mut sink));
+        assert!(held.await.is_err(), "job was dispatched to a busy sink");
+
+        let ready = SinkMessage::Ready {
+            schema_version: "1.0".to_string(),
+        };
+        sink.send(Message::Text(serde_json::to_string(&ready).unwrap()))
+            .await
+            .unwrap();
+
+        let (id, _) = next_job(&mut sink).await;
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        assert!(insert.await.unwrap().status().is_success());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_export_config_round_trips_effective_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.yaml");
+        std::fs::write(&source, "server:\n  admin_token: hunter2\n").unwrap();
+        let cli = |export: &std::path::Path, redact: bool| {
+            let mut args = vec![
+                "promptivd".to_string(),
+                "--config".to_string(),
+                source.display().to_string(),
+                "--bind".to_string(),
+                "127.0.0.1:9999".to_string(),
+                "--export-config".to_string(),
+                export.display().to_string(),
+            ];
+            if redact {
+                args.push("--redact-secrets".to_string());
+            }
+            Cli::parse_from(args)
+        };
+
+        let yaml = dir.path().join("exported.yaml");
+        let toml = dir.path().join("exported.toml");
+        let redacted = dir.path().join("redacted.yaml");
+        std::env::set_var("PROMPTIVD_SERVER__MAX_JOB_BYTES", "4096");
+        run(cli(&yaml, false)).await.unwrap();
+        run(cli(&toml, false)).await.unwrap();
+        run(cli(&redacted, true)).await.unwrap();
+        std::env::remove_var("PROMPTIVD_SERVER__MAX_JOB_BYTES");
+
+        for path in [&yaml, &toml] {
+            let reread = AppConfig::from_file(Some(path)).unwrap();
+            assert_eq!(reread.server.max_job_bytes, 4096, "{}", path.display());
+            assert_eq!(reread.server.bind_addrs[0].to_string(), "127.0.0.1:9999");
+            assert_eq!(reread.server.admin_token.as_deref(), Some("hunter2"));
+        }
+
+        let contents = std::fs::read_to_string(&redacted).unwrap();
+        assert!(!contents.contains("hunter2"));
+        assert!(contents.contains(promptivd::config::REDACTED));
+    }
+
+    #[test]
+    #[serial]
+    fn test_explain_config_attributes_values_to_their_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.yaml");
+        std::fs::write(
+            &source,
+            "server:\n  bind_addr: 127.0.0.1:7000\n  max_job_bytes: 4096\n",
+        )
+        .unwrap();
+        let cli = Cli::parse_from([
+            "promptivd",
+            "--config",
+            source.to_str().unwrap(),
+            "--bind",
+            "127.0.0.1:9999",
+        ]);
+
+        let explained = explain_config(&cli).unwrap();
+        let lookup = |key: &str| {
+            explained
+                .iter()
+                .find(|entry| entry.key == key)
+                .unwrap_or_else(|| panic!("{} not explained", key))
+        };
+
+        let bind_addrs = lookup("server.bind_addrs");
+        assert_eq!(bind_addrs.value, serde_json::json!(["127.0.0.1:9999"]));
+        assert_eq!(bind_addrs.source, ConfigSource::Cli);
+
+        let max_job_bytes = lookup("server.max_job_bytes");
+        assert_eq!(max_job_bytes.value, 4096);
+        assert_eq!(max_job_bytes.source, ConfigSource::File(source.clone()));
+
+        assert_eq!(lookup("log_level").source, ConfigSource::Default);
+    }
+
+    #[tokio::test]
+    async fn test_tls_listener_serves_https() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let tls = RustlsConfig::from_pem(
+            generated.cert.pem().into_bytes(),
+            generated.key_pair.serialize_pem().into_bytes(),
+        )
+        .await
+        .unwrap();
+
+        let config = create_test_config();
+        let sink_manager = Arc::new(SinkManager::new(config.server.clone()));
+        let state = AppState::new(sink_manager).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (_rebind_tx, rebind_rx) = mpsc::unbounded_channel();
+        tokio::spawn(serve_with_rebind(
+            create_router(state, &config),
+            Some(tls),
+            vec![(ListenSpec::Tcp(addr), BoundListener::Tcp(listener))],
+            rebind_rx,
+            std::time::Duration::from_secs(5),
+            std::future::pending(),
+        ));
+
+        // The certificate is self-signed, so only the transport is under test here
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("https://localhost:{}/v1/health", addr.port()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let plain = reqwest::get(format!("http://{}/v1/health", addr)).await;
+        assert!(plain.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rebind_serves_new_address_while_old_requests_drain() {
+        let config = create_test_config();
+        let sink_manager = Arc::new(SinkManager::new(config.server.clone()));
+        let state = AppState::new(Arc::clone(&sink_manager)).unwrap();
+        let app = create_router(state, &config);
+
+        let old = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let old_addr = old.local_addr().unwrap();
+        let (rebind_tx, rebind_rx) = mpsc::unbounded_channel();
+        tokio::spawn(serve_with_rebind(
+            app,
+            None,
+            vec![(ListenSpec::Tcp(old_addr), BoundListener::Tcp(old))],
+            rebind_rx,
+            std::time::Duration::from_secs(5),
+            std::future::pending(),
+        ));
+
+        // Leave an insert in flight on the old address
+        let mut sink = connect_sink(old_addr, &sink_manager).await;
+        let insert = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", old_addr))
+                .json(&insert_body())
+                .send()
+                .await
+                .unwrap()
+        });
+        let (id, _) = next_job(&mut sink).await;
+
+        let new = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let new_addr = new.local_addr().unwrap();
+        rebind_tx
+            .send(Rebind {
+                added: vec![(ListenSpec::Tcp(new_addr), BoundListener::Tcp(new))],
+                removed: vec![ListenSpec::Tcp(old_addr)],
+            })
+            .unwrap();
+
+        let health = reqwest::get(format!("http://{}/v1/health", new_addr))
+            .await
+            .unwrap();
+        assert_eq!(health.status(), reqwest::StatusCode::OK);
+
+        // The old listener stops accepting once the rebind lands
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        while tokio::net::TcpStream::connect(old_addr).await.is_ok() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "old listener still open"
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        let response = insert.await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    /// Sends only the request head and returns the first response bytes the server writes.
+    async fn send_head(addr: SocketAddr, head: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(head.as_bytes()).await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let len = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_rejects_oversized_body_before_transfer() {
+        let (addr, _) = spawn_server(create_test_config()).await;
+
+        // The body is never sent; the daemon must answer from the head alone
+        let response = send_head(
+            addr,
+            "POST /v1/insert HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Expect: 100-continue\r\nContent-Length: 10000000\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+        let response = send_head(
+            addr,
+            "POST /v1/insert HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Expect: 200-ok\r\nContent-Length: 10\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 417"), "{}", response);
+
+        // Within the limit the client is told to go ahead
+        let response = send_head(
+            addr,
+            "POST /v1/insert HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Expect: 100-continue\r\nContent-Length: 100\r\n\r\n",
+        )
+        .await;
+        assert!(
+            response.starts_with("HTTP/1.1 100 Continue"),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_swaps_live_settings_into_running_server() {
+        let mut config = create_test_config();
+        config.server.max_job_bytes = 1024;
+        let (addr, sink_manager) = spawn_server(config.clone()).await;
+        // Background inserts are only accepted once a sink has them
+        let _sink = connect_sink(addr, &sink_manager).await;
+        let state = AppState::new(Arc::clone(&sink_manager)).unwrap();
+        let client = reqwest::Client::new();
+        let insert = |text: String| {
+            let mut body = insert_body();
+            body["text"] = text.into();
+            client
+                .post(format!("http://{}/v1/insert?wait=false", addr))
+                .json(&body)
+                .send()
+        };
+
+        let response = insert("x".repeat(4096)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Raising the limit past the startup value takes effect without a restart
+        let mut next = config.clone();
+        next.server.max_job_bytes = 64 * 1024;
+        next.server.dispatch_timeout = std::time::Duration::from_secs(7);
+        next.server.cors_allow_any = true;
+        let applied = apply_reload(&state, &config, &next);
+        assert_eq!(applied.server.max_job_bytes, 64 * 1024);
+        assert!(!applied.server.cors_allow_any);
+        assert_eq!(
+            sink_manager.config().load().dispatch_timeout,
+            std::time::Duration::from_secs(7)
+        );
+
+        let response = insert("x".repeat(4096)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+
+        // Lowering it also cuts off bodies that declare no length
+        let mut lower = applied.clone();
+        lower.server.max_job_bytes = 64;
+        apply_reload(&state, &applied, &lower);
+        let chunk = format!("{{\"text\": \"{}\"}}", "x".repeat(200));
+        let response = send_head(
+            addr,
+            &format!(
+                "POST /v1/insert HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n{:X}\r\n{}\r\n0\r\n\r\n",
+                chunk.len(),
+                chunk
+            ),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_missing_capability_downgrades_placement_when_configured() {
+        let mut config = create_test_config();
+        config
+            .server
+            .capability_downgrades
+            .insert("cursor".to_string(), Placement::Bottom);
+        let (addr, sink_manager) = spawn_server(config).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let client = reqwest::Client::new();
+
+        let mut body = insert_body();
+        body["placement"] = serde_json::json!({"type": "cursor"});
+        body["required_capability"] = "cursor".into();
+        let insert = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .post(format!("http://{}/v1/insert", addr))
+                    .json(&body)
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        });
+
+        let (id, payload) = next_job(&mut sink).await;
+        assert_eq!(payload.placement, Some(Placement::Bottom));
+        let ack = ack(&id, AckStatus::Ok);
+        sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+            .await
+            .unwrap();
+
+        let response = insert.await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            body["downgrade"],
+            serde_json::json!({
+                "capability": "cursor",
+                "downgraded_from": {"type": "cursor"},
+                "downgraded_to": {"type": "bottom"},
+            })
+        );
+
+        // Capabilities without a configured fallback are still rejected
+        let mut body = insert_body();
+        body["required_capability"] = "top".into();
+        let response = client
+            .post(format!("http://{}/v1/insert", addr))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_route_to_the_sink_serving_their_provider() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut chatgpt = connect_sink_for(addr, &sink_manager, "chatgpt").await;
+        let mut claude = connect_sink_for(addr, &sink_manager, "claude").await;
+        let client = reqwest::Client::new();
+
+        let submit = |provider: Option<&str>| {
+            let mut body = insert_body();
+            if let Some(provider) = provider {
+                body["target"] = serde_json::json!({"provider": provider});
+            }
+            let request = client
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&body);
+            tokio::spawn(async move { request.send().await.unwrap() })
+        };
+
+        // An explicit provider picks its sink; no provider falls back to the oldest sink
+        for (provider, to_claude) in [
+            (Some("claude"), true),
+            (None, false),
+            (Some("chatgpt"), false),
+        ] {
+            let sink = if to_claude { &mut claude } else { &mut chatgpt };
+            let insert = submit(provider);
+            let (id, _) = next_job(sink).await;
+            sink.send(Message::Text(
+                serde_json::to_string(&ack(&id, AckStatus::Ok)).unwrap(),
+            ))
+            .await
+            .unwrap();
+            assert_eq!(insert.await.unwrap().status(), reqwest::StatusCode::OK);
+        }
+
+        let response = submit(Some("gemini")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].as_str().unwrap().contains("'gemini'"));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reaches_every_sink() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sinks = Vec::new();
+        for provider in ["chatgpt", "claude"] {
+            sinks.push(connect_sink_for(addr, &sink_manager, provider).await);
+        }
+        let sink_ids: Vec<String> = sink_manager
+            .list_sinks()
+            .await
+            .iter()
+            .map(|sink| sink.connection.id.to_string())
+            .collect();
+        let client = reqwest::Client::new();
+
+        for (statuses, expected_code, expected_status, partial) in [
+            (
+                [AckStatus::Ok, AckStatus::Ok],
+                reqwest::StatusCode::OK,
+                "ok",
+                false,
+            ),
+            (
+                [AckStatus::Ok, AckStatus::Failed],
+                reqwest::StatusCode::OK,
+                "ok",
+                true,
+            ),
+            (
+                [AckStatus::Retry, AckStatus::Failed],
+                reqwest::StatusCode::BAD_GATEWAY,
+                "failed",
+                false,
+            ),
+        ] {
+            let mut body = insert_body();
+            body["broadcast"] = true.into();
+            let request = client
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&body);
+            let insert = tokio::spawn(async move { request.send().await.unwrap() });
+
+            for (sink, status) in sinks.iter_mut().zip(statuses.clone()) {
+                let (id, _) = next_job(sink).await;
+                let ack = ack(&id, status);
+                sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+                    .await
+                    .unwrap();
+            }
+
+            let response = insert.await.unwrap();
+            assert_eq!(response.status(), expected_code);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["status"], expected_status);
+            assert_eq!(body["partial"], partial);
+            let results = body["results"].as_array().unwrap();
+            assert_eq!(results.len(), 2);
+            for ((result, sink_id), status) in results.iter().zip(&sink_ids).zip(statuses) {
+                assert_eq!(result["sink_id"], sink_id.as_str());
+                assert_eq!(result["status"], status.to_string());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_and_failed_acks_map_to_distinct_status_codes() {
+        let (addr, sink_manager) = spawn_server(create_test_config()).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        for (ack_status, expected) in [
+            (AckStatus::Retry, reqwest::StatusCode::SERVICE_UNAVAILABLE),
+            (AckStatus::Failed, reqwest::StatusCode::BAD_GATEWAY),
+        ] {
+            let insert = tokio::spawn(async move {
+                reqwest::Client::new()
+                    .post(format!("http://{}/v1/insert", addr))
+                    .json(&insert_body())
+                    .send()
+                    .await
+                    .unwrap()
+            });
+
+            let (id, _) = next_job(&mut sink).await;
+            let mut ack = ack(&id, ack_status.clone());
+            if let SinkMessage::Ack { error, .. } = &mut ack {
+                *error = Some("sink said no".to_string());
+            }
+            sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+                .await
+                .unwrap();
+
+            let response = insert.await.unwrap();
+            assert_eq!(response.status(), expected);
+            assert_eq!(
+                response.headers().contains_key("retry-after"),
+                ack_status == AckStatus::Retry
+            );
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["status"], ack_status.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_acks_are_redispatched_until_ok() {
+        let mut config = create_test_config();
+        config.server.dispatch_max_retries = 3;
+        config.server.dispatch_retry_backoff = std::time::Duration::from_millis(10);
+        let (addr, sink_manager) = spawn_server(config).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let insert = |addr: SocketAddr| {
+            tokio::spawn(async move {
+                reqwest::Client::new()
+                    .post(format!("http://{}/v1/insert", addr))
+                    .json(&insert_body())
+                    .send()
+                    .await
+                    .unwrap()
+            })
+        };
+
+        let pending = insert(addr);
+        let mut job_ids = Vec::new();
+        for status in [AckStatus::Retry, AckStatus::Retry, AckStatus::Ok] {
+            let (id, payload) = next_job(&mut sink).await;
+            assert_eq!(payload.text, "hello");
+            job_ids.push(id.clone());
+            sink.send(Message::Text(
+                serde_json::to_string(&ack(&id, status)).unwrap(),
+            ))
+            .await
+            .unwrap();
+        }
+        assert!(job_ids.iter().all(|id| *id == job_ids[0]));
+
+        let response = pending.await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["retries"], 2);
+
+        // Failed is terminal, so it is returned without another attempt
+        let pending = insert(addr);
+        let (id, _) = next_job(&mut sink).await;
+        sink.send(Message::Text(
+            serde_json::to_string(&ack(&id, AckStatus::Failed)).unwrap(),
+        ))
+        .await
+        .unwrap();
+        let response = pending.await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body.get("retries").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retry_not_redispatched_past_dispatch_timeout() {
+        let mut config = create_test_config();
+        config.server.dispatch_max_retries = 3;
+        config.server.dispatch_timeout = std::time::Duration::from_millis(500);
+        config.server.dispatch_retry_backoff = std::time::Duration::from_secs(1);
+        let (addr, sink_manager) = spawn_server(config).await;
+        let mut sink = connect_sink(addr, &sink_manager).await;
+
+        let started = std::time::Instant::now();
+        let pending = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/insert", addr))
+                .json(&insert_body())
+                .send()
+                .await
+                .unwrap()
+        });
+        let (id, _) = next_job(&mut sink).await;
+        sink.send(Message::Text(
+            serde_json::to_string(&ack(&id, AckStatus::Retry)).unwrap(),
+        ))
+        .await
+        .unwrap();
+
+        // The backoff would outlast the timeout the attempts share, so the retry ack is returned
+        let response = pending.await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "retry");
+        assert!(body.get("retries").is_none());
+        let again =
+            tokio::time::timeout(std::time::Duration::from_millis(200), next_job(&mut sink));
+        assert!(again.await.is_err(), "job was redispatched");
+    }
+
+    #[tokio::test]
+    async fn test_auth_token_guards_insert() {
+        let mut config = create_test_config();
+        config.server.auth_token = Some("secret".to_string());
+        let sink_manager = Arc::new(SinkManager::new(config.server.clone()));
+        let state = AppState::new(sink_manager).unwrap();
+        let app = create_router(state, &config);
+
+        for (authorization, expected) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("Bearer wrong"), StatusCode::UNAUTHORIZED),
+            // Authorized, then rejected only because no sink is connected
+            (Some("Bearer secret"), StatusCode::SERVICE_UNAVAILABLE),
+        ] {
+            let mut request = axum::http::Request::builder()
+                .method("POST")
+                .uri("/v1/insert")
+                .header("content-type", "application/json");
+            if let Some(value) = authorization {
+                request = request.header("authorization", value);
+            }
+            let response = app
+                .clone()
+                .oneshot(
+                    request
+                        .body(axum::body::Body::from(insert_body().to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{:?}", authorization);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_token_checked_before_websocket_upgrade() {
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error};
+
+        let mut config = create_test_config();
+        config.server.auth_token = Some("secret".to_string());
+        let (addr, _) = spawn_server(config).await;
+
+        let connect = |authorization: Option<&'static str>| async move {
+            let mut request = format!("ws://{}/v1/sink/ws", addr)
+                .into_client_request()
+                .unwrap();
+            if let Some(value) = authorization {
+                request
+                    .headers_mut()
+                    .insert("authorization", value.parse().unwrap());
+            }
+            connect_async(request).await
+        };
+
+        for authorization in [None, Some("Bearer wrong")] {
+            match connect(authorization).await {
+                Err(Error::Http(response)) => {
+                    assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+                }
+                other => panic!("expected 401 for {:?}, got {:?}", authorization, other),
+            }
+        }
+        assert!(connect(Some("Bearer secret")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sink_must_answer_reauth_challenges() {
+        use tokio_tungstenite::tungstenite::{
+            client::IntoClientRequest,
+            protocol::{frame::coding::CloseCode, CloseFrame},
+        };
+
+        let mut config = create_test_config();
+        config.server.auth_token = Some("secret".to_string());
+        config.server.sink_reauth_interval = std::time::Duration::from_millis(100);
+        let (addr, sink_manager) = spawn_server(config).await;
+
+        let connect = || async move {
+            let mut request = format!("ws://{}/v1/sink/ws", addr)
+                .into_client_request()
+                .unwrap();
+            request
+                .headers_mut()
+                .insert("authorization", "Bearer secret".parse().unwrap());
+            let (mut ws, _) = connect_async(request).await.unwrap();
+            let register = register_message(&["chatgpt"], "test");
+            ws.send(Message::Text(serde_json::to_string(&register).unwrap()))
+                .await
+                .unwrap();
+            ws
+        };
+        /// The nonce of the next challenge, or the close frame if the daemon hangs up first.
+        async fn next_challenge(
+            ws: &mut SinkStream,
+        ) -> Result<String, Option<CloseFrame<'static>>> {
+            while let Some(msg) = ws.next().await {
+                match msg.unwrap() {
+                    Message::Text(text) => {
+                        if let Ok(RelayMessage::ReauthChallenge { nonce, .. }) =
+                            serde_json::from_str(&text)
+                        {
+                            return Ok(nonce);
+                        }
+                    }
+                    Message::Close(frame) => return Err(frame),
+                    _ => {}
+                }
+            }
+            Err(None)
+        }
+        let answer = |nonce: String, token: &str| {
+            let response = SinkMessage::ReauthResponse {
+                schema_version: "1.0".to_string(),
+                nonce,
+                token: token.to_string(),
+            };
+            Message::Text(serde_json::to_string(&response).unwrap())
+        };
+        let assert_closed = |result: Result<String, Option<CloseFrame>>, reason: &str| {
+            let frame = result.expect_err("sink was challenged again").unwrap();
+            assert_eq!(frame.code, CloseCode::Policy);
+            assert_eq!(frame.reason, reason);
+        };
+
+        let mut ws = connect().await;
+        for _ in 0..3 {
+            let nonce = next_challenge(&mut ws).await.unwrap();
+            ws.send(answer(nonce, "secret")).await.unwrap();
+        }
+        assert!(sink_manager.has_active_sink());
+        drop(ws);
+
+        let mut ws = connect().await;
+        let nonce = next_challenge(&mut ws).await.unwrap();
+        ws.send(answer(nonce, "wrong")).await.unwrap();
+        assert_closed(next_challenge(&mut ws).await, "Re-authentication failed");
+
+        let mut ws = connect().await;
+        next_challenge(&mut ws).await.unwrap();
+        assert_closed(
+            next_challenge(&mut ws).await,
+            "Re-authentication not answered",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_websocket_upgrade_checks_origin() {
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error};
+
+        let connect = |addr: SocketAddr, origin: Option<&'static str>| async move {
+            let mut request = format!("ws://{}/v1/sink/ws", addr)
+                .into_client_request()
+                .unwrap();
+            if let Some(origin) = origin {
+                request
+                    .headers_mut()
+                    .insert("origin", origin.parse().unwrap());
+            }
+            connect_async(request).await
+        };
+        let assert_forbidden = |result: Result<_, Error>| match result {
+            Err(Error::Http(response)) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+            other => panic!("expected 403, got {:?}", other.map(|_| ())),
+        };
+
+        // Without ws_allowed_origins the CORS list applies
+        let (addr, _) = spawn_server(create_test_config()).await;
+        assert_forbidden(connect(addr, Some("https://evil.example")).await);
+        assert!(connect(addr, Some("http://localhost:3000")).await.is_ok());
+        assert!(connect(addr, None).await.is_ok());
+
+        let mut config = create_test_config();
+        config.server.ws_allowed_origins = Some(vec!["chrome-extension://abcdef".to_string()]);
+        let (addr, _) = spawn_server(config.clone()).await;
+        assert_forbidden(connect(addr, Some("http://localhost:3000")).await);
+        assert!(connect(addr, Some("chrome-extension://abcdef"))
+            .await
+            .is_ok());
+
+        config.server.ws_allow_any_origin = true;
+        let (addr, _) = spawn_server(config).await;
+        assert!(connect(addr, Some("https://evil.example")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_max_age_is_configurable() {
+        let mut config = create_test_config();
+        config.server.cors_max_age_secs = 120;
+        let state = create_test_state();
+        let app = create_router(state, &config);
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/v1/insert")
+                    .header(axum::http::header::ORIGIN, "http://localhost:3000")
+                    .header(axum::http::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response
+                .headers()
+                .get(axum::http::header::ACCESS_CONTROL_MAX_AGE)
+                .unwrap(),
+            "120"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_origins_are_configurable() {
+        let preflight = |config: AppConfig, origin: &'static str| async move {
+            let app = create_router(create_test_state(), &config);
+            let response = app
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method(Method::OPTIONS)
+                        .uri("/v1/insert")
+                        .header(axum::http::header::ORIGIN, origin)
+                        .header(axum::http::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            response
+                .headers()
+                .get(axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        let mut config = create_test_config();
+        config.server.cors_allowed_origins = vec!["http://localhost:5173".to_string()];
+        assert_eq!(
+            preflight(config.clone(), "http://localhost:5173").await,
+            Some("http://localhost:5173".to_string())
+        );
+        assert_eq!(
+            preflight(config.clone(), "http://localhost:3000").await,
+            None
+        );
+
+        config.server.cors_allow_any = true;
+        assert_eq!(
+            preflight(config, "http://localhost:3000").await,
+            Some("*".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bind_address_in_use_is_reported() {
+        let first = bind_listener("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = first.local_addr().unwrap();
+
+        let err = bind_listener(addr).await.unwrap_err();
+
+        assert!(matches!(err, AppError::AddrInUse { addr: a } if a == addr));
+        let message = err.to_string();
+        assert!(message.contains(&addr.to_string()));
+        assert!(message.contains("another promptivd instance"));
+        assert!(message.contains("--bind"));
+        assert_eq!(exit_code(&err), EXIT_ADDR_IN_USE);
+    }
+
+    #[tokio::test]
+    async fn test_failed_bind_releases_addresses_already_bound() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("promptivd.sock");
+        let taken = bind_listener("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let specs = [
+            ListenSpec::Unix(path.clone()),
+            ListenSpec::Tcp(taken.local_addr().unwrap()),
+        ];
+
+        let Err(err) = bind_all(&specs).await else {
+            panic!("address in use was bound again");
+        };
+        assert!(matches!(err, AppError::AddrInUse { .. }));
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_unix_listener_serves_inserts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("promptivd.sock");
+        // Left behind by a daemon that did not shut down cleanly
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let config = create_test_config();
+        let sink_manager = start_sink_manager(&config.server).unwrap();
+        let state = AppState::new(Arc::clone(&sink_manager)).unwrap();
+        let app = create_router(state, &config);
+        let tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let tcp_app = app.clone();
+        tokio::spawn(async move {
+            axum::serve(tcp, tcp_app).await.unwrap();
+        });
+
+        let spec = ListenSpec::Unix(path.clone());
+        let unix = BoundListener::bind(&spec).await.unwrap().serve(app, None);
+        let Err(err) = BoundListener::bind(&spec).await else {
+            panic!("socket in use was bound again");
+        };
+        assert!(matches!(&err, AppError::SocketInUse { path: p } if *p == path));
+        assert_eq!(exit_code(&err), EXIT_ADDR_IN_USE);
+
+        let mut sink = connect_sink(addr, &sink_manager).await;
+        let server = format!(
+            "{}{}",
+            promptivd::client::UNIX_SCHEME,
+            path.display().to_string().replace('/', "%2F")
+        );
+        // Two inserts on one client, the second over the connection the first opened
+        let insert = tokio::spawn(async move {
+            let request: promptivd::models::InsertTextRequest =
+                serde_json::from_value(insert_body()).unwrap();
+            let client = promptivd::client::InsertClient::new(server);
+            let first = client.insert(&request).await.unwrap();
+            let second = client.insert(&request).await.unwrap();
+            [first, second]
+        });
+
+        for _ in 0..2 {
+            let (id, payload) = next_job(&mut sink).await;
+            assert_eq!(payload.text, "hello");
+            let ack = ack(&id, AckStatus::Ok);
+            sink.send(Message::Text(serde_json::to_string(&ack).unwrap()))
+                .await
+                .unwrap();
+        }
+
+        let [first, second] = insert.await.unwrap();
+        for response in [&first, &second] {
+            assert!(response.status.is_success());
+            assert_eq!(response.body["status"], "ok");
+        }
+        assert_eq!(first.reused_connection, Some(false));
+        assert_eq!(second.reused_connection, Some(true));
+
+        unix.drain(None).await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_logs_are_shipped_to_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("promptivd-log.sock");
+        let log_listener = std::os::unix::net::UnixDatagram::bind(&socket_path).unwrap();
+        log_listener.set_nonblocking(true).unwrap();
+
+        let mut config = create_test_config();
+        config.log_socket_path = Some(socket_path);
+        let (subscriber, _reload) = build_subscriber(&config).unwrap();
+        // The test runtime is single-threaded, so the server task logs through this subscriber
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (addr, _sink_manager) = spawn_server(config).await;
+        let response = reqwest::get(format!("http://{}/v1/health", addr))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        let len = loop {
+            match log_listener.recv(&mut buf) {
+                Ok(len) => break len,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    assert!(
+                        tokio::time::Instant::now() < deadline,
+                        "no log line received"
+                    );
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                Err(e) => panic!("recv failed: {}", e),
+            }
+        };
+
+        let line = std::str::from_utf8(&buf[..len]).unwrap();
+        let record: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert!(record.get("level").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_second_signal_forces_exit() {
+        let (signal_tx, signal_rx) = mpsc::unbounded_channel();
+        let (graceful_tx, mut graceful_rx) = oneshot::channel();
+        let (forced_tx, mut forced_rx) = oneshot::channel();
+        let supervisor = tokio::spawn(supervise_shutdown(signal_rx, graceful_tx, move || {
+            forced_tx.send(()).unwrap()
+        }));
+
+        // The first signal only starts the graceful drain
+        signal_tx.send(ShutdownSignal::Interrupt).unwrap();
+        (&mut graceful_rx).await.unwrap();
+        tokio::task::yield_now().await;
+        assert!(forced_rx.try_recv().is_err());
+
+        signal_tx.send(ShutdownSignal::Interrupt).unwrap();
+        forced_rx.await.unwrap();
+        supervisor.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_single_signal_does_not_force_exit() {
+        let (signal_tx, signal_rx) = mpsc::unbounded_channel();
+        let (graceful_tx, graceful_rx) = oneshot::channel();
+        let forced = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let forced_flag = Arc::clone(&forced);
+
+        signal_tx.send(ShutdownSignal::Terminate).unwrap();
+        drop(signal_tx);
+        supervise_shutdown(signal_rx, graceful_tx, move || {
+            forced_flag.store(true, std::sync::atomic::Ordering::SeqCst)
+        })
+        .await;
+
+        graceful_rx.await.unwrap();
+        assert!(!forced.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
     #[test]
     fn test_config_validation() {
         let config = create_test_config();
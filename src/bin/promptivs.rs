@@ -3,13 +3,18 @@ use std::time::Duration;
 use clap::{Parser, ValueEnum};
 use futures_util::{SinkExt, StreamExt};
 use tokio::time::sleep;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, protocol::Message};
 use tracing::{error, info, warn};
 
-use promptivd::websocket::{AckStatus, RelayMessage, SinkMessage};
+use promptivd::websocket::{
+    decode_binary, encode_binary, AckStatus, RelayMessage, SinkMessage, EXPIRED_ACK_ERROR,
+};
 
 const SCHEMA_VERSION: &str = "1.0";
 const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Simulated chunks a job is inserted in when `--ack-delay-ms` is set.
+const PROGRESS_STEPS: u32 = 4;
 
 #[derive(Debug, Parser)]
 #[command(name = "promptivs")]
@@ -20,11 +25,21 @@ struct Cli {
     #[arg(long, default_value = "ws://127.0.0.1:8787/v1/sink/ws")]
     server: String,
 
+    /// Bearer token to connect with, and to answer re-authentication challenges with, when the
+    /// daemon sets `server.auth_token`
+    #[arg(long, env = "PROMPTIVS_AUTH_TOKEN")]
+    auth_token: Option<String>,
+
     /// Ack behaviour for incoming jobs
     #[arg(long, value_enum, default_value_t = AckMode::Ok)]
     ack_mode: AckMode,
 
-    /// Artificial processing delay before sending ACK (milliseconds)
+    /// Warning to attach to successful ACKs (may be passed multiple times)
+    #[arg(long = "ack-warning", value_name = "TEXT")]
+    ack_warnings: Vec<String>,
+
+    /// Artificial processing delay before sending ACK (milliseconds), reported as progress in
+    /// simulated chunks
     #[arg(long, default_value_t = 0u64)]
     ack_delay_ms: u64,
 
@@ -39,6 +54,30 @@ struct Cli {
     /// Provider identifiers supported by this sink (may be passed multiple times)
     #[arg(long = "provider", value_name = "ID", default_values_t = vec![String::from("chatgpt")])]
     providers: Vec<String>,
+
+    /// Ask the daemon for MessagePack binary frames instead of JSON text
+    #[arg(long)]
+    binary_frames: bool,
+
+    /// Initial delay before reconnecting after the connection drops (milliseconds)
+    #[arg(long, default_value_t = 500u64)]
+    reconnect_base_ms: u64,
+
+    /// Upper bound on the reconnect delay as it doubles (milliseconds)
+    #[arg(long, default_value_t = 30_000u64)]
+    reconnect_max_ms: u64,
+
+    /// Exit when the connection ends instead of reconnecting
+    #[arg(long)]
+    no_reconnect: bool,
+}
+
+/// How a connection ended without an error.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Disconnect {
+    Closed,
+    /// Another sink took over; reconnecting would only displace it in turn.
+    Superseded,
 }
 
 #[derive(Debug, Copy, Clone, ValueEnum)]
@@ -46,6 +85,7 @@ enum AckMode {
     Ok,
     Retry,
     Failed,
+    Cancelled,
 }
 
 impl std::fmt::Display for AckMode {
@@ -54,6 +94,7 @@ impl std::fmt::Display for AckMode {
             AckMode::Ok => write!(f, "ok"),
             AckMode::Retry => write!(f, "retry"),
             AckMode::Failed => write!(f, "failed"),
+            AckMode::Cancelled => write!(f, "cancelled"),
         }
     }
 }
@@ -64,6 +105,7 @@ impl From<AckMode> for AckStatus {
             AckMode::Ok => AckStatus::Ok,
             AckMode::Retry => AckStatus::Retry,
             AckMode::Failed => AckStatus::Failed,
+            AckMode::Cancelled => AckStatus::Cancelled,
         }
     }
 }
@@ -75,11 +117,42 @@ async fn main() -> anyhow::Result<()> {
     init_logging(&cli.log_level)?;
 
     info!(target: "promptivs", version = CLIENT_VERSION, "Starting sink client");
-    connect_and_run(cli).await
+
+    let base = Duration::from_millis(cli.reconnect_base_ms);
+    let max = Duration::from_millis(cli.reconnect_max_ms);
+    let mut delay = base;
+    loop {
+        let mut registered = false;
+        let result = connect_and_run(&cli, &mut registered).await;
+        if cli.no_reconnect {
+            return result.map(|_| ());
+        }
+
+        match result {
+            Ok(Disconnect::Superseded) => return Ok(()),
+            Ok(Disconnect::Closed) => info!("Connection closed"),
+            Err(err) => error!("Connection failed: {}", err),
+        }
+
+        // A connection that got as far as registering starts the backoff over
+        if registered {
+            delay = base;
+        }
+        info!("Reconnecting in {} ms", delay.as_millis());
+        sleep(delay).await;
+        delay = (delay * 2).min(max);
+    }
 }
 
-async fn connect_and_run(cli: Cli) -> anyhow::Result<()> {
-    let (ws_stream, _) = connect_async(cli.server.as_str()).await?;
+/// Runs one connection until it ends, setting `registered` once the daemon accepts the sink.
+async fn connect_and_run(cli: &Cli, registered: &mut bool) -> anyhow::Result<Disconnect> {
+    let mut request = cli.server.as_str().into_client_request()?;
+    if let Some(token) = &cli.auth_token {
+        request
+            .headers_mut()
+            .insert("authorization", format!("Bearer {}", token).parse()?);
+    }
+    let (ws_stream, _) = connect_async(request).await?;
     info!(server = %cli.server, "Connected");
 
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
@@ -89,6 +162,8 @@ async fn connect_and_run(cli: Cli) -> anyhow::Result<()> {
         version: CLIENT_VERSION.to_string(),
         capabilities: cli.capabilities.clone(),
         providers: cli.providers.clone(),
+        provider_capabilities: None,
+        binary_frames: cli.binary_frames,
     };
 
     ws_sender
@@ -96,92 +171,179 @@ async fn connect_and_run(cli: Cli) -> anyhow::Result<()> {
         .await?;
     info!("Sent REGISTER message");
 
-    while let Some(msg) = ws_receiver.next().await {
-        match msg {
-            Ok(Message::Text(text)) => match serde_json::from_str::<RelayMessage>(&text) {
-                Ok(RelayMessage::Ping { .. }) => {
-                    info!("Received PING");
-                    let pong = SinkMessage::Pong {
-                        schema_version: SCHEMA_VERSION.to_string(),
-                    };
-                    ws_sender
-                        .send(Message::Text(serde_json::to_string(&pong)?))
-                        .await?;
-                    info!("Sent PONG");
-                }
-                Ok(RelayMessage::Policy {
-                    supersede_on_register,
-                    max_job_bytes,
-                    ..
-                }) => {
-                    info!(
-                        "Received POLICY: supersede_on_register={}, max_job_bytes={}",
-                        supersede_on_register, max_job_bytes
-                    );
-                }
-                Ok(RelayMessage::InsertText { id, payload, .. }) => {
-                    info!(
-                        job_id = id,
-                        text = %payload.text,
-                        placement = ?payload.placement,
-                        source = ?payload.source,
-                        target = ?payload.target,
-                        metadata = ?payload.metadata,
-                        "Received insert_text"
-                    );
-
-                    if cli.ack_delay_ms > 0 {
-                        sleep(Duration::from_millis(cli.ack_delay_ms)).await;
-                    }
+    // Text until the daemon's policy confirms binary frames
+    let mut binary_frames = false;
 
-                    let status: AckStatus = cli.ack_mode.into();
-                    let error = match status {
-                        AckStatus::Ok => None,
-                        AckStatus::Retry => Some("Simulated retry".to_string()),
-                        AckStatus::Failed => Some("Simulated failure".to_string()),
-                    };
-                    let status_for_log = status.clone();
-                    let ack = SinkMessage::Ack {
-                        schema_version: SCHEMA_VERSION.to_string(),
-                        id,
-                        status,
-                        error,
-                    };
-
-                    ws_sender
-                        .send(Message::Text(serde_json::to_string(&ack)?))
-                        .await?;
-                    info!("Sent ACK with status {:?}", status_for_log);
-                }
-                Err(err) => {
-                    warn!("Failed to parse relay message: {}", err);
-                }
-            },
+    while let Some(msg) = ws_receiver.next().await {
+        let parsed = match msg {
+            Ok(Message::Text(text)) => {
+                serde_json::from_str::<RelayMessage>(&text).map_err(|e| e.to_string())
+            }
+            Ok(Message::Binary(bytes)) if binary_frames => {
+                decode_binary::<RelayMessage>(&bytes).map_err(|e| e.to_string())
+            }
+            Ok(Message::Binary(bytes)) => {
+                warn!(
+                    "Ignoring binary frame of {} bytes (binary frames not negotiated)",
+                    bytes.len()
+                );
+                continue;
+            }
             Ok(Message::Ping(payload)) => {
                 info!("Received websocket ping");
                 ws_sender.send(Message::Pong(payload)).await?;
+                continue;
             }
             Ok(Message::Close(frame)) => {
-                info!("WebSocket closed: {:?}", frame);
+                match &frame {
+                    Some(frame) => info!(
+                        code = u16::from(frame.code),
+                        reason = %frame.reason,
+                        "WebSocket closed by daemon"
+                    ),
+                    None => info!("WebSocket closed by daemon without a close code"),
+                }
                 let _ = ws_sender.send(Message::Close(frame)).await;
                 break;
             }
-            Ok(Message::Binary(bytes)) => {
-                warn!(
-                    "Ignoring binary frame of {} bytes (unsupported by protocol)",
-                    bytes.len()
-                );
+            Ok(other) => {
+                warn!("Ignoring unsupported frame: {:?}", other);
+                continue;
             }
-            Ok(other) => warn!("Ignoring unsupported frame: {:?}", other),
             Err(err) => {
                 error!("WebSocket error: {}", err);
                 break;
             }
+        };
+
+        match parsed {
+            Ok(RelayMessage::Ping { .. }) => {
+                info!("Received PING");
+                let pong = SinkMessage::Pong {
+                    schema_version: SCHEMA_VERSION.to_string(),
+                };
+                ws_sender.send(encode(&pong, binary_frames)?).await?;
+                info!("Sent PONG");
+            }
+            Ok(RelayMessage::Policy {
+                server_schema_version,
+                supersede_on_register,
+                max_job_bytes,
+                binary_frames: negotiated,
+                ..
+            }) => {
+                info!(
+                    "Received POLICY: server_schema_version={}, supersede_on_register={}, max_job_bytes={}, binary_frames={}",
+                    server_schema_version, supersede_on_register, max_job_bytes, negotiated
+                );
+                binary_frames = negotiated;
+                *registered = true;
+            }
+            Ok(RelayMessage::Superseded { reason, .. }) => {
+                info!("Superseded by another sink, exiting: {}", reason);
+                let _ = ws_sender.send(Message::Close(None)).await;
+                return Ok(Disconnect::Superseded);
+            }
+            Ok(RelayMessage::ReauthChallenge { nonce, .. }) => {
+                let Some(token) = &cli.auth_token else {
+                    warn!("Daemon asked to re-authenticate, but no --auth-token was given");
+                    continue;
+                };
+                let response = SinkMessage::ReauthResponse {
+                    schema_version: SCHEMA_VERSION.to_string(),
+                    nonce,
+                    token: token.clone(),
+                };
+                ws_sender.send(encode(&response, binary_frames)?).await?;
+                info!("Answered re-authentication challenge");
+            }
+            Ok(RelayMessage::Cancel { id, .. }) => {
+                // Jobs are acked before the next frame is read, so there is nothing to withdraw
+                info!(job_id = id, "Received cancel for an already acked job");
+            }
+            Ok(RelayMessage::InsertText { id, payload, .. }) => {
+                info!(
+                    job_id = id,
+                    text = %payload.text,
+                    placement = ?payload.placement,
+                    mode = %payload.mode,
+                    source = ?payload.source,
+                    target = ?payload.target,
+                    metadata = ?payload.metadata,
+                    new_session = payload.new_session,
+                    "Received insert_text"
+                );
+
+                // Echo probes exercise the wire path only: no delay, no simulated failure
+                let echo = payload.is_echo().then(|| payload.echo_summary());
+                if echo.is_none() && cli.ack_delay_ms > 0 {
+                    // Spread the delay over simulated chunks, reporting progress after each
+                    let total_chars = payload.text.chars().count();
+                    let step = Duration::from_millis(cli.ack_delay_ms) / PROGRESS_STEPS;
+                    for i in 1..=PROGRESS_STEPS {
+                        sleep(step).await;
+                        let inserted_chars = total_chars * i as usize / PROGRESS_STEPS as usize;
+                        let progress = SinkMessage::Progress {
+                            schema_version: SCHEMA_VERSION.to_string(),
+                            id: id.clone(),
+                            inserted_chars,
+                            total_chars,
+                        };
+                        ws_sender.send(encode(&progress, binary_frames)?).await?;
+                        info!(job_id = id, inserted_chars, total_chars, "Sent PROGRESS");
+                    }
+                }
+
+                // Stale jobs are dropped rather than inserted late
+                let expired = echo.is_none() && payload.is_expired_at(chrono::Utc::now());
+                let status: AckStatus = match echo {
+                    Some(_) => AckStatus::Ok,
+                    None if expired => AckStatus::Failed,
+                    None => cli.ack_mode.into(),
+                };
+                let warnings = match status {
+                    AckStatus::Ok => cli.ack_warnings.clone(),
+                    _ => Vec::new(),
+                };
+                let error = match status {
+                    AckStatus::Ok => None,
+                    AckStatus::Retry => Some("Simulated retry".to_string()),
+                    AckStatus::Failed if expired => Some(EXPIRED_ACK_ERROR.to_string()),
+                    AckStatus::Failed => Some("Simulated failure".to_string()),
+                    AckStatus::Cancelled => Some("Simulated cancellation".to_string()),
+                };
+                // An inserted job leaves its conversation open for later `reuse_only` jobs
+                let session_active = (echo.is_none() && status == AckStatus::Ok).then_some(true);
+                let status_for_log = status.clone();
+                let ack = SinkMessage::Ack {
+                    schema_version: SCHEMA_VERSION.to_string(),
+                    id,
+                    status,
+                    error,
+                    echo,
+                    warnings,
+                    session_active,
+                };
+
+                ws_sender.send(encode(&ack, binary_frames)?).await?;
+                info!("Sent ACK with status {:?}", status_for_log);
+            }
+            Err(err) => {
+                warn!("Failed to parse relay message: {}", err);
+            }
         }
     }
 
     info!("Sink loop terminated");
-    Ok(())
+    Ok(Disconnect::Closed)
+}
+
+fn encode(message: &SinkMessage, binary: bool) -> anyhow::Result<Message> {
+    Ok(if binary {
+        Message::Binary(encode_binary(message)?)
+    } else {
+        Message::Text(serde_json::to_string(message)?)
+    })
 }
 
 fn init_logging(level: &str) -> anyhow::Result<()> {
@@ -0,0 +1,187 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use reqwest::{redirect, Client, Url};
+use tracing::{info, warn};
+
+use crate::error::{AppError, AppResult};
+
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Parses a per-request callback URL, accepting only http(s) URLs whose host appears in
+/// `allowed_hosts`. An empty allowlist disables callbacks entirely.
+pub fn validate_callback_url(url: &str, allowed_hosts: &[String]) -> AppResult<Url> {
+    let parsed = Url::parse(url).map_err(|e| AppError::InvalidRequest {
+        reason: format!("Invalid callback_url: {}", e),
+    })?;
+
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(AppError::InvalidRequest {
+            reason: format!("Unsupported callback_url scheme: {}", parsed.scheme()),
+        });
+    }
+
+    let host = parsed.host_str().unwrap_or_default();
+    if !allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Err(AppError::InvalidRequest {
+            reason: format!("callback_url host is not allowed: {}", host),
+        });
+    }
+
+    Ok(parsed)
+}
+
+/// Client for delivering callbacks. Redirects are not followed, since an allowed host could
+/// otherwise send the callback on to a host outside `allowed_callback_hosts`.
+pub fn callback_client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .redirect(redirect::Policy::none())
+            .build()
+            .expect("callback client uses no TLS or proxy settings that can fail")
+    })
+}
+
+/// POSTs a job outcome to `url`, retrying with exponential backoff on transport errors and
+/// non-success responses.
+pub async fn deliver(client: &Client, url: Url, outcome: &serde_json::Value) -> AppResult<()> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut last_error = String::new();
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        match client.post(url.clone()).json(outcome).send().await {
+            Ok(response) if response.status().is_success() => {
+                info!(url = %url, attempt, "Delivered job callback");
+                return Ok(());
+            }
+            Ok(response) => last_error = format!("HTTP {}", response.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+
+        if attempt < MAX_ATTEMPTS {
+            warn!(url = %url, attempt, error = %last_error, "Job callback failed, retrying");
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    Err(AppError::CallbackFailed {
+        url: url.to_string(),
+        reason: last_error,
+    })
+}
+
+/// Delivers a job outcome in the background so the HTTP response is not held up by the callback.
+pub fn spawn_delivery(url: Url, outcome: serde_json::Value) {
+    tokio::spawn(async move {
+        if let Err(e) = deliver(callback_client(), url, &outcome).await {
+            warn!("{}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use axum::response::Redirect;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_validate_callback_url() {
+        let allowed = vec!["localhost".to_string()];
+
+        assert!(validate_callback_url("http://localhost:9000/hook", &allowed).is_ok());
+        assert!(validate_callback_url("https://LOCALHOST/hook", &allowed).is_ok());
+        assert!(matches!(
+            validate_callback_url("http://169.254.169.254/latest", &allowed),
+            Err(AppError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            validate_callback_url("file:///etc/passwd", &allowed),
+            Err(AppError::InvalidRequest { .. })
+        ));
+        assert!(validate_callback_url("http://localhost/hook", &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries_until_success() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        async fn hook(
+            State((tx, attempts)): State<(
+                mpsc::UnboundedSender<serde_json::Value>,
+                Arc<AtomicU32>,
+            )>,
+            Json(body): Json<serde_json::Value>,
+        ) -> StatusCode {
+            // Fail the first attempt to exercise the retry path
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            tx.send(body).unwrap();
+            StatusCode::OK
+        }
+
+        let app = Router::new()
+            .route("/hook", post(hook))
+            .with_state((tx, Arc::clone(&attempts)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let url = Url::parse(&format!("http://{}/hook", addr)).unwrap();
+        let outcome = serde_json::json!({"job_id": "job-1", "status": "ok"});
+
+        deliver(&Client::new(), url, &outcome).await.unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), outcome);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_does_not_follow_redirects() {
+        let followed = Arc::new(AtomicU32::new(0));
+        let internal = Router::new()
+            .route(
+                "/latest",
+                post(|State(followed): State<Arc<AtomicU32>>| async move {
+                    followed.fetch_add(1, Ordering::SeqCst);
+                    StatusCode::OK
+                }),
+            )
+            .with_state(Arc::clone(&followed));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let internal_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, internal).await.unwrap() });
+
+        // An allowed host that sends the callback on to one that is not
+        let target = format!("http://{}/latest", internal_addr);
+        let app = Router::new().route(
+            "/hook",
+            post(move || async move { Redirect::temporary(&target) }),
+        );
+        let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let allowed = vec!["localhost".to_string()];
+        let url =
+            validate_callback_url(&format!("http://localhost:{}/hook", port), &allowed).unwrap();
+        let outcome = serde_json::json!({"job_id": "job-1", "status": "ok"});
+
+        let result = deliver(callback_client(), url, &outcome).await;
+
+        assert!(matches!(result, Err(AppError::CallbackFailed { .. })));
+        assert_eq!(followed.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::client::conn::http1::SendRequest;
+use hyper_legacy::client::connect::HttpInfo;
+use reqwest::{Client, StatusCode};
+use thiserror::Error;
+
+use crate::models::InsertTextRequest;
+
+/// Scheme for reaching a daemon on a Unix socket, followed by the percent-encoded socket path,
+/// e.g. `http+unix://%2Frun%2Fpromptivd.sock`.
+pub const UNIX_SCHEME: &str = "http+unix://";
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Unix socket error: {0}")]
+    Unix(#[from] hyper::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid request: {0}")]
+    Request(#[from] hyper::http::Error),
+
+    #[error("HTTP/2 prior knowledge is not supported over Unix sockets")]
+    Http2OverUnix,
+}
+
+/// HTTP client for submitting insert jobs to a running daemon, over TCP or a Unix socket.
+/// Connections are kept alive between inserts, and clones share them.
+#[derive(Debug, Clone)]
+pub struct InsertClient {
+    http: Client,
+    server: String,
+    /// Local addresses of the TCP connections responses arrived on, to tell reused ones apart.
+    connections: Arc<Mutex<HashSet<SocketAddr>>>,
+    /// Connection to an `http+unix://` server, kept for the next insert.
+    unix: Arc<tokio::sync::Mutex<Option<SendRequest<Full<Bytes>>>>>,
+}
+
+/// Status and JSON body returned by `POST /v1/insert`.
+#[derive(Debug, Clone)]
+pub struct InsertResponse {
+    pub status: StatusCode,
+    pub body: serde_json::Value,
+    /// Whether the request went over a connection an earlier insert opened; unset when the
+    /// HTTP client does not say which connection it used.
+    pub reused_connection: Option<bool>,
+}
+
+impl InsertClient {
+    pub fn new(server: impl Into<String>) -> Self {
+        Self::with_client(Client::new(), server)
+    }
+
+    pub fn with_client(http: Client, server: impl Into<String>) -> Self {
+        Self {
+            http,
+            server: server.into().trim_end_matches('/').to_string(),
+            connections: Arc::default(),
+            unix: Arc::default(),
+        }
+    }
+
+    /// A client that speaks HTTP/2 to the daemon from the first request rather than
+    /// negotiating it, so every insert shares one multiplexed connection. Only for `http://`
+    /// servers; Unix socket connections always use HTTP/1.1.
+    pub fn http2_prior_knowledge(server: impl Into<String>) -> Result<Self, ClientError> {
+        let http = Client::builder().http2_prior_knowledge().build()?;
+        let client = Self::with_client(http, server);
+        if client.unix_socket().is_some() {
+            return Err(ClientError::Http2OverUnix);
+        }
+        Ok(client)
+    }
+
+    pub fn insert_url(&self) -> String {
+        format!("{}/v1/insert", self.server)
+    }
+
+    /// Socket path of an `http+unix://` server, or `None` for a TCP one.
+    pub fn unix_socket(&self) -> Option<PathBuf> {
+        let encoded = self.server.strip_prefix(UNIX_SCHEME)?;
+        let decoded = percent_encoding::percent_decode_str(encoded).decode_utf8_lossy();
+        Some(PathBuf::from(decoded.into_owned()))
+    }
+
+    pub async fn insert(&self, request: &InsertTextRequest) -> Result<InsertResponse, ClientError> {
+        if let Some(socket) = self.unix_socket() {
+            return self.insert_over_unix(&socket, request).await;
+        }
+
+        let response = self
+            .http
+            .post(self.insert_url())
+            .json(request)
+            .send()
+            .await?;
+        let reused_connection = response
+            .extensions()
+            .get::<HttpInfo>()
+            .map(|info| !self.connections.lock().unwrap().insert(info.local_addr()));
+        let status = response.status();
+        let body = response.json().await?;
+
+        Ok(InsertResponse {
+            status,
+            body,
+            reused_connection,
+        })
+    }
+
+    /// Sends `request` on the connection kept from the previous insert, or on a new one if
+    /// there is none or the daemon closed it. Inserts on one client take turns on it.
+    async fn insert_over_unix(
+        &self,
+        socket: &std::path::Path,
+        request: &InsertTextRequest,
+    ) -> Result<InsertResponse, ClientError> {
+        let mut kept = self.unix.lock().await;
+        let reused = match kept.as_mut() {
+            Some(sender) => sender.ready().await.is_ok(),
+            None => false,
+        };
+        if !reused {
+            let stream = tokio::net::UnixStream::connect(socket).await?;
+            let (sender, connection) =
+                hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+            tokio::spawn(connection);
+            *kept = Some(sender);
+        }
+        let sender = kept.as_mut().expect("connection was just opened");
+
+        let response = send_over_unix(sender, request).await;
+        if response.is_err() {
+            *kept = None;
+        }
+        response.map(|response| InsertResponse {
+            reused_connection: Some(reused),
+            ..response
+        })
+    }
+}
+
+async fn send_over_unix(
+    sender: &mut SendRequest<Full<Bytes>>,
+    request: &InsertTextRequest,
+) -> Result<InsertResponse, ClientError> {
+    let http_request = hyper::Request::post("/v1/insert")
+        .header(hyper::header::HOST, "localhost")
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(serde_json::to_vec(request)?)))?;
+    let response = sender.send_request(http_request).await?;
+
+    let status = StatusCode::from_u16(response.status().as_u16())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let bytes = response.into_body().collect().await?.to_bytes();
+    let body = serde_json::from_slice(&bytes)?;
+
+    Ok(InsertResponse {
+        status,
+        body,
+        reused_connection: None,
+    })
+}
+
+impl InsertResponse {
+    pub fn job_id(&self) -> &str {
+        self.body
+            .get("job_id")
+            .and_then(|v| v.as_str())
+            .unwrap_or("<unknown>")
+    }
+
+    /// Warnings reported alongside the outcome, by the daemon or the sink.
+    pub fn warnings(&self) -> Vec<&str> {
+        self.body
+            .get("warnings")
+            .and_then(|v| v.as_array())
+            .map(|warnings| warnings.iter().filter_map(|w| w.as_str()).collect())
+            .unwrap_or_default()
+    }
+}
@@ -1,56 +1,498 @@
+use arc_swap::ArcSwap;
+use axum::http::HeaderValue;
 use config::Source;
+use regex::RegexSet;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{net::SocketAddr, path::Path};
 
 pub use config::ConfigError;
-use config::{Config, Environment, File};
+use config::{Config, Environment, File, FileFormat, ValueKind};
 use serde::{Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+use crate::models::Placement;
+
+/// An address the daemon listens on: a TCP socket address such as `127.0.0.1:8787`, or a Unix
+/// domain socket written as `unix:/run/promptivd.sock`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SerializeDisplay, DeserializeFromStr)]
+pub enum ListenSpec {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenSpec::Tcp(addr) => write!(f, "{}", addr),
+            ListenSpec::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for ListenSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("unix: listen address needs a socket path".to_string()),
+            Some(path) => Ok(ListenSpec::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(ListenSpec::Tcp)
+                .map_err(|e| format!("Invalid listen address '{}': {}", s, e)),
+        }
+    }
+}
+
+/// Accepts a single listen address as well as a list, so configs written for the old
+/// single-address `bind_addr` keep loading.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<ListenSpec>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(ListenSpec),
+        Many(Vec<ListenSpec>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(spec) => vec![spec],
+        OneOrMany::Many(specs) => specs,
+    })
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct ServerConfig {
-    pub bind_addr: SocketAddr,
+    /// Addresses served with the same routes and state, e.g. a TCP address plus a Unix socket
+    /// for local clients.
+    #[serde(alias = "bind_addr", deserialize_with = "one_or_many")]
+    pub bind_addrs: Vec<ListenSpec>,
     pub require_sink: bool,
+    /// Queue jobs on disk while no sink is connected and replay them once one registers.
+    pub persist_queue: bool,
+    /// Queue file; defaults to `promptivd/queue.jsonl` under the user data directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_path: Option<PathBuf>,
+    /// Once the queue holds this many jobs, the oldest are dropped.
+    pub max_queued_jobs: usize,
+    /// Banner returned with inserts while maintenance mode is on, unless `POST /v1/maintenance`
+    /// gives its own.
+    pub maintenance_banner: String,
     pub supersede_on_register: bool,
+    /// Whether sinks may switch to MessagePack binary frames when they ask for them.
+    pub allow_binary_frames: bool,
     pub max_job_bytes: usize,
+    /// Limit on the serialized size of a job's `metadata`, on top of `max_job_bytes`.
+    pub max_metadata_bytes: usize,
+    /// Jobs accepted in one `POST /v1/insert/batch` request, each still held to
+    /// `max_job_bytes`.
+    pub max_batch_size: usize,
+    pub max_concurrent_requests: usize,
+    pub max_inflight_bytes: usize,
+    /// Jobs a single sink may have awaiting an ack; further jobs for it are rejected with 503.
+    /// 0 removes the limit.
+    pub max_inflight_per_sink: usize,
     #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
     pub websocket_ping_interval: Duration,
     #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
     pub websocket_pong_timeout: Duration,
     pub websocket_max_missed_pings: u32,
+    /// Sends heartbeats as WebSocket control pings instead of JSON `ping` messages.
+    pub use_control_ping: bool,
+    /// How often a registered sink must prove it still holds `auth_token` by answering a
+    /// `reauth_challenge`; zero turns re-authentication off.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub sink_reauth_interval: Duration,
+    /// Disconnects a sink that acks more than this many unknown or already resolved jobs within
+    /// `orphan_ack_window`; 0 only logs and counts them.
+    pub max_orphan_acks: u32,
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub orphan_ack_window: Duration,
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub websocket_close_grace: Duration,
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub registration_timeout: Duration,
     #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
     pub dispatch_timeout: Duration,
+    /// Upper bound on the per-job `timeout_ms` a client may request.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub max_dispatch_timeout: Duration,
+    /// Times a job the sink acks `retry` is dispatched again before the ack is returned to the
+    /// client; 0 returns it at once.
+    pub dispatch_max_retries: u32,
+    /// Wait before the first redispatch, doubled for each one after it.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub dispatch_retry_backoff: Duration,
+    /// How long `POST /v1/insert?wait=false` waits for the job to be queued for a sink before
+    /// giving up on it with 503.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub send_timeout: Duration,
+    /// How long the old listener keeps serving in-flight requests after a rebind.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub rebind_drain_grace: Duration,
+    /// How long a drain waits for dispatched jobs to be acked before shutdown proceeds.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub drain_timeout: Duration,
+    /// How long finished job records stay queryable via `GET /v1/jobs/:id`.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub job_retention: Duration,
+    /// SQLite database keeping every finished job for `GET /v1/jobs`. Needs the `sqlite`
+    /// feature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sqlite_path: Option<PathBuf>,
+    /// Rolling window of jobs aggregated by `GET /v1/stats`; at most `job_retention`.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub stats_window: Duration,
+    pub allowed_callback_hosts: Vec<String>,
+    /// Browser origins allowed to call the API, e.g. an extension's dev server.
+    pub cors_allowed_origins: Vec<String>,
+    /// Allows every origin, for trusted local setups; `cors_allowed_origins` is then ignored.
+    pub cors_allow_any: bool,
+    pub cors_max_age_secs: u64,
+    /// Browser origins allowed to open the sink WebSocket. Unset follows `cors_allowed_origins`
+    /// and `cors_allow_any`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ws_allowed_origins: Option<Vec<String>>,
+    /// Accepts sink WebSocket upgrades from every origin, for local development.
+    pub ws_allow_any_origin: bool,
+    pub text_denylist_patterns: Vec<String>,
+    pub lenient_validation: bool,
+    /// Rejects insert requests containing fields the schema does not define.
+    pub reject_unknown_request_fields: bool,
+    /// HTTP status returned when the sink acks a job as `failed`.
+    pub failed_ack_status: u16,
+    /// `Retry-After` seconds sent with the 503 returned when the sink acks `retry`.
+    pub ack_retry_after_secs: u64,
+    /// Inserts each client may make per second on average; 0 disables rate limiting.
+    pub rate_limit_per_sec: u32,
+    /// Inserts a client may make in a burst before `rate_limit_per_sec` applies; 0 disables
+    /// rate limiting.
+    pub rate_limit_burst: u32,
+    /// How long an `Idempotency-Key` replays the response of the insert that first used it; 0
+    /// disables deduplication.
+    #[serde(with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub idempotency_ttl: Duration,
+    /// Idempotency keys remembered at once; the oldest are forgotten first.
+    pub max_idempotency_keys: usize,
+    /// Metadata merged into every job under the caller's own keys.
+    pub default_metadata: serde_json::Map<String, serde_json::Value>,
+    /// Per-provider metadata objects, taking precedence over `default_metadata`.
+    pub provider_default_metadata: HashMap<String, serde_json::Value>,
+    /// Placement to fall back to, keyed by capability, when the sink lacks a job's
+    /// `required_capability`. Capabilities without an entry are rejected.
+    pub capability_downgrades: HashMap<String, Placement>,
+    /// String transforms applied to every job's text before dispatch.
+    pub text_transforms: TextTransforms,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    /// Bearer token required by `/v1/insert` and `/v1/sink/ws`. Unset leaves them open.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+    /// PEM certificate chain. Set together with `tls_key_path` to serve HTTPS and `wss://`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert_path: Option<PathBuf>,
+    /// PEM private key matching `tls_cert_path`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key_path: Option<PathBuf>,
+}
+
+/// Server-side text transforms, applied in a fixed order: `wrap_code_fence`, then `prepend`,
+/// then `append`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextTransforms {
+    /// Wraps the text in a Markdown code fence tagged with the job's content type language.
+    pub wrap_code_fence: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prepend: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub append: Option<String>,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
-            bind_addr: "127.0.0.1:8787".parse().unwrap(),
+            bind_addrs: vec![ListenSpec::Tcp("127.0.0.1:8787".parse().unwrap())],
             require_sink: false,
+            allow_binary_frames: true,
+            persist_queue: false,
+            queue_path: None,
+            max_queued_jobs: 1000,
+            maintenance_banner:
+                "Maintenance in progress; jobs are queued and delivered once it ends".to_string(),
             supersede_on_register: true,
-            max_job_bytes: 128 * 1024, // 128 KiB
+            max_job_bytes: 128 * 1024,     // 128 KiB
+            max_metadata_bytes: 16 * 1024, // 16 KiB
+            max_batch_size: 100,
+            max_concurrent_requests: 64,
+            max_inflight_bytes: 8 * 1024 * 1024, // 8 MiB
+            max_inflight_per_sink: 0,
             websocket_ping_interval: Duration::from_secs(15),
             websocket_pong_timeout: Duration::from_secs(10),
             websocket_max_missed_pings: 3,
+            use_control_ping: false,
+            sink_reauth_interval: Duration::ZERO,
+            max_orphan_acks: 0,
+            orphan_ack_window: Duration::from_secs(60),
+            websocket_close_grace: Duration::from_secs(2),
+            registration_timeout: Duration::from_secs(5),
             dispatch_timeout: Duration::from_secs(30),
+            max_dispatch_timeout: Duration::from_secs(300),
+            dispatch_max_retries: 0,
+            dispatch_retry_backoff: Duration::from_secs(1),
+            send_timeout: Duration::from_secs(2),
+            rebind_drain_grace: Duration::from_secs(30),
+            drain_timeout: Duration::from_secs(30),
+            job_retention: Duration::from_secs(600),
+            sqlite_path: None,
+            stats_window: Duration::from_secs(600),
+            allowed_callback_hosts: Vec::new(),
+            cors_allowed_origins: vec![
+                "http://localhost:3000".to_string(),
+                "http://127.0.0.1:3000".to_string(),
+            ],
+            cors_allow_any: false,
+            cors_max_age_secs: 86400,
+            ws_allowed_origins: None,
+            ws_allow_any_origin: false,
+            text_denylist_patterns: Vec::new(),
+            lenient_validation: false,
+            reject_unknown_request_fields: false,
+            failed_ack_status: 502,
+            ack_retry_after_secs: 1,
+            rate_limit_per_sec: 0,
+            rate_limit_burst: 0,
+            idempotency_ttl: Duration::from_secs(600),
+            max_idempotency_keys: 10_000,
+            default_metadata: serde_json::Map::new(),
+            provider_default_metadata: HashMap::new(),
+            capability_downgrades: HashMap::new(),
+            text_transforms: TextTransforms::default(),
+            admin_token: None,
+            auth_token: None,
+            tls_cert_path: None,
+            tls_key_path: None,
+        }
+    }
+}
+
+/// Parses the `key` setting's `origins` into header values. Each entry must be an origin as
+/// browsers send it: a scheme, host and optional port, without a path or trailing slash.
+fn parse_origins(key: &str, origins: &[String]) -> Result<Vec<HeaderValue>, ConfigError> {
+    origins
+        .iter()
+        .map(|origin| {
+            let invalid = |reason: String| {
+                ConfigError::Message(format!("Invalid {} entry {:?}: {}", key, origin, reason))
+            };
+            let url = reqwest::Url::parse(origin).map_err(|e| invalid(e.to_string()))?;
+            // Checked by hand since extension schemes have no origin as far as `Url` knows
+            let bare = url.has_host()
+                && url.username().is_empty()
+                && matches!(url.path(), "" | "/")
+                && url.query().is_none()
+                && url.fragment().is_none()
+                && !origin.ends_with('/');
+            if !bare {
+                return Err(invalid(
+                    "expected an origin such as http://localhost:3000".to_string(),
+                ));
+            }
+            HeaderValue::from_str(origin).map_err(|e| invalid(e.to_string()))
+        })
+        .collect()
+}
+
+impl ServerConfig {
+    /// Compiles `text_denylist_patterns` into a single matcher.
+    pub fn compile_text_denylist(&self) -> Result<RegexSet, ConfigError> {
+        RegexSet::new(&self.text_denylist_patterns).map_err(|e| {
+            ConfigError::Message(format!("Invalid text_denylist_patterns entry: {}", e))
+        })
+    }
+
+    /// Parses `cors_allowed_origins` into header values.
+    pub fn cors_origins(&self) -> Result<Vec<HeaderValue>, ConfigError> {
+        parse_origins("cors_allowed_origins", &self.cors_allowed_origins)
+    }
+
+    /// Parses `ws_allowed_origins` like `cors_origins`, or `None` when it is unset.
+    pub fn ws_origins(&self) -> Result<Option<Vec<HeaderValue>>, ConfigError> {
+        self.ws_allowed_origins
+            .as_deref()
+            .map(|origins| parse_origins("ws_allowed_origins", origins))
+            .transpose()
+    }
+
+    /// Whether a browser page at `origin` may open the sink WebSocket.
+    pub fn ws_origin_allowed(&self, origin: &HeaderValue) -> bool {
+        let listed = |origins: &[String]| {
+            origins
+                .iter()
+                .any(|allowed| allowed.as_bytes().eq_ignore_ascii_case(origin.as_bytes()))
+        };
+        match &self.ws_allowed_origins {
+            _ if self.ws_allow_any_origin => true,
+            Some(origins) => listed(origins),
+            None => self.cors_allow_any || listed(&self.cors_allowed_origins),
         }
     }
+
+    /// The certificate and key paths when TLS is configured. Errors if only one is set, or if
+    /// either file cannot be read or holds no PEM certificate or key.
+    pub fn tls_paths(&self) -> Result<Option<(&Path, &Path)>, ConfigError> {
+        let (cert_path, key_path) = match (&self.tls_cert_path, &self.tls_key_path) {
+            (None, None) => return Ok(None),
+            (Some(cert), Some(key)) => (cert.as_path(), key.as_path()),
+            _ => {
+                return Err(ConfigError::Message(
+                    "tls_cert_path and tls_key_path must be set together".to_string(),
+                ))
+            }
+        };
+
+        let open = |path: &Path| {
+            std::fs::File::open(path)
+                .map(std::io::BufReader::new)
+                .map_err(|e| ConfigError::Message(format!("Cannot read {}: {}", path.display(), e)))
+        };
+        let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_default();
+        if certs.is_empty() {
+            return Err(ConfigError::Message(format!(
+                "No PEM certificate found in tls_cert_path {}",
+                cert_path.display()
+            )));
+        }
+        if !matches!(
+            rustls_pemfile::private_key(&mut open(key_path)?),
+            Ok(Some(_))
+        ) {
+            return Err(ConfigError::Message(format!(
+                "No PEM private key found in tls_key_path {}",
+                key_path.display()
+            )));
+        }
+
+        Ok(Some((cert_path, key_path)))
+    }
+
+    /// `queue_path`, or the default location under the user data directory.
+    pub fn resolved_queue_path(&self) -> Option<PathBuf> {
+        self.queue_path
+            .clone()
+            .or_else(|| dirs::data_dir().map(|d| d.join("promptivd/queue.jsonl")))
+    }
 }
 
+/// Server settings of the running daemon, replaced as a whole when the configuration is
+/// reloaded so readers never take a lock.
+pub type SharedConfig = Arc<ArcSwap<ServerConfig>>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub log_level: String,
     pub log_format: LogFormat,
+    /// Unix datagram socket that additionally receives every log record as a JSON line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_socket_path: Option<PathBuf>,
 }
 
+/// Prefix for environment overrides. Nested fields are addressed with a double underscore,
+/// e.g. `PROMPTIVD_SERVER__BIND_ADDRS` for `server.bind_addrs`, and top-level fields directly,
+/// e.g. `PROMPTIVD_LOG_LEVEL`. Variables that name no config field are ignored.
+pub const ENV_PREFIX: &str = "PROMPTIVD";
+/// Placeholder written in place of secrets by [`AppConfig::redacted`].
+pub const REDACTED: &str = "<redacted>";
+const ENV_SEPARATOR: &str = "__";
+/// Upper bound on `dispatch_max_retries`; every attempt shares the job's dispatch timeout, so
+/// more would only be cut short.
+const MAX_DISPATCH_RETRIES: u32 = 10;
+
+/// Flat aliases kept for compatibility with environments set up before nested overrides.
 #[derive(Debug, Default, Deserialize)]
 #[serde(default)]
 pub struct EnvConfig {
+    /// Alias for `PROMPTIVD_SERVER__BIND_ADDRS` with a single TCP address.
     pub server_bind_addr: Option<SocketAddr>,
-    pub log_level: Option<String>,
-    pub log_format: Option<LogFormat>,
+}
+
+/// Config source that files a legacy `server.bind_addr` key under `server.bind_addrs`. Left as
+/// is, it would sit next to the default `bind_addrs` once the sources are layered, and the two
+/// would clash as the same field.
+#[derive(Debug, Clone)]
+struct LegacyBindAddr<S>(S);
+
+impl<S: Source + Clone + Send + Sync + 'static> Source for LegacyBindAddr<S> {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<config::Map<String, config::Value>, ConfigError> {
+        fn rename(
+            table: &mut config::Map<String, config::Value>,
+            from: &str,
+            to: &str,
+        ) -> Result<(), ConfigError> {
+            let Some(addr) = table.remove(from) else {
+                return Ok(());
+            };
+            if table.contains_key(to) {
+                return Err(ConfigError::Message(
+                    "Set either server.bind_addr or server.bind_addrs, not both".to_string(),
+                ));
+            }
+            table.insert(to.to_string(), addr);
+            Ok(())
+        }
+
+        let mut table = self.0.collect()?;
+        // Files nest the key under a `server` table; the environment uses a dotted key
+        rename(&mut table, "server.bind_addr", "server.bind_addrs")?;
+        if let Some(ValueKind::Table(server)) = table.get_mut("server").map(|v| &mut v.kind) {
+            rename(server, "bind_addr", "bind_addrs")?;
+        }
+        Ok(table)
+    }
+}
+
+/// Where an effective config value was last set, in increasing order of precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Env,
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "file {}", path.display()),
+            ConfigSource::Env => write!(f, "env"),
+            ConfigSource::Cli => write!(f, "cli"),
+        }
+    }
+}
+
+/// An effective config value together with the source that provided it.
+#[derive(Debug, Clone)]
+pub struct ExplainedValue {
+    /// Dotted path of the field, e.g. `server.bind_addrs`.
+    pub key: String,
+    pub value: serde_json::Value,
+    pub source: ConfigSource,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,12 +502,85 @@ pub enum LogFormat {
     Pretty,
 }
 
+/// On-disk config formats, chosen by file extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[default]
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub const ALL: [ConfigFormat; 3] = [ConfigFormat::Yaml, ConfigFormat::Toml, ConfigFormat::Json];
+
+    /// Format named by `path`'s extension, if it is one of the supported ones.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConfigFormat::Yaml => "yaml",
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Json => "json",
+        }
+    }
+
+    fn file_format(self) -> FileFormat {
+        match self {
+            ConfigFormat::Yaml => FileFormat::Yaml,
+            ConfigFormat::Toml => FileFormat::Toml,
+            ConfigFormat::Json => FileFormat::Json,
+        }
+    }
+
+    fn serialize(self, config: &AppConfig) -> Result<String, std::io::Error> {
+        let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        match self {
+            ConfigFormat::Yaml => serde_yaml::to_string(config).map_err(|e| invalid(e.to_string())),
+            ConfigFormat::Toml => {
+                toml::to_string_pretty(config).map_err(|e| invalid(e.to_string()))
+            }
+            ConfigFormat::Json => {
+                serde_json::to_string_pretty(config).map_err(|e| invalid(e.to_string()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_path(Path::new(&format!("config.{}", s))).ok_or_else(|| {
+            format!(
+                "unsupported config format '{}', expected yaml, toml or json",
+                s
+            )
+        })
+    }
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             server: ServerConfig::default(),
             log_level: "info".to_string(),
             log_format: LogFormat::Pretty,
+            log_socket_path: None,
         }
     }
 }
@@ -74,28 +589,211 @@ impl AppConfig {
     pub fn from_file<P: AsRef<std::path::Path>>(
         config_path: Option<P>,
     ) -> Result<Self, ConfigError> {
-        let mut sources: Vec<File<_, _>> = Vec::new();
+        let sources = Self::config_files(config_path)
+            .into_iter()
+            .map(|(path, required)| Self::file_source(path, required))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        if config_path.is_none() {
-            if let Some(pb) = Self::get_default_config_path() {
-                sources.push(File::from(pb).required(false));
+        Self::from_sources(sources)
+    }
+
+    /// Config files consulted by [`AppConfig::from_file`], in load order, with whether each
+    /// must exist. Without an explicit path, every supported format is looked for in the user
+    /// config directory and then the working directory.
+    fn config_files<P: AsRef<Path>>(config_path: Option<P>) -> Vec<(PathBuf, bool)> {
+        match config_path {
+            Some(p) => vec![(p.as_ref().to_path_buf(), true)],
+            None => ConfigFormat::ALL
+                .iter()
+                .filter_map(|&format| Self::get_default_config_path(format))
+                .chain(
+                    ConfigFormat::ALL
+                        .iter()
+                        .map(|format| PathBuf::from(format!("promptivd.{}", format))),
+                )
+                .map(|path| (path, false))
+                .collect(),
+        }
+    }
+
+    /// Source for the config file at `path`, parsed in the format its extension names.
+    fn file_source(
+        path: PathBuf,
+        required: bool,
+    ) -> Result<LegacyBindAddr<File<config::FileSourceFile, FileFormat>>, ConfigError> {
+        let format = ConfigFormat::from_path(&path).ok_or_else(|| {
+            ConfigError::Message(format!(
+                "Unsupported config file extension: {} (expected .yaml, .yml, .toml or .json)",
+                path.display()
+            ))
+        })?;
+        Ok(LegacyBindAddr(
+            File::from(path)
+                .format(format.file_format())
+                .required(required),
+        ))
+    }
+
+    /// Maps the dotted path of every key set by the defaults, config files and environment to
+    /// the last of those sources that set it, mirroring the precedence of
+    /// [`AppConfig::from_file`]. CLI overrides are applied by the caller.
+    pub fn provenance<P: AsRef<Path>>(
+        config_path: Option<P>,
+    ) -> Result<HashMap<String, ConfigSource>, ConfigError> {
+        fn record(
+            provenance: &mut HashMap<String, ConfigSource>,
+            prefix: &str,
+            table: &config::Map<String, config::Value>,
+            source: &ConfigSource,
+        ) {
+            for (key, value) in table {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                match &value.kind {
+                    ValueKind::Table(nested) if !nested.is_empty() => {
+                        record(provenance, &path, nested, source)
+                    }
+                    _ => {
+                        provenance.insert(path, source.clone());
+                    }
+                }
             }
-            sources.push(File::from(Path::new("promptivd.yaml")).required(false));
         }
 
-        if let Some(p) = config_path {
-            sources.push(File::from(p.as_ref()).required(true));
+        let mut provenance = HashMap::new();
+        let defaults = Config::try_from(&AppConfig::default())?.collect()?;
+        record(&mut provenance, "", &defaults, &ConfigSource::Default);
+
+        for (path, required) in Self::config_files(config_path) {
+            let table = Self::file_source(path.clone(), required)?.collect()?;
+            record(&mut provenance, "", &table, &ConfigSource::File(path));
         }
 
-        Self::from_sources(sources)
+        let env = Self::env_source().collect()?;
+        record(&mut provenance, "", &env, &ConfigSource::Env);
+        if Environment::with_prefix(ENV_PREFIX)
+            .collect()?
+            .contains_key("server_bind_addr")
+        {
+            provenance.insert("server.bind_addrs".to_string(), ConfigSource::Env);
+        }
+
+        Ok(provenance)
     }
 
-    pub fn get_default_config_path() -> Option<PathBuf> {
-        dirs::config_dir().map(|d| d.join("promptivd/config.yaml"))
+    /// Lists every effective value with its source, sorted by key. Secrets are redacted.
+    pub fn explain(&self, provenance: &HashMap<String, ConfigSource>) -> Vec<ExplainedValue> {
+        fn flatten(
+            prefix: String,
+            value: serde_json::Value,
+            out: &mut Vec<(String, serde_json::Value)>,
+        ) {
+            match value {
+                serde_json::Value::Object(map) if !map.is_empty() => {
+                    for (key, nested) in map {
+                        let path = if prefix.is_empty() {
+                            key
+                        } else {
+                            format!("{}.{}", prefix, key)
+                        };
+                        flatten(path, nested, out);
+                    }
+                }
+                leaf => out.push((prefix, leaf)),
+            }
+        }
+
+        let mut leaves = Vec::new();
+        let value = serde_json::to_value(self.redacted()).unwrap_or_default();
+        flatten(String::new(), value, &mut leaves);
+        leaves.sort_by(|a, b| a.0.cmp(&b.0));
+
+        leaves
+            .into_iter()
+            .map(|(key, value)| {
+                let source = provenance
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or(ConfigSource::Default);
+                ExplainedValue { key, value, source }
+            })
+            .collect()
     }
 
-    pub fn create_default_config_file() -> Result<PathBuf, std::io::Error> {
-        let config_path = Self::get_default_config_path().ok_or_else(|| {
+    /// Takes the settings of `next` that can change while the daemon runs, keeping the rest of
+    /// `self`. Also returns the keys, e.g. `server.cors_allowed_origins`, whose changes were
+    /// ignored because they only apply on restart. `server.bind_addrs` is left out of both, since
+    /// the caller rebinds the listeners itself.
+    pub fn reloaded(&self, next: &AppConfig) -> (AppConfig, Vec<String>) {
+        let mut applied = self.clone();
+        applied.log_level = next.log_level.clone();
+        let server = &mut applied.server;
+        server.dispatch_timeout = next.server.dispatch_timeout;
+        server.max_dispatch_timeout = next.server.max_dispatch_timeout;
+        server.dispatch_max_retries = next.server.dispatch_max_retries;
+        server.dispatch_retry_backoff = next.server.dispatch_retry_backoff;
+        server.send_timeout = next.server.send_timeout;
+        server.registration_timeout = next.server.registration_timeout;
+        server.websocket_pong_timeout = next.server.websocket_pong_timeout;
+        server.websocket_close_grace = next.server.websocket_close_grace;
+        server.max_job_bytes = next.server.max_job_bytes;
+        // Reloaded together so the pair keeps satisfying validate()
+        server.max_inflight_bytes = next.server.max_inflight_bytes;
+        server.max_batch_size = next.server.max_batch_size;
+        server.supersede_on_register = next.server.supersede_on_register;
+        server.maintenance_banner = next.server.maintenance_banner.clone();
+
+        fn changed_keys(
+            prefix: &str,
+            before: &serde_json::Value,
+            after: &serde_json::Value,
+            out: &mut Vec<String>,
+        ) {
+            let (Some(before), Some(after)) = (before.as_object(), after.as_object()) else {
+                return;
+            };
+            let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+            for key in keys {
+                let (old, new) = (before.get(key), after.get(key));
+                if prefix.is_empty() && key == "server" {
+                    if let (Some(old), Some(new)) = (old, new) {
+                        changed_keys("server.", old, new, out);
+                    }
+                } else if old != new {
+                    out.push(format!("{}{}", prefix, key));
+                }
+            }
+        }
+
+        let mut ignored = Vec::new();
+        changed_keys(
+            "",
+            &serde_json::to_value(&applied).unwrap_or_default(),
+            &serde_json::to_value(next).unwrap_or_default(),
+            &mut ignored,
+        );
+        ignored.retain(|key| key != "server.bind_addrs");
+        (applied, ignored)
+    }
+
+    fn env_source() -> LegacyBindAddr<Environment> {
+        LegacyBindAddr(
+            Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .separator(ENV_SEPARATOR)
+                .try_parsing(true),
+        )
+    }
+
+    pub fn get_default_config_path(format: ConfigFormat) -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join(format!("promptivd/config.{}", format)))
+    }
+
+    pub fn create_default_config_file(format: ConfigFormat) -> Result<PathBuf, std::io::Error> {
+        let config_path = Self::get_default_config_path(format).ok_or_else(|| {
             std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 "Could not determine config directory",
@@ -106,21 +804,160 @@ impl AppConfig {
             std::fs::create_dir_all(parent)?;
         }
 
-        let default_config = AppConfig::default();
-        let config_yaml = serde_yaml::to_string(&default_config)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        std::fs::write(&config_path, config_yaml)?;
+        let contents = format.serialize(&AppConfig::default())?;
+        std::fs::write(&config_path, contents)?;
         Ok(config_path)
     }
 
+    /// Writes this config to `path` in the format its extension names, YAML if none.
+    pub fn write_to_file(&self, path: &Path) -> Result<(), std::io::Error> {
+        let contents = ConfigFormat::from_path(path)
+            .unwrap_or_default()
+            .serialize(self)?;
+        std::fs::write(path, contents)
+    }
+
+    /// Returns a copy with secret values such as `admin_token` replaced by a placeholder.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        for token in [
+            &mut config.server.admin_token,
+            &mut config.server.auth_token,
+        ] {
+            if token.is_some() {
+                *token = Some(REDACTED.to_string());
+            }
+        }
+        config
+    }
+
     pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.bind_addrs.is_empty() {
+            return Err(ConfigError::Message(
+                "bind_addrs must list at least one address".to_string(),
+            ));
+        }
+        let mut seen = std::collections::HashSet::new();
+        if let Some(dup) = self.server.bind_addrs.iter().find(|s| !seen.insert(*s)) {
+            return Err(ConfigError::Message(format!(
+                "bind_addrs lists {} more than once",
+                dup
+            )));
+        }
+
         if self.server.max_job_bytes == 0 {
             return Err(ConfigError::Message(
                 "max_job_bytes must be greater than 0".to_string(),
             ));
         }
 
+        if cfg!(not(feature = "sqlite")) && self.server.sqlite_path.is_some() {
+            return Err(ConfigError::Message(
+                "sqlite_path requires promptivd to be built with the sqlite feature".to_string(),
+            ));
+        }
+
+        // Older jobs are no longer recorded, so a longer window would silently undercount
+        if self.server.stats_window > self.server.job_retention {
+            return Err(ConfigError::Message(
+                "stats_window must not exceed job_retention".to_string(),
+            ));
+        }
+
+        if self.server.max_batch_size == 0 {
+            return Err(ConfigError::Message(
+                "max_batch_size must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.server.max_concurrent_requests == 0 {
+            return Err(ConfigError::Message(
+                "max_concurrent_requests must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.server.max_inflight_bytes < self.server.max_job_bytes {
+            return Err(ConfigError::Message(
+                "max_inflight_bytes must be at least max_job_bytes".to_string(),
+            ));
+        }
+
+        if self.server.max_dispatch_timeout < self.server.dispatch_timeout {
+            return Err(ConfigError::Message(
+                "max_dispatch_timeout must be at least dispatch_timeout".to_string(),
+            ));
+        }
+
+        if self.server.dispatch_max_retries > MAX_DISPATCH_RETRIES {
+            return Err(ConfigError::Message(format!(
+                "dispatch_max_retries must be at most {}",
+                MAX_DISPATCH_RETRIES
+            )));
+        }
+        // Otherwise no retry could start before the dispatch timeout runs out
+        if self.server.dispatch_max_retries > 0
+            && self.server.dispatch_retry_backoff >= self.server.dispatch_timeout
+        {
+            return Err(ConfigError::Message(
+                "dispatch_retry_backoff must be shorter than dispatch_timeout".to_string(),
+            ));
+        }
+
+        if !(400..=599).contains(&self.server.failed_ack_status) {
+            return Err(ConfigError::Message(
+                "failed_ack_status must be a 4xx or 5xx status code".to_string(),
+            ));
+        }
+
+        self.server.compile_text_denylist()?;
+        self.server.cors_origins()?;
+        self.server.ws_origins()?;
+        self.server.tls_paths()?;
+
+        if self.server.persist_queue {
+            if self.server.max_queued_jobs == 0 {
+                return Err(ConfigError::Message(
+                    "max_queued_jobs must be greater than 0 when persist_queue is enabled"
+                        .to_string(),
+                ));
+            }
+            if self.server.resolved_queue_path().is_none() {
+                return Err(ConfigError::Message(
+                    "queue_path must be set when no user data directory is available".to_string(),
+                ));
+            }
+        }
+
+        if let Some((provider, _)) = self
+            .server
+            .provider_default_metadata
+            .iter()
+            .find(|(_, value)| !value.is_object())
+        {
+            return Err(ConfigError::Message(format!(
+                "provider_default_metadata for '{}' must be an object",
+                provider
+            )));
+        }
+
+        if self.server.send_timeout.is_zero() {
+            return Err(ConfigError::Message(
+                "send_timeout must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.server.registration_timeout.is_zero() {
+            return Err(ConfigError::Message(
+                "registration_timeout must be greater than 0".to_string(),
+            ));
+        }
+
+        if !self.server.sink_reauth_interval.is_zero() && self.server.auth_token.is_none() {
+            return Err(ConfigError::Message(
+                "sink_reauth_interval requires auth_token".to_string(),
+            ));
+        }
+
         if self.server.websocket_max_missed_pings == 0 {
             return Err(ConfigError::Message(
                 "websocket_max_missed_pings must be greater than 0".to_string(),
@@ -141,11 +978,11 @@ impl AppConfig {
             builder = builder.add_source(src);
         }
 
-        let base = builder.build()?;
+        let base = builder.add_source(Self::env_source()).build()?;
         let mut cfg: AppConfig = base.try_deserialize()?;
 
         let env_cfg: EnvConfig = Config::builder()
-            .add_source(Environment::with_prefix("PROMPTIVD").try_parsing(true))
+            .add_source(Environment::with_prefix(ENV_PREFIX).try_parsing(true))
             .build()?
             .try_deserialize()
             .unwrap_or_default();
@@ -156,13 +993,7 @@ impl AppConfig {
 
     fn apply_env_overrides(&mut self, e: EnvConfig) {
         if let Some(v) = e.server_bind_addr {
-            self.server.bind_addr = v;
-        }
-        if let Some(v) = e.log_level {
-            self.log_level = v;
-        }
-        if let Some(v) = e.log_format {
-            self.log_format = v;
+            self.server.bind_addrs = vec![ListenSpec::Tcp(v)];
         }
     }
 }
@@ -174,10 +1005,27 @@ mod tests {
     use std::io::Write;
     use tempfile::Builder;
 
+    #[test]
+    fn test_listen_spec_parses_tcp_and_unix_addresses() {
+        let tcp: ListenSpec = "127.0.0.1:8788".parse().unwrap();
+        assert_eq!(tcp, ListenSpec::Tcp("127.0.0.1:8788".parse().unwrap()));
+        let unix: ListenSpec = "unix:/run/promptivd.sock".parse().unwrap();
+        assert_eq!(unix, ListenSpec::Unix(PathBuf::from("/run/promptivd.sock")));
+
+        for spec in [tcp, unix] {
+            assert_eq!(spec.to_string().parse::<ListenSpec>().unwrap(), spec);
+        }
+        assert!("unix:".parse::<ListenSpec>().is_err());
+        assert!("localhost".parse::<ListenSpec>().is_err());
+    }
+
     #[test]
     fn test_default_config() {
         let config = AppConfig::default();
-        assert_eq!(config.server.bind_addr.port(), 8787);
+        assert_eq!(
+            config.server.bind_addrs,
+            [ListenSpec::Tcp("127.0.0.1:8787".parse().unwrap())]
+        );
         assert_eq!(config.log_level, "info");
     }
 
@@ -190,6 +1038,185 @@ mod tests {
         assert!(config.validate().is_err());
 
         config.server.max_job_bytes = 1024;
+        assert!(config.validate().is_ok());
+
+        config.server.max_concurrent_requests = 0;
+        assert!(config.validate().is_err());
+
+        config.server.max_concurrent_requests = 64;
+        config.server.max_batch_size = 0;
+        assert!(config.validate().is_err());
+
+        config.server.max_batch_size = AppConfig::default().server.max_batch_size;
+        config.server.stats_window = config.server.job_retention + Duration::from_secs(1);
+        assert!(config.validate().is_err());
+
+        config.server.stats_window = config.server.job_retention;
+        config.server.sqlite_path = Some(PathBuf::from("jobs.sqlite3"));
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "sqlite"));
+    }
+
+    #[test]
+    fn test_config_validation_rejects_invalid_denylist_pattern() {
+        let mut config = AppConfig::default();
+        config.server.text_denylist_patterns = vec![r"sk-[A-Za-z0-9]{20,}".to_string()];
+        assert!(config.validate().is_ok());
+
+        config
+            .server
+            .text_denylist_patterns
+            .push("(unclosed".to_string());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("text_denylist_patterns"));
+    }
+
+    #[test]
+    fn test_reload_applies_live_settings_only() {
+        let current = AppConfig::default();
+        let mut next = AppConfig {
+            log_level: "debug".to_string(),
+            log_format: LogFormat::Json,
+            ..Default::default()
+        };
+        next.server.dispatch_timeout = Duration::from_secs(5);
+        next.server.max_job_bytes = 1024;
+        next.server.supersede_on_register = !current.server.supersede_on_register;
+        next.server.bind_addrs = vec!["127.0.0.1:9999".parse().unwrap()];
+        next.server.cors_allow_any = true;
+        next.server.auth_token = Some("secret".to_string());
+
+        let (applied, ignored) = current.reloaded(&next);
+
+        assert_eq!(applied.log_level, "debug");
+        assert_eq!(applied.server.dispatch_timeout, Duration::from_secs(5));
+        assert_eq!(applied.server.max_job_bytes, 1024);
+        assert_eq!(
+            applied.server.max_inflight_bytes,
+            next.server.max_inflight_bytes
+        );
+        assert_eq!(
+            applied.server.supersede_on_register,
+            next.server.supersede_on_register
+        );
+        assert!(matches!(applied.log_format, LogFormat::Pretty));
+        assert_eq!(applied.server.bind_addrs, current.server.bind_addrs);
+        assert!(!applied.server.cors_allow_any);
+        assert!(applied.server.auth_token.is_none());
+        assert_eq!(
+            ignored,
+            ["log_format", "server.auth_token", "server.cors_allow_any"]
+        );
+
+        let (_, ignored) = current.reloaded(&current);
+        assert!(ignored.is_empty());
+
+        // Raising both limits must not leave a job limit above the old inflight budget
+        let mut next = current.clone();
+        next.server.max_job_bytes = current.server.max_inflight_bytes * 2;
+        next.server.max_inflight_bytes = current.server.max_inflight_bytes * 4;
+        next.validate().unwrap();
+        let (applied, ignored) = current.reloaded(&next);
+        applied.validate().unwrap();
+        assert!(ignored.is_empty());
+    }
+
+    #[test]
+    fn test_config_validation_rejects_invalid_cors_origin() {
+        let mut config = AppConfig::default();
+        config
+            .server
+            .cors_allowed_origins
+            .push("chrome-extension://abcdefghijklmnop".to_string());
+        assert_eq!(config.server.cors_origins().unwrap().len(), 3);
+
+        for origin in ["localhost:3000", "http://localhost:3000/", "*"] {
+            config.server.cors_allowed_origins = vec![origin.to_string()];
+            let err = config.validate().unwrap_err();
+            assert!(err.to_string().contains("cors_allowed_origins"), "{}", err);
+        }
+
+        config.server.cors_allowed_origins = Vec::new();
+        config.server.ws_allowed_origins = Some(vec!["http://localhost:3000/".to_string()]);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("ws_allowed_origins"), "{}", err);
+    }
+
+    #[test]
+    fn test_config_validation_rejects_non_object_provider_metadata() {
+        let mut config = AppConfig::default();
+        config.server.provider_default_metadata.insert(
+            "claude".to_string(),
+            serde_json::json!({"max_tokens": 8192}),
+        );
+        assert!(config.validate().is_ok());
+
+        config.server.provider_default_metadata.insert(
+            "chatgpt".to_string(),
+            serde_json::json!(["not", "an", "object"]),
+        );
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("'chatgpt'"));
+    }
+
+    #[test]
+    fn test_config_validation_checks_tls_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = dir.path().join("cert.pem");
+        let key = dir.path().join("key.pem");
+        std::fs::write(&cert, generated.cert.pem()).unwrap();
+        std::fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+
+        let mut config = AppConfig::default();
+        config.server.tls_cert_path = Some(cert.clone());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("must be set together"));
+
+        config.server.tls_key_path = Some(key.clone());
+        assert!(config.validate().is_ok());
+
+        // A certificate where the key should be
+        config.server.tls_key_path = Some(cert);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("tls_key_path"));
+
+        config.server.tls_key_path = Some(dir.path().join("missing.pem"));
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("missing.pem"));
+    }
+
+    #[test]
+    fn test_config_validation_bounds_dispatch_retries() {
+        let mut config = AppConfig::default();
+        config.server.dispatch_max_retries = MAX_DISPATCH_RETRIES;
+        assert!(config.validate().is_ok());
+
+        config.server.dispatch_max_retries = MAX_DISPATCH_RETRIES + 1;
+        assert!(config.validate().is_err());
+
+        config.server.dispatch_max_retries = 1;
+        config.server.dispatch_retry_backoff = config.server.dispatch_timeout;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_validation_requires_auth_token_for_reauth() {
+        let mut config = AppConfig::default();
+        config.server.sink_reauth_interval = Duration::from_secs(300);
+        assert!(config.validate().is_err());
+
+        config.server.auth_token = Some("secret".to_string());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_validation_rejects_non_error_failed_ack_status() {
+        let mut config = AppConfig::default();
+        config.server.failed_ack_status = 422;
+        assert!(config.validate().is_ok());
+
+        config.server.failed_ack_status = 200;
+        assert!(config.validate().is_err());
     }
 
     #[test]
@@ -205,10 +1232,121 @@ log_level: "debug"
         temp_file.write_all(yaml_content.as_bytes()).unwrap();
 
         let config = AppConfig::from_file(Some(temp_file.path())).unwrap();
-        assert_eq!(config.server.bind_addr.port(), 9999);
+        assert_eq!(
+            config.server.bind_addrs,
+            [ListenSpec::Tcp("127.0.0.1:9999".parse().unwrap())]
+        );
         assert_eq!(config.log_level, "debug");
     }
 
+    #[test]
+    #[serial]
+    fn test_bind_addrs_accepts_a_list_or_the_legacy_single_address() {
+        let load = |content: &str| {
+            let mut file = Builder::new().suffix(".yaml").tempfile().unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+            AppConfig::from_file(Some(file.path()))
+        };
+
+        let config =
+            load("server:\n  bind_addrs:\n    - 127.0.0.1:9999\n    - unix:/run/promptivd.sock\n")
+                .unwrap();
+        assert_eq!(
+            config.server.bind_addrs,
+            [
+                ListenSpec::Tcp("127.0.0.1:9999".parse().unwrap()),
+                ListenSpec::Unix(PathBuf::from("/run/promptivd.sock")),
+            ]
+        );
+
+        let config = load("server:\n  bind_addrs: unix:/run/promptivd.sock\n").unwrap();
+        assert_eq!(
+            config.server.bind_addrs,
+            [ListenSpec::Unix(PathBuf::from("/run/promptivd.sock"))]
+        );
+
+        assert!(load("server:\n  bind_addr: 127.0.0.1:1\n  bind_addrs: 127.0.0.1:2\n").is_err());
+
+        let mut config = AppConfig::default();
+        config.server.bind_addrs.clear();
+        assert!(config.validate().is_err());
+        config.server.bind_addrs = vec!["127.0.0.1:1".parse().unwrap(); 2];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_formats_load_alike() {
+        let sources = [
+            (
+                ".yaml",
+                r#"
+server:
+  bind_addrs: ["127.0.0.1:9999"]
+  max_job_bytes: 2048
+log_format: json
+"#,
+            ),
+            (
+                ".toml",
+                r#"
+log_format = "json"
+
+[server]
+bind_addrs = ["127.0.0.1:9999"]
+max_job_bytes = 2048
+"#,
+            ),
+            (
+                ".json",
+                r#"{"server": {"bind_addrs": ["127.0.0.1:9999"], "max_job_bytes": 2048},
+                    "log_format": "json"}"#,
+            ),
+        ];
+
+        let loaded: Vec<_> = sources
+            .iter()
+            .map(|(suffix, content)| {
+                let mut file = Builder::new().suffix(suffix).tempfile().unwrap();
+                file.write_all(content.as_bytes()).unwrap();
+                let config = AppConfig::from_file(Some(file.path())).unwrap();
+                serde_json::to_value(config).unwrap()
+            })
+            .collect();
+
+        assert_eq!(loaded[0]["server"]["max_job_bytes"], 2048);
+        assert_eq!(loaded[0], loaded[1]);
+        assert_eq!(loaded[0], loaded[2]);
+
+        let file = Builder::new().suffix(".ini").tempfile().unwrap();
+        assert!(AppConfig::from_file(Some(file.path())).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_written_config_round_trips_in_every_format() {
+        let mut config = AppConfig::default();
+        config.server.max_job_bytes = 4096;
+        config.server.admin_token = Some("secret".to_string());
+        let expected = serde_json::to_value(&config).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        for format in ConfigFormat::ALL {
+            let path = dir.path().join(format!("config.{}", format));
+            config.write_to_file(&path).unwrap();
+            let reloaded = AppConfig::from_file(Some(&path)).unwrap();
+            assert_eq!(
+                serde_json::to_value(reloaded).unwrap(),
+                expected,
+                "{}",
+                format
+            );
+        }
+
+        assert_eq!("TOML".parse::<ConfigFormat>(), Ok(ConfigFormat::Toml));
+        assert!("ini".parse::<ConfigFormat>().is_err());
+    }
+
     #[test]
     #[serial]
     fn test_environment_variables() {
@@ -216,11 +1354,37 @@ log_level: "debug"
         std::env::set_var("PROMPTIVD_LOG_LEVEL", "trace");
 
         let config = AppConfig::from_file(None::<&str>).unwrap();
-        assert_eq!(config.server.bind_addr.to_string(), "0.0.0.0:8080");
+        assert_eq!(config.server.bind_addrs[0].to_string(), "0.0.0.0:8080");
         assert_eq!(config.log_level, "trace");
 
         // Cleanup
         std::env::remove_var("PROMPTIVD_SERVER_BIND_ADDR");
         std::env::remove_var("PROMPTIVD_LOG_LEVEL");
     }
+
+    #[test]
+    #[serial]
+    fn test_nested_environment_overrides_ignore_unknown_vars() {
+        std::env::set_var("PROMPTIVD_SERVER__BIND_ADDR", "0.0.0.0:9090");
+        std::env::set_var("PROMPTIVD_SERVER__MAX_JOB_BYTES", "2048");
+        std::env::set_var("PROMPTIVD_LOG_FORMAT", "json");
+        std::env::set_var("PROMPTIVD_FOO", "not a config field");
+        std::env::set_var("PROMPTIVD_SERVER__NOT_A_FIELD", "ignored");
+
+        let config = AppConfig::from_file(None::<&str>).unwrap();
+        assert_eq!(config.server.bind_addrs[0].to_string(), "0.0.0.0:9090");
+        assert_eq!(config.server.max_job_bytes, 2048);
+        assert!(matches!(config.log_format, LogFormat::Json));
+
+        // Cleanup
+        for var in [
+            "PROMPTIVD_SERVER__BIND_ADDR",
+            "PROMPTIVD_SERVER__MAX_JOB_BYTES",
+            "PROMPTIVD_LOG_FORMAT",
+            "PROMPTIVD_FOO",
+            "PROMPTIVD_SERVER__NOT_A_FIELD",
+        ] {
+            std::env::remove_var(var);
+        }
+    }
 }
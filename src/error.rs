@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,20 +19,195 @@ pub enum AppError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("Address {addr} is already in use. Is another promptivd instance running? Stop it or choose a different address with --bind")]
+    AddrInUse { addr: SocketAddr },
+
+    #[error("Unix socket {} is already in use. Is another promptivd instance running?", path.display())]
+    SocketInUse { path: std::path::PathBuf },
+
+    #[error("Permission denied binding {addr}. Ports below 1024 usually require elevated privileges; choose a higher port with --bind")]
+    BindPermissionDenied { addr: SocketAddr },
+
     #[error("No sink connected")]
     NoSink,
 
+    #[error("No connected sink serves provider '{provider}'")]
+    NoMatchingSink { provider: String },
+
+    #[error("Sink {sink_id} disconnected before acking the job")]
+    SinkDisconnectedMidJob { sink_id: uuid::Uuid },
+
     #[error("Invalid request: {reason}")]
     InvalidRequest { reason: String },
 
     #[error("Job payload too large: {size} bytes (max: {max})")]
     PayloadTooLarge { size: usize, max: usize },
 
+    #[error("Batch of {size} jobs exceeds the limit of {max}")]
+    BatchTooLarge { size: usize, max: usize },
+
+    #[error("Unsupported expectation: {expectation}")]
+    ExpectationFailed { expectation: String },
+
     #[error("Sink registration failed: {reason}")]
     SinkRegistrationFailed { reason: String },
 
+    #[error("Disconnecting misbehaving sink: {reason}")]
+    SinkMisbehaving { reason: String },
+
     #[error("Job dispatch timeout after {timeout_ms}ms")]
     DispatchTimeout { timeout_ms: u64 },
+
+    #[error("Daemon is draining and not accepting new jobs")]
+    Draining,
+
+    #[error("Daemon is in maintenance and cannot queue broadcast jobs")]
+    Maintenance,
+
+    #[error("Too many concurrent requests")]
+    Overloaded,
+
+    #[error("Rate limit exceeded; retry in {retry_after_ms}ms")]
+    RateLimited { retry_after_ms: u64 },
+
+    #[error("Job text matches a blocked content pattern")]
+    BlockedContent,
+
+    #[error("Job not found: {job_id}")]
+    JobNotFound { job_id: String },
+
+    #[error("Job {job_id} cannot be cancelled: {reason}")]
+    JobNotCancellable { job_id: String, reason: String },
+
+    #[error("X-Request-Id {id} is already in use by a pending or queued job")]
+    RequestIdInUse { id: String },
+
+    #[error("Missing or invalid bearer token")]
+    Unauthorized,
+
+    #[error("Origin {origin} may not open the sink WebSocket")]
+    OriginNotAllowed { origin: String },
+
+    #[error(
+        "Sink does not support capability '{capability}'{}",
+        provider.as_ref().map(|p| format!(" for provider '{}'", p)).unwrap_or_default()
+    )]
+    UnsupportedCapability {
+        capability: String,
+        provider: Option<String>,
+    },
+
+    #[error(
+        "No connected sink has a session to reuse{}",
+        provider.as_ref().map(|p| format!(" for provider '{}'", p)).unwrap_or_default()
+    )]
+    NoReusableSession { provider: Option<String> },
+
+    #[error("Sink is busy with {inflight} jobs awaiting an ack")]
+    SinkBusy { inflight: usize },
+
+    #[error("memory_pressure: {inflight} of {max} in-flight bytes in use")]
+    MemoryPressure { inflight: usize, max: usize },
+
+    #[error("Callback to {url} failed: {reason}")]
+    CallbackFailed { url: String, reason: String },
+}
+
+/// Machine-readable identifier sent as `code` in HTTP error bodies, so clients need not match on
+/// the message text. The strings are part of the API and must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoSink,
+    NoMatchingSink,
+    SinkDisconnected,
+    InvalidRequest,
+    PayloadTooLarge,
+    BatchTooLarge,
+    ExpectationFailed,
+    DispatchTimeout,
+    Overloaded,
+    RateLimited,
+    SinkBusy,
+    Draining,
+    Maintenance,
+    MemoryPressure,
+    BlockedContent,
+    Unauthorized,
+    OriginNotAllowed,
+    JobNotFound,
+    JobNotCancellable,
+    RequestIdInUse,
+    UnsupportedCapability,
+    NoReusableSession,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::NoSink => "no_sink",
+            ErrorCode::NoMatchingSink => "no_matching_sink",
+            ErrorCode::SinkDisconnected => "sink_disconnected",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::BatchTooLarge => "batch_too_large",
+            ErrorCode::ExpectationFailed => "expectation_failed",
+            ErrorCode::DispatchTimeout => "dispatch_timeout",
+            ErrorCode::Overloaded => "overloaded",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::SinkBusy => "sink_busy",
+            ErrorCode::Draining => "draining",
+            ErrorCode::Maintenance => "maintenance",
+            ErrorCode::MemoryPressure => "memory_pressure",
+            ErrorCode::BlockedContent => "blocked_content",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::OriginNotAllowed => "origin_not_allowed",
+            ErrorCode::JobNotFound => "job_not_found",
+            ErrorCode::JobNotCancellable => "job_not_cancellable",
+            ErrorCode::RequestIdInUse => "request_id_in_use",
+            ErrorCode::UnsupportedCapability => "unsupported_capability",
+            ErrorCode::NoReusableSession => "no_reusable_session",
+            ErrorCode::Internal => "internal",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::NoSink => ErrorCode::NoSink,
+            AppError::NoMatchingSink { .. } => ErrorCode::NoMatchingSink,
+            AppError::SinkDisconnectedMidJob { .. } => ErrorCode::SinkDisconnected,
+            AppError::InvalidRequest { .. } | AppError::Serialization(_) => {
+                ErrorCode::InvalidRequest
+            }
+            AppError::PayloadTooLarge { .. } => ErrorCode::PayloadTooLarge,
+            AppError::BatchTooLarge { .. } => ErrorCode::BatchTooLarge,
+            AppError::ExpectationFailed { .. } => ErrorCode::ExpectationFailed,
+            AppError::DispatchTimeout { .. } => ErrorCode::DispatchTimeout,
+            AppError::Overloaded => ErrorCode::Overloaded,
+            AppError::RateLimited { .. } => ErrorCode::RateLimited,
+            AppError::SinkBusy { .. } => ErrorCode::SinkBusy,
+            AppError::Draining => ErrorCode::Draining,
+            AppError::Maintenance => ErrorCode::Maintenance,
+            AppError::MemoryPressure { .. } => ErrorCode::MemoryPressure,
+            AppError::BlockedContent => ErrorCode::BlockedContent,
+            AppError::Unauthorized => ErrorCode::Unauthorized,
+            AppError::OriginNotAllowed { .. } => ErrorCode::OriginNotAllowed,
+            AppError::JobNotFound { .. } => ErrorCode::JobNotFound,
+            AppError::JobNotCancellable { .. } => ErrorCode::JobNotCancellable,
+            AppError::RequestIdInUse { .. } => ErrorCode::RequestIdInUse,
+            AppError::UnsupportedCapability { .. } => ErrorCode::UnsupportedCapability,
+            AppError::NoReusableSession { .. } => ErrorCode::NoReusableSession,
+            _ => ErrorCode::Internal,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -43,6 +220,20 @@ pub enum ValidationError {
 
     #[error("Empty snippet content")]
     EmptySnippet,
+
+    #[error("Unsupported content type: {content_type}")]
+    UnsupportedContentType { content_type: String },
+
+    #[error("Job already expired at {expires_at}")]
+    Expired {
+        expires_at: chrono::DateTime<chrono::Utc>,
+    },
+
+    #[error("timeout_ms must be greater than 0")]
+    ZeroTimeout,
+
+    #[error("Invalid metadata: {reason}")]
+    InvalidMetadata { reason: String },
 }
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -1,122 +1,1168 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+use axum::body::Body;
 use axum::extract::ws::WebSocketUpgrade;
-use axum::extract::State;
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
 use axum::response::Response;
+use axum::BoxError;
 use axum::{response::IntoResponse, Json};
 use chrono::Utc;
-use tracing::{info, warn};
+use http_body_util::Limited;
+use regex::RegexSet;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tracing::{debug, info, level_filters::LevelFilter, warn, Instrument, Span};
+use tracing_subscriber::{reload, EnvFilter, Registry};
 use uuid::Uuid;
 
-use crate::config::ServerConfig;
-use crate::error::AppError;
-use crate::models::{HealthResponse, InsertTextRequest, ProvidersResponse};
-use crate::websocket::{AckResponse, AckStatus, SinkManager};
+use crate::callback;
+use crate::config::{ConfigError, ServerConfig, SharedConfig};
+use crate::error::{AppError, AppResult};
+use crate::idempotency::{CachedResponse, IdempotencyCache, Replay};
+use crate::jobs::{self, JobRecord, JobStats, JobSummary};
+use crate::models::{
+    BatchQuery, DrainResponse, HealthResponse, InsertQuery, InsertTextRequest, JobsQuery,
+    LogLevelRequest, LogLevelResponse, MaintenanceRequest, MaintenanceResponse,
+    ProviderAvailability, ProvidersQuery, ProvidersResponse, RouteInfo, RoutesResponse, SinkInfo,
+    StatusResponse,
+};
+use crate::queue::QueuedJob;
+use crate::rate_limit::RateLimiter;
+use crate::websocket::{AckResponse, AckStatus, SinkManager, SinkOutcome, ECHO_METADATA_KEY};
 
 #[derive(Clone)]
 pub struct AppState {
     pub sink_manager: Arc<SinkManager>,
-    pub config: ServerConfig,
+    /// Shared with the sink manager and swapped on reload. The denylist, rate limiter and
+    /// idempotency cache below are built once from the settings in effect at startup.
+    pub config: SharedConfig,
+    pub text_denylist: Arc<RegexSet>,
+    pub log_reload: Option<LogReloadHandle>,
+    /// Route table served by `GET /v1/routes`, filled in when the router is built.
+    pub routes: Arc<Vec<RouteInfo>>,
+    /// Set by `POST /v1/admin/drain` or shutdown; inserts are rejected while set.
+    pub draining: Arc<AtomicBool>,
+    /// End of the shutdown drain's grace window, once shutdown has started.
+    pub drain_deadline: Arc<Mutex<Option<Instant>>>,
+    /// Per-client insert limit, keyed by `source.client`; unset when limiting is disabled.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Responses replayed for repeated `Idempotency-Key`s; unset when deduplication is disabled.
+    pub idempotency: Option<Arc<IdempotencyCache>>,
 }
 
-pub async fn health() -> Json<HealthResponse> {
+/// Handle onto the daemon's live log filter, created by `init_logging`.
+pub type LogReloadHandle = reload::Handle<EnvFilter, Registry>;
+
+/// The log filter for `log_level`, with any `RUST_LOG` directives applied on top. Used at startup,
+/// on SIGHUP and by `PUT /v1/loglevel`, so a level yields the same filter whichever set it.
+pub fn env_filter(log_level: &str) -> AppResult<EnvFilter> {
+    let level = log_level.parse::<LevelFilter>().map_err(|e| {
+        AppError::Config(ConfigError::Message(format!(
+            "Invalid log level '{}': {}",
+            log_level, e
+        )))
+    })?;
+
+    EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env()
+        .map_err(|e| {
+            AppError::Config(ConfigError::Message(format!(
+                "Failed to parse log filter: {}",
+                e
+            )))
+        })
+}
+
+impl AppState {
+    pub fn new(sink_manager: Arc<SinkManager>) -> Result<Self, ConfigError> {
+        let shared = Arc::clone(sink_manager.config());
+        let config = shared.load();
+        let text_denylist = Arc::new(config.compile_text_denylist()?);
+        let rate_limiter =
+            RateLimiter::new(config.rate_limit_per_sec, config.rate_limit_burst).map(Arc::new);
+        let idempotency =
+            IdempotencyCache::new(config.idempotency_ttl, config.max_idempotency_keys)
+                .map(Arc::new);
+
+        Ok(Self {
+            sink_manager,
+            config: Arc::clone(&shared),
+            text_denylist,
+            log_reload: None,
+            routes: Arc::new(Vec::new()),
+            draining: Arc::new(AtomicBool::new(false)),
+            drain_deadline: Arc::new(Mutex::new(None)),
+            rate_limiter,
+            idempotency,
+        })
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting new jobs and waits up to `timeout` for already accepted ones to finish,
+    /// returning whether they all did.
+    pub async fn drain(&self, timeout: std::time::Duration) -> bool {
+        *self.drain_deadline.lock().unwrap() = Some(Instant::now() + timeout);
+        self.draining.store(true, Ordering::Relaxed);
+        self.sink_manager.wait_for_idle(timeout).await
+    }
+
+    /// Time left in the shutdown drain's grace window, if one is running.
+    pub fn drain_remaining(&self) -> Option<std::time::Duration> {
+        self.drain_deadline
+            .lock()
+            .unwrap()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn with_log_reload(mut self, handle: LogReloadHandle) -> Self {
+        self.log_reload = Some(handle);
+        self
+    }
+}
+
+/// Header a client may set to choose the job id of an insert; it is echoed back on the response.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The client-supplied request id, if any. Ids are limited in length and to characters that are
+/// safe in URLs and log lines, since they become job ids.
+fn request_id(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get(REQUEST_ID_HEADER) else {
+        return Ok(None);
+    };
+    let invalid = || AppError::InvalidRequest {
+        reason: format!(
+            "X-Request-Id must be at most {} letters, digits, '-', '_', '.' or ':'",
+            MAX_REQUEST_ID_LEN
+        ),
+    };
+
+    let id = value.to_str().map_err(|_| invalid())?.trim();
+    if id.is_empty() {
+        return Ok(None);
+    }
+    let sane = id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    if !sane {
+        return Err(invalid());
+    }
+    Ok(Some(id.to_string()))
+}
+
+/// Header a client may set so that retries of an insert within `server.idempotency_ttl` replay
+/// the first response instead of dispatching again.
+pub const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+/// Set on responses replayed for a repeated `Idempotency-Key`.
+pub const IDEMPOTENT_REPLAYED_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN)
+        .ok_or_else(|| AppError::InvalidRequest {
+            reason: format!(
+                "Idempotency-Key must be 1 to {} printable ASCII characters",
+                MAX_IDEMPOTENCY_KEY_LEN
+            ),
+        })?;
+    Ok(Some(key.to_string()))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Whether the request's bearer token is `expected`, compared in constant time so the response
+/// time does not reveal how much of a guess was right.
+fn bearer_matches(headers: &HeaderMap, expected: &str) -> bool {
+    bearer_token(headers).is_some_and(|token| token_matches(token, expected))
+}
+
+/// Constant-time comparison of a presented token with the configured one.
+pub(crate) fn token_matches(token: &str, expected: &str) -> bool {
+    token.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+/// Admin endpoints are disabled unless `admin_token` is configured, and then require it as a
+/// bearer token.
+fn require_admin(config: &ServerConfig, headers: &HeaderMap) -> Result<(), AppError> {
+    match config.admin_token.as_deref() {
+        Some(expected) if bearer_matches(headers, expected) => Ok(()),
+        _ => Err(AppError::Unauthorized),
+    }
+}
+
+/// Client endpoints are open unless `auth_token` is configured, and then require it as a bearer
+/// token.
+fn require_client(config: &ServerConfig, headers: &HeaderMap) -> Result<(), AppError> {
+    match config.auth_token.as_deref() {
+        Some(expected) if !bearer_matches(headers, expected) => Err(AppError::Unauthorized),
+        _ => Ok(()),
+    }
+}
+
+/// Middleware guarding the routes marked `RouteAuth::Client`.
+pub async fn check_client_auth(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    require_client(&state.config.load(), request.headers())?;
+    Ok(next.run(request).await)
+}
+
+/// Liveness: always 200 while the daemon serves requests, with the sink state for context.
+pub async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
+    let sink_manager = &state.sink_manager;
     Json(HealthResponse {
         ok: true,
         timestamp: Utc::now(),
         version: env!("CARGO_PKG_VERSION").to_string(),
+        sink_connected: sink_manager.has_active_sink(),
+        sink_count: sink_manager.sink_count().await,
+        last_pong_age_ms: sink_manager
+            .last_pong_age()
+            .map(|age| age.as_millis().try_into().unwrap_or(u64::MAX)),
     })
 }
 
+/// Readiness: like `health`, but 503 while `require_sink` is set and no sink is connected.
+pub async fn ready(state: State<AppState>) -> Result<Json<HealthResponse>, AppError> {
+    if state.config.load().require_sink && !state.sink_manager.has_active_sink() {
+        return Err(AppError::NoSink);
+    }
+    Ok(health(state).await)
+}
+
+/// Lists the providers of every connected sink, or with `?name=` reports whether one provider
+/// is served. The latter answers 200 with `available: false` when nothing serves it, and 503
+/// only when no sink is connected and `require_sink` is set.
 pub async fn list_providers(
     State(state): State<AppState>,
-) -> Result<Json<ProvidersResponse>, AppError> {
-    match state.sink_manager.active_providers().await {
-        Some(providers) => Ok(Json(ProvidersResponse { providers })),
-        None => Err(AppError::NoSink),
+    Query(query): Query<ProvidersQuery>,
+) -> Result<Response, AppError> {
+    let Some(name) = query.name else {
+        return match state.sink_manager.active_providers().await {
+            Some(providers) => Ok(Json(ProvidersResponse { providers }).into_response()),
+            None => Err(AppError::NoSink),
+        };
+    };
+
+    let sink_ids = match state.sink_manager.sinks_for_provider(&name).await {
+        Some(sink_ids) => sink_ids,
+        None if state.config.load().require_sink => return Err(AppError::NoSink),
+        None => Vec::new(),
+    };
+    Ok(Json(ProviderAvailability {
+        name,
+        available: !sink_ids.is_empty(),
+        sink_ids,
+    })
+    .into_response())
+}
+
+pub async fn status(State(state): State<AppState>) -> Json<StatusResponse> {
+    let maintenance_banner = state.sink_manager.maintenance_banner();
+    Json(StatusResponse {
+        sink_connected: state.sink_manager.has_active_sink(),
+        sink_ready: state.sink_manager.sink_ready().await,
+        inflight_bytes: state.sink_manager.inflight_bytes(),
+        max_inflight_bytes: state.config.load().max_inflight_bytes,
+        draining: state.is_draining(),
+        pending_jobs: state.sink_manager.jobs().pending(),
+        drain_remaining_ms: state
+            .drain_remaining()
+            .map(|remaining| remaining.as_millis() as u64),
+        maintenance: maintenance_banner.is_some(),
+        maintenance_banner,
+    })
+}
+
+pub async fn job_stats(State(state): State<AppState>) -> Json<JobStats> {
+    let window = state.config.load().stats_window;
+    Json(state.sink_manager.jobs().stats(window))
+}
+
+/// Lists jobs newest first, from the SQLite job history when `sqlite_path` is set and from the
+/// records kept for `job_retention` otherwise.
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Query(query): Query<JobsQuery>,
+) -> Result<Json<Vec<JobSummary>>, AppError> {
+    #[cfg(feature = "sqlite")]
+    if let Some(history) = state.sink_manager.jobs().history().cloned() {
+        let jobs = tokio::task::spawn_blocking(move || history.query(&query))
+            .await
+            .map_err(std::io::Error::other)??;
+        return Ok(Json(jobs));
     }
+    Ok(Json(state.sink_manager.jobs().list(&query)))
 }
 
-pub async fn insert_job(
+pub async fn get_job(
     State(state): State<AppState>,
-    Json(payload): Json<InsertTextRequest>,
-) -> Result<impl IntoResponse, AppError> {
+    Path(job_id): Path<String>,
+) -> Result<Json<JobRecord>, AppError> {
+    state
+        .sink_manager
+        .jobs()
+        .get(&job_id)
+        .map(Json)
+        .ok_or(AppError::JobNotFound { job_id })
+}
+
+/// Cancels a job that a sink has not acked yet, e.g. one submitted with `?wait=false`.
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    state.sink_manager.cancel_job(&job_id).await?;
+    Ok(Json(serde_json::json!({
+        "job_id": job_id,
+        "status": "cancelled",
+    })))
+}
+
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state
+            .sink_manager
+            .metrics()
+            .render(&state.sink_manager.list_sinks().await),
+    )
+}
+
+pub async fn list_sinks(State(state): State<AppState>) -> Json<Vec<SinkInfo>> {
+    Json(state.sink_manager.list_sinks().await)
+}
+
+pub async fn list_routes(State(state): State<AppState>) -> Json<RoutesResponse> {
+    Json(RoutesResponse {
+        routes: state.routes.to_vec(),
+    })
+}
+
+/// Parses an insert request body, rejecting unknown fields when configured to.
+fn parse_insert_body(state: &AppState, body: serde_json::Value) -> AppResult<InsertTextRequest> {
+    let (payload, unknown_fields) =
+        InsertTextRequest::from_json_value(body).map_err(|e| AppError::InvalidRequest {
+            reason: format!("Invalid request body: {}", e),
+        })?;
+    if let Some(field) = unknown_fields.first() {
+        if state.config.load().reject_unknown_request_fields {
+            return Err(AppError::InvalidRequest {
+                reason: format!("Unknown field: {}", field),
+            });
+        }
+        debug!(fields = ?unknown_fields, "Ignoring unknown request fields");
+    }
+    Ok(payload)
+}
+
+/// Checks an insert request's size, validity and text, returning the validation errors tolerated
+/// under `lenient_validation` as warnings.
+fn check_insert_request(
+    state: &AppState,
+    payload: &mut InsertTextRequest,
+) -> AppResult<Vec<String>> {
+    let config = state.config.load();
+
     // Validate payload size
-    let payload_size = serde_json::to_string(&payload)?.len();
-    if payload_size > state.config.max_job_bytes {
+    let payload_size = serde_json::to_string(&*payload)?.len();
+    if payload_size > config.max_job_bytes {
         return Err(AppError::PayloadTooLarge {
             size: payload_size,
-            max: state.config.max_job_bytes,
+            max: config.max_job_bytes,
         });
     }
 
     // Validate the request
-    payload.validate().map_err(|e| AppError::InvalidRequest {
-        reason: format!("Validation error: {:?}", e),
-    })?;
+    let warnings = payload
+        .validate_metadata_size(config.max_metadata_bytes)
+        .and_then(|_| {
+            if config.lenient_validation {
+                payload.validate_lenient()
+            } else {
+                payload.validate().map(|_| Vec::new())
+            }
+        })
+        .map_err(|e| AppError::InvalidRequest {
+            reason: format!("Validation error: {:?}", e),
+        })?
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    // Never log the text itself: a match usually means it contains a secret
+    if let Some(pattern) = state.text_denylist.matches(&payload.text).iter().next() {
+        warn!(pattern, "Job rejected: text matches a denylist pattern");
+        return Err(AppError::BlockedContent);
+    }
+
+    Ok(warnings)
+}
+
+type InsertResponse = (StatusCode, HeaderMap, Json<serde_json::Value>);
+
+pub async fn insert_job(
+    State(state): State<AppState>,
+    Query(query): Query<InsertQuery>,
+    request_headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<impl IntoResponse, AppError> {
+    state.sink_manager.metrics().job_received();
+    if state.is_draining() {
+        return Err(AppError::Draining);
+    }
+
+    let idempotency = match (&state.idempotency, idempotency_key(&request_headers)?) {
+        (Some(cache), Some(key)) => {
+            if let Some(replay) = cache.get(&key) {
+                return Ok(replayed(replay));
+            }
+            Some((Arc::clone(cache), key))
+        }
+        _ => None,
+    };
+
+    // A supplied request id doubles as the job id, so client and daemon logs line up. It is
+    // claimed once the job is tracked or queued, failing then if another job holds it.
+    let job_id = request_id(&request_headers)?.unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = jobs::job_span(&job_id);
+    let Some((cache, key)) = idempotency else {
+        return submit_insert(state, query, job_id, body)
+            .instrument(span)
+            .await;
+    };
+    if let Some(replay) = cache.begin(&key, &job_id) {
+        return Ok(replayed(replay));
+    }
+    let result = submit_insert(state, query, job_id, body)
+        .instrument(span)
+        .await;
+    match &result {
+        Ok((status, headers, Json(body))) => cache.complete(
+            &key,
+            CachedResponse {
+                status: *status,
+                headers: headers.clone(),
+                body: body.clone(),
+            },
+        ),
+        // Rejected requests may succeed when retried, e.g. once a sink connects
+        Err(_) => cache.forget(&key),
+    }
+    result
+}
+
+/// The response to a request whose `Idempotency-Key` an earlier request already used.
+fn replayed(replay: Replay) -> InsertResponse {
+    let (status, mut headers, body) = match replay {
+        Replay::Done(response) => (response.status, response.headers, response.body),
+        Replay::InProgress { job_id } => {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                REQUEST_ID_HEADER,
+                HeaderValue::from_str(&job_id).expect("request ids are header-safe"),
+            );
+            let body = serde_json::json!({
+                "job_id": job_id,
+                "request_id": job_id,
+                "status": "pending",
+            });
+            (StatusCode::ACCEPTED, headers, body)
+        }
+    };
+    headers.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    (status, headers, Json(body))
+}
+
+async fn submit_insert(
+    state: AppState,
+    query: InsertQuery,
+    job_id: String,
+    body: serde_json::Value,
+) -> Result<InsertResponse, AppError> {
+    // One snapshot for the whole request, even if the configuration is reloaded meanwhile
+    let config = state.config.load_full();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(&job_id).expect("request ids are header-safe"),
+    );
+
+    let mut payload = parse_insert_body(&state, body)?;
+    if let Some(provider) = payload.target.as_ref().and_then(|t| t.provider.as_deref()) {
+        Span::current().record("provider", provider);
+    }
+
+    if let Some(limiter) = &state.rate_limiter {
+        limiter
+            .check(&payload.source.client)
+            .map_err(|wait| AppError::RateLimited {
+                retry_after_ms: wait.as_millis().try_into().unwrap_or(u64::MAX),
+            })?;
+    }
+
+    let mut warnings = check_insert_request(&state, &mut payload)?;
+    if !warnings.is_empty() {
+        warn!(?warnings, client = %payload.source.client, "Relaying job despite validation errors");
+    }
+
+    let provider_defaults = payload
+        .target
+        .as_ref()
+        .and_then(|t| t.provider.as_ref())
+        .and_then(|p| config.provider_default_metadata.get(p))
+        .and_then(|v| v.as_object());
+    let mut defaults = Vec::with_capacity(2);
+    defaults.extend(provider_defaults);
+    defaults.push(&config.default_metadata);
+    payload.merge_default_metadata(&defaults);
+    payload.apply_transforms(&config.text_transforms);
+
+    if query.echo {
+        let metadata = payload
+            .metadata
+            .get_or_insert_with(|| serde_json::json!({}));
+        match metadata.as_object_mut() {
+            Some(object) => {
+                object.insert(ECHO_METADATA_KEY.to_string(), true.into());
+            }
+            None => {
+                return Err(AppError::InvalidRequest {
+                    reason: "echo requires metadata to be an object".to_string(),
+                })
+            }
+        }
+    }
+
+    let callback_url = payload
+        .callback_url
+        .as_deref()
+        .map(|url| callback::validate_callback_url(url, &config.allowed_callback_hosts))
+        .transpose()?;
+
+    let priority = payload.priority.unwrap_or_default();
+    let required_capability = payload.required_capability.clone();
+    let timeout_ms = payload.timeout_ms;
+    let broadcast = payload.broadcast;
+
+    // With a persistent queue, hold the job until a sink registers or maintenance ends instead
+    // of failing it. Broadcasts are not queued, since the sinks they would reach are not known
+    // yet.
+    let banner = state.sink_manager.maintenance_banner();
+    if broadcast && banner.is_some() {
+        return Err(AppError::Maintenance);
+    }
+    if state.sink_manager.would_queue(broadcast) {
+        state.sink_manager.enqueue(QueuedJob {
+            id: job_id.clone(),
+            payload: payload.into(),
+            priority,
+            required_capability,
+            timeout_ms,
+            queued_at: Utc::now(),
+        })?;
+        info!(job_id = %job_id, maintenance = banner.is_some(), "Queued job for replay");
+
+        let mut response = serde_json::json!({
+            "job_id": job_id,
+            "request_id": job_id,
+            "status": "queued",
+        });
+        if !warnings.is_empty() {
+            response["warnings"] = warnings.into();
+        }
+        if let Some(banner) = banner {
+            response["banner"] = banner.into();
+        }
+        return Ok((StatusCode::ACCEPTED, headers, Json(response)));
+    }
 
     // Check if sink is required and available
-    if state.config.require_sink && !state.sink_manager.has_active_sink() {
+    if config.require_sink && !state.sink_manager.has_active_sink() {
         warn!("Job rejected: no sink available and require_sink is true");
         return Err(AppError::NoSink);
     }
 
-    let job_id = Uuid::new_v4().to_string();
-    let ack = state
+    let timeout = timeout_ms.map(std::time::Duration::from_millis);
+
+    if !query.wait {
+        // Record the job before responding so an immediate poll finds it
+        let job = state.sink_manager.track_new_job(&job_id)?;
+        let mut sent = job.subscribe_sent();
+        let cancel = job.cancel_signal().clone();
+        let sink_manager = Arc::clone(&state.sink_manager);
+        let background_job_id = job_id.clone();
+        tokio::spawn(async move {
+            let capability = required_capability.as_deref();
+            let outcome = if broadcast {
+                let result = sink_manager
+                    .broadcast_job(job, payload.into(), priority, capability, timeout)
+                    .await;
+                match &result {
+                    Ok(_) => info!(job_id = %background_job_id, "Background broadcast acked"),
+                    Err(e) => {
+                        warn!(job_id = %background_job_id, error = %e, "Background broadcast failed")
+                    }
+                }
+                broadcast_outcome(&background_job_id, &result)
+            } else {
+                let result = sink_manager
+                    .dispatch_tracked_job(job, payload.into(), priority, capability, timeout)
+                    .await;
+                match &result {
+                    Ok(ack) => {
+                        info!(job_id = %background_job_id, status = %ack.status, "Background job acked")
+                    }
+                    Err(e) => {
+                        warn!(job_id = %background_job_id, error = %e, "Background job failed")
+                    }
+                }
+                callback_outcome(&background_job_id, &result)
+            };
+            if let Some(url) = callback_url {
+                callback::spawn_delivery(url, outcome);
+            }
+        }
+        .instrument(Span::current()));
+
+        // Only accept the job once its frame is queued for a sink, so a dispatch that fails or
+        // stalls before then is reported rather than left for the client to discover
+        let queued = matches!(
+            tokio::time::timeout(config.send_timeout, sent.wait_for(|sent| *sent)).await,
+            Ok(Ok(_))
+        );
+        if !queued {
+            cancel.cancel();
+            warn!(job_id = %job_id, "Background job was not queued for a sink in time");
+            return Err(AppError::NoSink);
+        }
+
+        let mut response = serde_json::json!({
+            "job_id": job_id,
+            "request_id": job_id,
+            "status": "pending",
+        });
+        if !warnings.is_empty() {
+            response["warnings"] = warnings.into();
+        }
+        return Ok((StatusCode::ACCEPTED, headers, Json(response)));
+    }
+
+    if broadcast {
+        let job = state.sink_manager.track_new_job(&job_id)?;
+        let result = state
+            .sink_manager
+            .broadcast_job(
+                job,
+                payload.into(),
+                priority,
+                required_capability.as_deref(),
+                timeout,
+            )
+            .await;
+        let mut response = broadcast_outcome(&job_id, &result);
+        if let Some(url) = callback_url {
+            callback::spawn_delivery(url, response.clone());
+        }
+
+        let outcomes = result?;
+        let delivered = outcomes.iter().any(|o| o.status == AckStatus::Ok);
+        info!(job_id = %job_id, sinks = outcomes.len(), delivered, "Broadcast job acked");
+        response["request_id"] = job_id.into();
+        if !warnings.is_empty() {
+            response["warnings"] = warnings.into();
+        }
+        let code = if delivered {
+            StatusCode::OK
+        } else {
+            StatusCode::from_u16(config.failed_ack_status).unwrap_or(StatusCode::BAD_GATEWAY)
+        };
+        return Ok((code, headers, Json(response)));
+    }
+
+    let job = state.sink_manager.track_new_job(&job_id)?;
+    let result = state
         .sink_manager
-        .dispatch_job(
-            job_id.clone(),
-            payload.text.clone(),
-            payload.placement.clone(),
-            payload.source.clone(),
-            payload.target.clone(),
-            payload.metadata.clone(),
+        .dispatch_tracked_job(
+            job,
+            payload.into(),
+            priority,
+            required_capability.as_deref(),
+            timeout,
         )
-        .await?;
+        .await;
 
-    let AckResponse { status, error } = ack;
+    if let Some(url) = callback_url {
+        callback::spawn_delivery(url, callback_outcome(&job_id, &result));
+    }
+
+    let AckResponse {
+        status,
+        error,
+        echo,
+        warnings: sink_warnings,
+        downgrade,
+        retries,
+        ..
+    } = result?;
+    warnings.extend(sink_warnings);
+    let downgrade = downgrade.map(serde_json::to_value).transpose()?;
 
     match status {
         AckStatus::Ok => {
             info!(job_id = %job_id, "Job delivered successfully");
-            let response = serde_json::json!({
+            let mut response = serde_json::json!({
                 "job_id": job_id,
+                "request_id": job_id,
                 "status": "ok",
             });
-            Ok((StatusCode::OK, Json(response)))
+            if !warnings.is_empty() {
+                response["warnings"] = warnings.into();
+            }
+            if let Some(echo) = echo {
+                response["echo"] = echo;
+            }
+            if let Some(downgrade) = downgrade {
+                response["downgrade"] = downgrade;
+            }
+            if retries > 0 {
+                response["retries"] = retries.into();
+            }
+            Ok((StatusCode::OK, headers, Json(response)))
         }
-        AckStatus::Retry | AckStatus::Failed => {
+        AckStatus::Retry | AckStatus::Failed | AckStatus::Cancelled => {
             warn!(job_id = %job_id, status = ?status, error = ?error, "Sink reported failure");
-            let response = serde_json::json!({
+            let mut response = serde_json::json!({
                 "job_id": job_id,
+                "request_id": job_id,
                 "status": status.to_string(),
                 "error": error,
             });
-            Ok((StatusCode::BAD_GATEWAY, Json(response)))
+            if !warnings.is_empty() {
+                response["warnings"] = warnings.into();
+            }
+            if let Some(downgrade) = downgrade {
+                response["downgrade"] = downgrade;
+            }
+            if retries > 0 {
+                response["retries"] = retries.into();
+            }
+
+            // Retry is transient, so tell clients when to come back; Failed is terminal
+            let code = match status {
+                AckStatus::Retry => {
+                    headers.insert(header::RETRY_AFTER, config.ack_retry_after_secs.into());
+                    StatusCode::SERVICE_UNAVAILABLE
+                }
+                AckStatus::Cancelled => StatusCode::CONFLICT,
+                _ => StatusCode::from_u16(config.failed_ack_status)
+                    .unwrap_or(StatusCode::BAD_GATEWAY),
+            };
+            Ok((code, headers, Json(response)))
         }
     }
 }
 
-pub async fn websocket_handler(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
-    ws.on_upgrade(move |socket| async move {
+/// Key under which `?dedupe=true` collapses batch entries: a hash of the target provider and
+/// text. Entries without a text string are never collapsed; they are rejected on their own.
+fn dedupe_key(body: &serde_json::Value) -> Option<[u8; 32]> {
+    let text = body.get("text")?.as_str()?;
+    let provider = body
+        .pointer("/target/provider")
+        .and_then(|p| p.as_str())
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    // Length-prefixed so that no provider and text pair can run into another
+    hasher.update((provider.len() as u64).to_le_bytes());
+    hasher.update(provider);
+    hasher.update(text);
+    Some(hasher.finalize().into())
+}
+
+/// Submits each job of a JSON array in order, as `POST /v1/insert` would, and answers
+/// `207 Multi-Status` with one result per job in request order. A rejected job does not stop the
+/// ones after it; its result has status `rejected` and the error's `code`. With `?dedupe=true`,
+/// an entry repeating an earlier one's provider and text is not dispatched again but reports
+/// the earlier entry's result.
+pub async fn insert_batch(
+    State(state): State<AppState>,
+    Query(query): Query<BatchQuery>,
+    Json(bodies): Json<Vec<serde_json::Value>>,
+) -> Result<impl IntoResponse, AppError> {
+    if state.is_draining() {
+        return Err(AppError::Draining);
+    }
+    let max_batch_size = state.config.load().max_batch_size;
+    if bodies.len() > max_batch_size {
+        return Err(AppError::BatchTooLarge {
+            size: bodies.len(),
+            max: max_batch_size,
+        });
+    }
+
+    let insert_query = query.insert_query();
+    let mut results: Vec<serde_json::Value> = Vec::with_capacity(bodies.len());
+    let mut first_index: HashMap<[u8; 32], usize> = HashMap::new();
+    for body in bodies {
+        if query.dedupe {
+            if let Some(key) = dedupe_key(&body) {
+                if let Some(&index) = first_index.get(&key) {
+                    debug!(duplicate_of = index, "Collapsing duplicate batch entry");
+                    results.push(results[index].clone());
+                    continue;
+                }
+                first_index.insert(key, results.len());
+            }
+        }
+
+        state.sink_manager.metrics().job_received();
+        let job_id = Uuid::new_v4().to_string();
+        let span = jobs::job_span(&job_id);
+        let result = submit_insert(state.clone(), insert_query.clone(), job_id.clone(), body)
+            .instrument(span)
+            .await;
+
+        let (status, mut response) = match result {
+            Ok((status, _, Json(response))) => (status, response),
+            Err(e) => {
+                let response = serde_json::json!({
+                    "job_id": job_id,
+                    "status": "rejected",
+                    "error": e.to_string(),
+                    "code": e.code().as_str(),
+                });
+                (e.into_response().status(), response)
+            }
+        };
+        response["http_status"] = status.as_u16().into();
+        results.push(response);
+    }
+
+    Ok((
+        StatusCode::MULTI_STATUS,
+        Json(serde_json::json!({ "results": results })),
+    ))
+}
+
+/// Dry run of `POST /v1/insert`: applies the same checks and sink routing, but never dispatches
+/// or queues the job, and does not count against the client's rate limit.
+pub async fn validate_insert(
+    State(state): State<AppState>,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    if state.is_draining() {
+        return Err(AppError::Draining);
+    }
+
+    let mut payload = parse_insert_body(&state, body)?;
+    let warnings = check_insert_request(&state, &mut payload)?;
+    if let Some(url) = payload.callback_url.as_deref() {
+        callback::validate_callback_url(url, &state.config.load().allowed_callback_hosts)?;
+    }
+
+    if payload.broadcast && state.sink_manager.maintenance_banner().is_some() {
+        return Err(AppError::Maintenance);
+    }
+    let mut response = if state.sink_manager.would_queue(payload.broadcast) {
+        serde_json::json!({ "valid": true, "would_route_to": null, "would_queue": true })
+    } else {
+        let target = payload.target.as_ref();
+        let sink_id = state
+            .sink_manager
+            .route(
+                target.and_then(|t| t.provider.as_deref()),
+                target.and_then(|t| t.session_policy.as_ref()),
+                payload.required_capability.as_deref(),
+                payload.mode,
+            )
+            .await?;
+        serde_json::json!({ "valid": true, "would_route_to": sink_id })
+    };
+    if !warnings.is_empty() {
+        response["warnings"] = warnings.into();
+    }
+    Ok(Json(response))
+}
+
+/// Starts a drain and returns at once. Calling it again is harmless, so operators can poll it
+/// until `pending_jobs` reaches zero before stopping the daemon.
+pub async fn start_drain(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<DrainResponse>, AppError> {
+    require_admin(&state.config.load(), &headers)?;
+
+    if !state.draining.swap(true, Ordering::Relaxed) {
+        info!("Draining: no longer accepting new jobs");
+    }
+
+    Ok(Json(DrainResponse {
+        draining: true,
+        pending_jobs: state.sink_manager.jobs().pending(),
+    }))
+}
+
+/// Turns maintenance mode on or off. While it is on, new inserts are accepted into the job queue
+/// with a banner and held there; turning it off replays them. Requires `persist_queue`, since
+/// otherwise there is no queue to hold them in.
+pub async fn set_maintenance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<MaintenanceRequest>,
+) -> Result<Json<MaintenanceResponse>, AppError> {
+    let config = state.config.load();
+    require_admin(&config, &headers)?;
+
+    let Some(queue) = state.sink_manager.queue() else {
+        return Err(AppError::InvalidRequest {
+            reason: "Maintenance mode requires server.persist_queue".to_string(),
+        });
+    };
+
+    let banner = payload.enabled.then(|| {
+        payload
+            .banner
+            .unwrap_or_else(|| config.maintenance_banner.clone())
+    });
+    match &banner {
+        Some(banner) => info!(banner = %banner, "Maintenance started; queuing new jobs"),
+        None => info!(
+            queued_jobs = queue.len(),
+            "Maintenance ended; replaying queued jobs"
+        ),
+    }
+    state.sink_manager.set_maintenance(banner.clone());
+
+    Ok(Json(MaintenanceResponse {
+        maintenance: banner.is_some(),
+        banner,
+        queued_jobs: queue.len(),
+    }))
+}
+
+pub async fn set_log_level(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LogLevelRequest>,
+) -> Result<Json<LogLevelResponse>, AppError> {
+    require_admin(&state.config.load(), &headers)?;
+
+    let level = payload
+        .level
+        .parse::<LevelFilter>()
+        .map_err(|_| AppError::InvalidRequest {
+            reason: format!("Invalid log level: {}", payload.level),
+        })?;
+
+    let handle = state.log_reload.as_ref().ok_or_else(|| {
+        AppError::Config(ConfigError::Message(
+            "Log level reloading is not available".to_string(),
+        ))
+    })?;
+    handle
+        .reload(env_filter(&level.to_string())?)
+        .map_err(|e| {
+            AppError::Config(ConfigError::Message(format!(
+                "Failed to update log level: {}",
+                e
+            )))
+        })?;
+
+    info!(level = %level, "Log level changed");
+    Ok(Json(LogLevelResponse {
+        level: level.to_string(),
+    }))
+}
+
+pub async fn websocket_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    // Checked again here so the upgrade can never be accepted without the token, even if the
+    // route is mounted without `check_client_auth`
+    let config = state.config.load();
+    require_client(&config, &headers)?;
+
+    // Browsers always send an Origin on upgrades, so this keeps arbitrary web pages from
+    // registering as a sink. Native sinks send none and are not affected.
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        if !config.ws_origin_allowed(origin) {
+            let origin = String::from_utf8_lossy(origin.as_bytes()).into_owned();
+            warn!(origin = %origin, "Rejected sink WebSocket from disallowed origin");
+            return Err(AppError::OriginNotAllowed { origin });
+        }
+    }
+
+    Ok(ws.on_upgrade(move |socket| async move {
         if let Err(e) = state.sink_manager.handle_websocket(socket).await {
             warn!("WebSocket error: {}", e);
         }
-    })
+    }))
+}
+
+/// Body POSTed to a job's `callback_url` once dispatch completes.
+fn callback_outcome(job_id: &str, result: &AppResult<AckResponse>) -> serde_json::Value {
+    match result {
+        Ok(ack) => serde_json::json!({
+            "job_id": job_id,
+            "status": ack.status.to_string(),
+            "error": ack.error,
+        }),
+        Err(e) => serde_json::json!({
+            "job_id": job_id,
+            "status": "error",
+            "error": e.to_string(),
+        }),
+    }
+}
+
+/// Outcome of a broadcast job, as returned to the client and sent to its callback. It is `ok`
+/// when any sink inserted the job, with `partial` set unless every sink did.
+fn broadcast_outcome(job_id: &str, result: &AppResult<Vec<SinkOutcome>>) -> serde_json::Value {
+    match result {
+        Ok(outcomes) => {
+            let delivered = outcomes
+                .iter()
+                .filter(|o| o.status == AckStatus::Ok)
+                .count();
+            serde_json::json!({
+                "job_id": job_id,
+                "status": if delivered > 0 { "ok" } else { "failed" },
+                "partial": delivered > 0 && delivered < outcomes.len(),
+                "results": outcomes,
+            })
+        }
+        Err(e) => serde_json::json!({
+            "job_id": job_id,
+            "status": "error",
+            "error": e.to_string(),
+        }),
+    }
+}
+
+/// Rejects an insert whose declared `Content-Length` already exceeds `max_job_bytes` before its
+/// body is read. Hyper sends `100 Continue` only once the body is polled, so clients using
+/// `Expect: 100-continue` never transfer an oversized body; bodies without a length are cut off
+/// incrementally once they pass the limit. The limit is read per request, so it follows reloads;
+/// routes using this should disable the router's fixed `DefaultBodyLimit`.
+pub async fn check_insert_body(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let max_job_bytes = state.config.load().max_job_bytes;
+    limit_body(max_job_bytes, request, next).await
+}
+
+/// Like `check_insert_body`, for a batch of up to `max_batch_size` jobs of `max_job_bytes` each.
+pub async fn check_batch_body(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let config = state.config.load();
+    let max_bytes = config.max_job_bytes.saturating_mul(config.max_batch_size);
+    limit_body(max_bytes, request, next).await
+}
+
+async fn limit_body(max_bytes: usize, request: Request, next: Next) -> Result<Response, AppError> {
+    if let Some(expect) = request.headers().get(header::EXPECT) {
+        if !expect.as_bytes().eq_ignore_ascii_case(b"100-continue") {
+            return Err(AppError::ExpectationFailed {
+                expectation: String::from_utf8_lossy(expect.as_bytes()).into_owned(),
+            });
+        }
+    }
+
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if let Some(size) = declared.filter(|size| *size > max_bytes) {
+        return Err(AppError::PayloadTooLarge {
+            size,
+            max: max_bytes,
+        });
+    }
+
+    let request = request.map(|body| Body::new(Limited::new(body, max_bytes)));
+    Ok(next.run(request).await)
+}
+
+/// Maps errors raised by the load-shedding layer in front of the insert route. The wrapped
+/// handler is infallible, so the only error that can surface here is the shed request.
+pub async fn handle_overload(err: BoxError) -> AppError {
+    warn!("Request shed: {}", err);
+    AppError::Overloaded
 }
+
 // Error handling for HTTP responses
+/// `Retry-After` seconds suggested when a sink is at `max_inflight_per_sink`; acks usually take
+/// well under this.
+const SINK_BUSY_RETRY_AFTER_SECS: u64 = 1;
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, message) = match &self {
             AppError::NoSink => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::NoMatchingSink { .. } => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::SinkDisconnectedMidJob { .. } => (StatusCode::BAD_GATEWAY, self.to_string()),
             AppError::InvalidRequest { .. } => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::PayloadTooLarge { .. } => (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()),
+            AppError::BatchTooLarge { .. } => (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()),
+            AppError::ExpectationFailed { .. } => {
+                (StatusCode::EXPECTATION_FAILED, self.to_string())
+            }
             AppError::Serialization(_) => (StatusCode::BAD_REQUEST, "Invalid JSON".to_string()),
             AppError::Config(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Configuration error".to_string(),
             ),
             AppError::DispatchTimeout { .. } => (StatusCode::GATEWAY_TIMEOUT, self.to_string()),
+            AppError::Overloaded => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+            AppError::SinkBusy { .. } => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::Draining => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::Maintenance => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::MemoryPressure { .. } => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::BlockedContent => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
+            AppError::OriginNotAllowed { .. } => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::JobNotFound { .. } => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::JobNotCancellable { .. } => (StatusCode::CONFLICT, self.to_string()),
+            AppError::RequestIdInUse { .. } => (StatusCode::CONFLICT, self.to_string()),
+            AppError::UnsupportedCapability { .. } => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string())
+            }
+            AppError::NoReusableSession { .. } => (StatusCode::CONFLICT, self.to_string()),
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal server error".to_string(),
@@ -125,26 +1171,40 @@ impl IntoResponse for AppError {
 
         let body = serde_json::json!({
             "error": message,
+            "code": self.code().as_str(),
             "timestamp": Utc::now(),
         });
 
-        (status, Json(body)).into_response()
+        let mut headers = HeaderMap::new();
+        match self {
+            AppError::RateLimited { retry_after_ms } => {
+                // Retry-After has whole-second resolution, so round up rather than invite a
+                // retry that is still too early
+                headers.insert(
+                    header::RETRY_AFTER,
+                    retry_after_ms.div_ceil(1000).max(1).into(),
+                );
+            }
+            AppError::SinkBusy { .. } => {
+                headers.insert(header::RETRY_AFTER, SINK_BUSY_RETRY_AFTER_SECS.into());
+            }
+            _ => {}
+        }
+
+        (status, headers, Json(body)).into_response()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::{SinkConnection, SourceInfo};
+    use crate::models::{SinkConnection, SourceInfo, TargetSpec};
+    use crate::queue::JobQueue;
 
     fn create_test_state() -> AppState {
-        let config = ServerConfig::default();
-        let sink_manager = Arc::new(SinkManager::new(config.clone()));
+        let sink_manager = Arc::new(SinkManager::new(ServerConfig::default()));
 
-        AppState {
-            sink_manager,
-            config,
-        }
+        AppState::new(sink_manager).unwrap()
     }
 
     fn create_test_request() -> InsertTextRequest {
@@ -159,13 +1219,122 @@ mod tests {
             placement: None,
             target: None,
             metadata: Some(serde_json::json!({"test": "data"})),
+            ..Default::default()
         }
     }
 
+    async fn post_insert(
+        state: AppState,
+        request: InsertTextRequest,
+    ) -> Result<Response, AppError> {
+        let body = serde_json::to_value(request).unwrap();
+        insert_job(
+            State(state),
+            Query(InsertQuery::default()),
+            HeaderMap::new(),
+            Json(body),
+        )
+        .await
+        .map(IntoResponse::into_response)
+    }
+
+    #[tokio::test]
+    async fn test_error_body_carries_stable_code() {
+        for (error, code) in [
+            (AppError::NoSink, "no_sink"),
+            (
+                AppError::DispatchTimeout { timeout_ms: 100 },
+                "dispatch_timeout",
+            ),
+            (
+                AppError::PayloadTooLarge { size: 2, max: 1 },
+                "payload_too_large",
+            ),
+            (
+                AppError::InvalidRequest {
+                    reason: "bad".to_string(),
+                },
+                "invalid_request",
+            ),
+            (AppError::Io(std::io::Error::other("disk")), "internal"),
+        ] {
+            let response = error.into_response();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["code"], code);
+            assert!(body["error"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_insert_rate_limited_after_burst() {
+        let config = ServerConfig {
+            rate_limit_per_sec: 1,
+            rate_limit_burst: 3,
+            ..Default::default()
+        };
+        let state = AppState::new(Arc::new(SinkManager::new(config.clone()))).unwrap();
+
+        // Without a sink each insert fails, but it still spends the client's tokens
+        for _ in 0..3 {
+            let result = post_insert(state.clone(), create_test_request()).await;
+            assert!(matches!(result, Err(AppError::NoSink)));
+        }
+        let error = post_insert(state.clone(), create_test_request())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::RateLimited { retry_after_ms } if retry_after_ms > 0));
+
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        // Other clients have buckets of their own
+        let mut other = create_test_request();
+        other.source.client = "other".to_string();
+        let result = post_insert(state, other).await;
+        assert!(matches!(result, Err(AppError::NoSink)));
+    }
+
     #[tokio::test]
     async fn test_health_endpoint() {
-        let response = health().await;
+        let state = create_test_state();
+        let response = health(State(state.clone())).await;
         assert!(response.0.ok);
+        assert!(!response.0.sink_connected);
+        assert_eq!(response.0.sink_count, 0);
+        assert_eq!(response.0.last_pong_age_ms, None);
+
+        state
+            .sink_manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        let response = health(State(state)).await;
+        assert!(response.0.sink_connected);
+        assert_eq!(response.0.sink_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ready_requires_sink_only_when_configured() {
+        assert!(ready(State(create_test_state())).await.is_ok());
+
+        let config = ServerConfig {
+            require_sink: true,
+            ..Default::default()
+        };
+        let state = AppState::new(Arc::new(SinkManager::new(config.clone()))).unwrap();
+        let result = ready(State(state.clone())).await;
+        assert!(matches!(result, Err(AppError::NoSink)));
+        // Liveness is unaffected
+        assert!(health(State(state.clone())).await.0.ok);
+
+        state
+            .sink_manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        assert!(ready(State(state)).await.is_ok());
     }
 
     #[tokio::test]
@@ -173,28 +1342,531 @@ mod tests {
         let state = create_test_state();
         let request = create_test_request();
 
-        let result = insert_job(State(state), Json(request)).await;
+        let result = post_insert(state, request).await;
+
+        assert!(matches!(result, Err(AppError::NoSink)));
+    }
+
+    #[tokio::test]
+    async fn test_insert_job_unsupported_capability_rejected_up_front() {
+        let state = create_test_state();
+        let connection = SinkConnection::new(
+            vec!["append".to_string()],
+            vec!["chatgpt".to_string()],
+            "1.2.3".to_string(),
+        );
+        state.sink_manager.set_test_sink(connection).await;
+
+        let mut request = create_test_request();
+        request.required_capability = Some("cursor".to_string());
+
+        // The test sink never acks, so only an up-front rejection can return in time
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            post_insert(state, request),
+        )
+        .await
+        .expect("capability was not checked before dispatch");
+
+        let err = result.unwrap_err();
+        assert!(matches!(
+            &err,
+            AppError::UnsupportedCapability { capability, .. } if capability == "cursor"
+        ));
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_insert_job_unknown_field_policy() {
+        let mut body = serde_json::to_value(create_test_request()).unwrap();
+        body["placment"] = serde_json::json!({"type": "top"});
 
+        // Ignored by default; the request proceeds to dispatch
+        let state = create_test_state();
+        let result = insert_job(
+            State(state),
+            Query(InsertQuery::default()),
+            HeaderMap::new(),
+            Json(body.clone()),
+        )
+        .await
+        .map(IntoResponse::into_response);
         assert!(matches!(result, Err(AppError::NoSink)));
+
+        let state = create_test_state();
+        state.config.store(Arc::new(ServerConfig {
+            reject_unknown_request_fields: true,
+            ..Default::default()
+        }));
+        let result = insert_job(
+            State(state),
+            Query(InsertQuery::default()),
+            HeaderMap::new(),
+            Json(body),
+        )
+        .await
+        .map(IntoResponse::into_response);
+        assert!(matches!(
+            result,
+            Err(AppError::InvalidRequest { reason }) if reason == "Unknown field: placment"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_insert_job_request_id() {
+        let state = create_test_state();
+        state
+            .sink_manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        let body = serde_json::to_value(create_test_request()).unwrap();
+        // Answered before the ack, which the test sink never sends
+        let query = InsertQuery {
+            wait: false,
+            ..Default::default()
+        };
+        let insert = |headers: HeaderMap| {
+            insert_job(
+                State(state.clone()),
+                Query(query.clone()),
+                headers,
+                Json(body.clone()),
+            )
+        };
+        let read = |response: Response| async move {
+            let header = response.headers()[REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (header, body)
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("trace-42"));
+        let response = insert(headers.clone()).await.unwrap().into_response();
+        let (header, body) = read(response).await;
+        assert_eq!(header, "trace-42");
+        assert_eq!(body["request_id"], "trace-42");
+        assert_eq!(body["job_id"], "trace-42");
+        assert!(state.sink_manager.jobs().get("trace-42").is_some());
+
+        // The id stays reserved while its job is pending
+        let error = insert(headers)
+            .await
+            .map(IntoResponse::into_response)
+            .unwrap_err();
+        assert!(matches!(error, AppError::RequestIdInUse { ref id } if id == "trace-42"));
+        assert_eq!(error.into_response().status(), StatusCode::CONFLICT);
+
+        let response = insert(HeaderMap::new()).await.unwrap().into_response();
+        let (header, body) = read(response).await;
+        assert!(Uuid::parse_str(&header).is_ok());
+        assert_eq!(body["request_id"], header);
+        assert_eq!(body["job_id"], header);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("no spaces"));
+        let result = insert(headers).await.map(IntoResponse::into_response);
+        assert!(matches!(result, Err(AppError::InvalidRequest { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_inserts_claim_request_id_once() {
+        let state = create_test_state();
+        state
+            .sink_manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        let insert = || {
+            let mut headers = HeaderMap::new();
+            headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("shared-id"));
+            insert_job(
+                State(state.clone()),
+                Query(InsertQuery {
+                    wait: false,
+                    ..Default::default()
+                }),
+                headers,
+                Json(serde_json::to_value(create_test_request()).unwrap()),
+            )
+        };
+
+        let (first, second) = tokio::join!(insert(), insert());
+        let results = [
+            first.map(IntoResponse::into_response),
+            second.map(IntoResponse::into_response),
+        ];
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(results
+            .iter()
+            .any(|result| matches!(result, Err(AppError::RequestIdInUse { .. }))));
+        assert_eq!(state.sink_manager.jobs().pending(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_of_queued_job_is_in_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(dir.path().join("queue.jsonl"), 10).unwrap();
+        let sink_manager = SinkManager::new(ServerConfig::default()).with_queue(queue);
+        let state = AppState::new(Arc::new(sink_manager)).unwrap();
+        let insert = |wait: bool| {
+            let mut headers = HeaderMap::new();
+            headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("queued-id"));
+            insert_job(
+                State(state.clone()),
+                Query(InsertQuery {
+                    wait,
+                    ..Default::default()
+                }),
+                headers,
+                Json(serde_json::to_value(create_test_request()).unwrap()),
+            )
+        };
+
+        // Without a sink the job waits in the queue
+        let response = insert(true).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let result = insert(true).await.map(IntoResponse::into_response);
+        assert!(matches!(result, Err(AppError::RequestIdInUse { .. })));
+
+        // It stays claimed when a sink would take a new job at once
+        state
+            .sink_manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        let result = insert(false).await.map(IntoResponse::into_response);
+        assert!(matches!(result, Err(AppError::RequestIdInUse { .. })));
+        assert_eq!(state.sink_manager.queue().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_background_insert_rejected_when_sink_channel_closed() {
+        let state = create_test_state();
+        state
+            .sink_manager
+            .set_closed_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        let result = insert_job(
+            State(state.clone()),
+            Query(InsertQuery {
+                wait: false,
+                ..Default::default()
+            }),
+            HeaderMap::new(),
+            Json(serde_json::to_value(create_test_request()).unwrap()),
+        )
+        .await
+        .map(IntoResponse::into_response);
+        assert!(matches!(result, Err(AppError::NoSink)));
+        assert_eq!(state.sink_manager.jobs().pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replays_first_response() {
+        let state = create_test_state();
+        state
+            .sink_manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        let body = serde_json::to_value(create_test_request()).unwrap();
+        let query = InsertQuery {
+            wait: false,
+            ..Default::default()
+        };
+        let insert = |key: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_static(key));
+            insert_job(
+                State(state.clone()),
+                Query(query.clone()),
+                headers,
+                Json(body.clone()),
+            )
+        };
+        let read = |response: Response| async move {
+            let replayed = response.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (body["job_id"].as_str().unwrap().to_string(), replayed)
+        };
+
+        let (first, replayed) = read(insert("retry-me").await.unwrap().into_response()).await;
+        assert!(!replayed);
+        let (again, replayed) = read(insert("retry-me").await.unwrap().into_response()).await;
+        assert!(replayed);
+        assert_eq!(again, first);
+
+        let (other, replayed) = read(insert("fresh").await.unwrap().into_response()).await;
+        assert!(!replayed);
+        assert_ne!(other, first);
+        assert_eq!(state.sink_manager.jobs().pending(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_idempotent_insert_can_be_retried() {
+        let state = create_test_state();
+        let mut headers = HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_static("k"));
+        let insert = || {
+            insert_job(
+                State(state.clone()),
+                Query(InsertQuery {
+                    wait: false,
+                    ..Default::default()
+                }),
+                headers.clone(),
+                Json(serde_json::to_value(create_test_request()).unwrap()),
+            )
+        };
+
+        state.config.store(Arc::new(ServerConfig {
+            require_sink: true,
+            ..Default::default()
+        }));
+        let result = insert_job(
+            State(state.clone()),
+            Query(InsertQuery::default()),
+            headers.clone(),
+            Json(serde_json::to_value(create_test_request()).unwrap()),
+        )
+        .await
+        .map(IntoResponse::into_response);
+        assert!(matches!(result, Err(AppError::NoSink)));
+
+        state
+            .sink_manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.2.3".to_string()))
+            .await;
+        let response = insert().await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(!response.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_validate_insert_routes_without_dispatching() {
+        let state = create_test_state();
+        let validate = |request: InsertTextRequest| {
+            validate_insert(
+                State(state.clone()),
+                Json(serde_json::to_value(request).unwrap()),
+            )
+        };
+
+        let result = validate(create_test_request()).await;
+        assert!(matches!(result, Err(AppError::NoSink)));
+
+        let connection = SinkConnection::new(
+            vec!["insert".to_string()],
+            vec!["chatgpt".to_string()],
+            "1.2.3".to_string(),
+        );
+        let sink_id = connection.id;
+        state.sink_manager.set_test_sink(connection).await;
+
+        let Json(response) = validate(create_test_request()).await.unwrap();
+        assert_eq!(response["valid"], true);
+        assert_eq!(response["would_route_to"], sink_id.to_string());
+        assert_eq!(state.sink_manager.jobs().pending(), 0);
+
+        let mut request = create_test_request();
+        request.required_capability = Some("submit".to_string());
+        let result = validate(request).await;
+        assert!(matches!(
+            result,
+            Err(AppError::UnsupportedCapability { .. })
+        ));
+
+        let mut request = create_test_request();
+        request.text = String::new();
+        let result = validate(request).await;
+        assert!(matches!(result, Err(AppError::InvalidRequest { .. })));
     }
 
     #[tokio::test]
     async fn test_payload_too_large() {
-        let mut state = create_test_state();
-        state.config.max_job_bytes = 10; // Very small limit
+        let state = create_test_state();
+        state.config.store(Arc::new(ServerConfig {
+            max_job_bytes: 10, // Very small limit
+            ..Default::default()
+        }));
 
         let request = create_test_request();
 
-        let result = insert_job(State(state), Json(request)).await;
+        let result = post_insert(state, request).await;
 
         assert!(matches!(result, Err(AppError::PayloadTooLarge { .. })));
     }
 
+    #[tokio::test]
+    async fn test_batch_over_max_batch_size_rejected() {
+        let state = create_test_state();
+        state.config.store(Arc::new(ServerConfig {
+            max_batch_size: 2,
+            ..Default::default()
+        }));
+        let body = serde_json::to_value(create_test_request()).unwrap();
+
+        let result = insert_batch(
+            State(state),
+            Query(BatchQuery::default()),
+            Json(vec![body; 3]),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(AppError::BatchTooLarge { size: 3, max: 2 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_insert_job_rejects_disallowed_callback_host() {
+        let state = create_test_state();
+        state.config.store(Arc::new(ServerConfig {
+            allowed_callback_hosts: vec!["localhost".to_string()],
+            ..Default::default()
+        }));
+
+        let mut request = create_test_request();
+        request.callback_url = Some("http://internal.example/hook".to_string());
+
+        let result = post_insert(state, request).await;
+
+        assert!(matches!(
+            result,
+            Err(AppError::InvalidRequest { reason }) if reason.contains("not allowed")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_insert_job_blocks_denylisted_text() {
+        let config = ServerConfig {
+            text_denylist_patterns: vec![
+                r"-----BEGIN [A-Z ]*PRIVATE KEY-----".to_string(),
+                r"\bsk-[A-Za-z0-9]{20,}\b".to_string(),
+            ],
+            ..Default::default()
+        };
+        let sink_manager = Arc::new(SinkManager::new(config.clone()));
+        let state = AppState::new(sink_manager).unwrap();
+
+        let mut request = create_test_request();
+        request.text = "token = sk-abcdefghijklmnopqrstuvwxyz".to_string();
+        let result = post_insert(state.clone(), request).await;
+        assert!(matches!(result, Err(AppError::BlockedContent)));
+
+        // Clean text passes the filter and proceeds to dispatch
+        let result = post_insert(state, create_test_request()).await;
+        assert!(matches!(result, Err(AppError::NoSink)));
+    }
+
+    #[test]
+    fn test_require_client_only_when_token_configured() {
+        let mut config = ServerConfig::default();
+        let mut headers = HeaderMap::new();
+        assert!(require_client(&config, &headers).is_ok());
+
+        config.auth_token = Some("secret".to_string());
+        assert!(matches!(
+            require_client(&config, &headers),
+            Err(AppError::Unauthorized)
+        ));
+
+        headers.insert(header::AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        assert!(require_client(&config, &headers).is_err());
+        headers.insert(header::AUTHORIZATION, "Bearer secre".parse().unwrap());
+        assert!(require_client(&config, &headers).is_err());
+
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(require_client(&config, &headers).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level() {
+        let (filter, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let state = create_test_state().with_log_reload(handle.clone());
+        state.config.store(Arc::new(ServerConfig {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        }));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let request = |level: &str| {
+            Json(LogLevelRequest {
+                level: level.to_string(),
+            })
+        };
+
+        let response = set_log_level(State(state.clone()), headers.clone(), request("debug"))
+            .await
+            .unwrap();
+        assert_eq!(response.0.level, "debug");
+        assert_eq!(
+            handle.with_current(|f| f.to_string()).unwrap(),
+            env_filter("debug").unwrap().to_string()
+        );
+
+        let result = set_log_level(State(state.clone()), headers, request("verbose")).await;
+        assert!(matches!(result, Err(AppError::InvalidRequest { .. })));
+
+        let result = set_log_level(State(state), HeaderMap::new(), request("debug")).await;
+        assert!(matches!(result, Err(AppError::Unauthorized)));
+
+        drop(filter);
+    }
+
+    #[tokio::test]
+    async fn test_insert_job_lenient_validation() {
+        let mut request = create_test_request();
+        request.target = Some(TargetSpec {
+            provider: Some(String::new()),
+            session_policy: None,
+        });
+
+        let result = post_insert(create_test_state(), request.clone()).await;
+        assert!(matches!(result, Err(AppError::InvalidRequest { .. })));
+
+        // With lenient validation the job gets past validation and on to dispatch
+        let state = create_test_state();
+        state.config.store(Arc::new(ServerConfig {
+            lenient_validation: true,
+            ..Default::default()
+        }));
+        let result = post_insert(state.clone(), request.clone()).await;
+        assert!(matches!(result, Err(AppError::NoSink)));
+
+        request.text = " ".to_string();
+        let result = post_insert(state, request).await;
+        assert!(matches!(result, Err(AppError::InvalidRequest { .. })));
+    }
+
+    async fn providers_body(state: &AppState, name: Option<&str>) -> AppResult<serde_json::Value> {
+        let query = ProvidersQuery {
+            name: name.map(str::to_string),
+        };
+        let response = list_providers(State(state.clone()), Query(query)).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        Ok(serde_json::from_slice(&bytes).unwrap())
+    }
+
     #[tokio::test]
     async fn test_list_providers_no_sink() {
         let state = create_test_state();
 
-        let result = list_providers(State(state)).await;
+        let result = providers_body(&state, None).await;
 
         assert!(matches!(result, Err(AppError::NoSink)));
     }
@@ -207,8 +1879,67 @@ mod tests {
 
         state.sink_manager.set_test_sink(connection).await;
 
-        let response = list_providers(State(state)).await.unwrap();
+        let body = providers_body(&state, None).await.unwrap();
+
+        assert_eq!(body["providers"], serde_json::json!(providers));
+    }
+
+    #[tokio::test]
+    async fn test_provider_filter_reports_availability() {
+        let state = create_test_state();
+        let body = providers_body(&state, Some("chatgpt")).await.unwrap();
+        assert_eq!(body["available"], false);
+        assert_eq!(body["sink_ids"], serde_json::json!([]));
+
+        let connection = SinkConnection::new(vec![], vec!["chatgpt".to_string()], "1.2.3".into());
+        let sink_id = connection.id;
+        state.sink_manager.set_test_sink(connection).await;
+
+        let body = providers_body(&state, Some("chatgpt")).await.unwrap();
+        assert_eq!(body["name"], "chatgpt");
+        assert_eq!(body["available"], true);
+        assert_eq!(body["sink_ids"], serde_json::json!([sink_id]));
+        let body = providers_body(&state, Some("claude")).await.unwrap();
+        assert_eq!(body["available"], false);
+
+        let config = ServerConfig {
+            require_sink: true,
+            ..Default::default()
+        };
+        let state = AppState::new(Arc::new(SinkManager::new(config))).unwrap();
+        let result = providers_body(&state, Some("chatgpt")).await;
+        assert!(matches!(result, Err(AppError::NoSink)));
+    }
+
+    #[tokio::test]
+    async fn test_list_sinks_empty() {
+        let state = create_test_state();
+
+        let response = list_sinks(State(state)).await;
+
+        assert!(response.0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_sinks_with_sink() {
+        let state = create_test_state();
+        let connection = SinkConnection::new(
+            vec!["insert".to_string()],
+            vec!["chatgpt".to_string()],
+            "1.2.3".to_string(),
+        );
+        let id = connection.id;
+
+        state.sink_manager.set_test_sink(connection).await;
+
+        let response = list_sinks(State(state)).await;
 
-        assert_eq!(response.0.providers, providers);
+        assert_eq!(response.0.len(), 1);
+        let sink = &response.0[0];
+        assert_eq!(sink.connection.id, id);
+        assert_eq!(sink.connection.providers, vec!["chatgpt".to_string()]);
+        assert!(sink.ready);
+        assert!(!sink.awaiting_pong);
+        assert_eq!(sink.inflight_jobs, 0);
     }
 }
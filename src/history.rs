@@ -0,0 +1,256 @@
+//! SQLite history of finished jobs, kept beyond `job_retention` when `sqlite_path` is set.
+//!
+//! Jobs are handed to a writer thread over a bounded channel and committed in batches, so
+//! dispatch never waits on the database.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OpenFlags};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::jobs::JobSummary;
+use crate::models::JobsQuery;
+
+/// Finished jobs waiting for the writer. Once full, further jobs are dropped from the history
+/// rather than holding up the dispatch that finished them.
+const CHANNEL_CAPACITY: usize = 1024;
+/// Most jobs committed in one transaction.
+const MAX_BATCH: usize = 256;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    provider TEXT,
+    source TEXT,
+    status TEXT NOT NULL,
+    error TEXT,
+    inserted_chars INTEGER,
+    duration_ms INTEGER
+);
+CREATE INDEX IF NOT EXISTS jobs_created_at ON jobs (created_at);
+";
+
+enum Command {
+    Record(JobSummary),
+    Flush(oneshot::Sender<()>),
+}
+
+impl std::fmt::Debug for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Record(job) => f.debug_tuple("Record").field(&job.id).finish(),
+            Command::Flush(_) => f.write_str("Flush"),
+        }
+    }
+}
+
+/// Handle to the job history database. Clones share the same writer, which stops once every
+/// handle is dropped.
+#[derive(Debug, Clone)]
+pub struct JobHistory {
+    path: PathBuf,
+    tx: mpsc::Sender<Command>,
+}
+
+impl JobHistory {
+    /// Opens or creates the database at `path` and starts its writer.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut conn = Connection::open(&path).map_err(io::Error::other)?;
+        // WAL lets `GET /v1/jobs` read while the writer commits
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(io::Error::other)?;
+        conn.execute_batch(SCHEMA).map_err(io::Error::other)?;
+
+        let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+        thread::Builder::new()
+            .name("job-history".to_string())
+            .spawn(move || {
+                while let Some(command) = rx.blocking_recv() {
+                    let mut batch = Vec::new();
+                    let mut flushed = Vec::new();
+                    let mut next = Some(command);
+                    while let Some(command) = next.take() {
+                        match command {
+                            Command::Record(job) => batch.push(job),
+                            Command::Flush(done) => flushed.push(done),
+                        }
+                        if batch.len() < MAX_BATCH {
+                            next = rx.try_recv().ok();
+                        }
+                    }
+
+                    if let Err(e) = write_batch(&mut conn, &batch) {
+                        warn!(error = %e, jobs = batch.len(), "Failed to write job history");
+                    }
+                    for done in flushed {
+                        let _ = done.send(());
+                    }
+                }
+            })?;
+
+        Ok(Self { path, tx })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues `job` to be written.
+    pub fn record(&self, job: JobSummary) {
+        if let Err(e) = self.tx.try_send(Command::Record(job)) {
+            warn!(error = %e, "Job history writer is behind; dropping job from the history");
+        }
+    }
+
+    /// Waits until every job recorded so far has been committed.
+    pub async fn flush(&self) {
+        let (done, committed) = oneshot::channel();
+        if self.tx.send(Command::Flush(done)).await.is_ok() {
+            let _ = committed.await;
+        }
+    }
+
+    /// Jobs matching `query`, newest first. Blocks on the database, so call it off the runtime.
+    pub fn query(&self, query: &JobsQuery) -> io::Result<Vec<JobSummary>> {
+        let conn = Connection::open_with_flags(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(io::Error::other)?;
+        let mut statement = conn
+            .prepare(
+                "SELECT id, created_at, provider, source, status, error, inserted_chars, duration_ms
+                 FROM jobs
+                 WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR provider = ?2)
+                 ORDER BY created_at DESC
+                 LIMIT ?3",
+            )
+            .map_err(io::Error::other)?;
+
+        let status = query.status.map(|status| status.to_string());
+        let limit = i64::try_from(query.limit).unwrap_or(i64::MAX);
+        let rows = statement
+            .query_map(params![status, query.provider, limit], |row| {
+                let status: String = row.get(4)?;
+                Ok(JobSummary {
+                    id: row.get(0)?,
+                    created_at: row.get(1)?,
+                    provider: row.get(2)?,
+                    source: row.get(3)?,
+                    status: serde_json::from_value(serde_json::Value::String(status)).map_err(
+                        |e| rusqlite::Error::FromSqlConversionFailure(4, Type::Text, Box::new(e)),
+                    )?,
+                    error: row.get(5)?,
+                    inserted_chars: row.get(6)?,
+                    duration_ms: row.get(7)?,
+                })
+            })
+            .map_err(io::Error::other)?;
+        rows.collect::<Result<_, _>>().map_err(io::Error::other)
+    }
+}
+
+fn write_batch(conn: &mut Connection, jobs: &[JobSummary]) -> rusqlite::Result<()> {
+    if jobs.is_empty() {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT OR REPLACE INTO jobs
+             (id, created_at, provider, source, status, error, inserted_chars, duration_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for job in jobs {
+            insert.execute(params![
+                job.id,
+                job.created_at,
+                job.provider,
+                job.source,
+                job.status.to_string(),
+                job.error,
+                job.inserted_chars,
+                job.duration_ms,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::{JobStatus, JobStore};
+    use crate::websocket::InsertTextPayload;
+
+    fn payload(provider: Option<&str>, text: &str) -> InsertTextPayload {
+        serde_json::from_value(serde_json::json!({
+            "schema_version": "1.0",
+            "source": {"client": "nvim", "label": null, "path": null},
+            "text": text,
+            "placement": null,
+            "target": provider.map(|p| serde_json::json!({"provider": p, "session_policy": null})),
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_finished_jobs_are_queryable_from_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = JobHistory::open(dir.path().join("jobs.sqlite3")).unwrap();
+        let store = JobStore::new().with_history(history.clone());
+
+        let job = store.track("job-1");
+        job.describe(&payload(Some("chatgpt"), "hello"));
+        job.finish(JobStatus::Ok);
+
+        let job = store.track("job-2");
+        job.describe(&payload(None, "world"));
+        job.set_error("Editor not focused");
+        job.finish(JobStatus::Failed);
+
+        // Still pending, so not in the history yet
+        let _pending = store.track("job-3");
+
+        history.flush().await;
+        let all = history.query(&JobsQuery::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all, store.list(&JobsQuery::default())[1..]);
+
+        let ok = history
+            .query(&JobsQuery {
+                status: Some(JobStatus::Ok),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(ok.len(), 1);
+        assert_eq!(ok[0].id, "job-1");
+        assert_eq!(ok[0].provider.as_deref(), Some("chatgpt"));
+        assert_eq!(ok[0].source.as_deref(), Some("nvim"));
+        assert_eq!(ok[0].inserted_chars, Some(5));
+        assert!(ok[0].duration_ms.is_some());
+
+        let failed = history
+            .query(&JobsQuery {
+                status: Some(JobStatus::Failed),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(failed[0].id, "job-2");
+        assert_eq!(failed[0].error.as_deref(), Some("Editor not focused"));
+        assert_eq!(failed[0].inserted_chars, None);
+
+        // Reopening keeps the jobs written before
+        drop(store);
+        let reopened = JobHistory::open(history.path()).unwrap();
+        assert_eq!(reopened.query(&JobsQuery::default()).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::http::{HeaderMap, StatusCode};
+
+/// Response recorded for an idempotency key, replayed to retries of the same request.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: serde_json::Value,
+}
+
+/// What an earlier request with the same key left behind.
+#[derive(Debug, Clone)]
+pub enum Replay {
+    /// The earlier request is still being dispatched as `job_id`.
+    InProgress {
+        job_id: String,
+    },
+    Done(CachedResponse),
+}
+
+#[derive(Debug)]
+struct Entry {
+    created: Instant,
+    job_id: String,
+    response: Option<CachedResponse>,
+}
+
+impl Entry {
+    fn replay(&self) -> Replay {
+        match &self.response {
+            Some(response) => Replay::Done(response.clone()),
+            None => Replay::InProgress {
+                job_id: self.job_id.clone(),
+            },
+        }
+    }
+}
+
+/// Recent `Idempotency-Key`s with the outcome of the insert that first used them. Keys expire
+/// after `ttl`; once `capacity` keys are held, expired keys are dropped, then the oldest.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyCache {
+    /// Returns `None` when either value is zero, which disables deduplication.
+    pub fn new(ttl: Duration, capacity: usize) -> Option<Self> {
+        if ttl.is_zero() || capacity == 0 {
+            return None;
+        }
+        Some(Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// What an earlier request with `key` left behind, if it is still within the TTL.
+    pub fn get(&self, key: &str) -> Option<Replay> {
+        self.get_at(key, Instant::now())
+    }
+
+    /// Claims `key` for the job about to be dispatched as `job_id`. If another request claimed
+    /// it first, returns what that request left behind instead.
+    pub fn begin(&self, key: &str, job_id: &str) -> Option<Replay> {
+        self.begin_at(key, job_id, Instant::now())
+    }
+
+    /// Records the response to replay for `key`.
+    pub fn complete(&self, key: &str, response: CachedResponse) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    /// Releases `key` so a retry dispatches again, e.g. after the request was rejected.
+    pub fn forget(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn get_at(&self, key: &str, now: Instant) -> Option<Replay> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| now.duration_since(entry.created) < self.ttl)
+            .map(Entry::replay)
+    }
+
+    fn begin_at(&self, key: &str, job_id: &str, now: Instant) -> Option<Replay> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(key) {
+            if now.duration_since(entry.created) < self.ttl {
+                return Some(entry.replay());
+            }
+        }
+
+        if entries.len() >= self.capacity {
+            entries.retain(|_, entry| now.duration_since(entry.created) < self.ttl);
+        }
+        if entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.created)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key.to_string(),
+            Entry {
+                created: now,
+                job_id: job_id.to_string(),
+                response: None,
+            },
+        );
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: body.into(),
+        }
+    }
+
+    #[test]
+    fn test_zero_disables_deduplication() {
+        assert!(IdempotencyCache::new(Duration::ZERO, 10).is_none());
+        assert!(IdempotencyCache::new(Duration::from_secs(60), 0).is_none());
+    }
+
+    #[test]
+    fn test_keys_replay_until_expired_or_evicted() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 2).unwrap();
+        let start = Instant::now();
+
+        assert!(cache.begin_at("a", "job-a", start).is_none());
+        assert!(matches!(
+            cache.get_at("a", start),
+            Some(Replay::InProgress { job_id }) if job_id == "job-a"
+        ));
+        cache.complete("a", response("first"));
+        assert!(matches!(
+            cache.begin_at("a", "job-a2", start),
+            Some(Replay::Done(cached)) if cached.body == "first"
+        ));
+
+        let expired = start + Duration::from_secs(60);
+        assert!(cache.get_at("a", expired).is_none());
+        assert!(cache.begin_at("a", "job-a3", expired).is_none());
+
+        // Full of live keys, so the oldest goes
+        assert!(cache
+            .begin_at("b", "job-b", expired + Duration::from_secs(1))
+            .is_none());
+        let later = expired + Duration::from_secs(2);
+        assert!(cache.begin_at("c", "job-c", later).is_none());
+        assert!(cache.get_at("a", later).is_none());
+        assert!(cache.get_at("b", later).is_some());
+
+        cache.forget("b");
+        assert!(cache.get_at("b", later).is_none());
+    }
+}
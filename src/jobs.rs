@@ -0,0 +1,574 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{field, info, info_span, Span};
+use uuid::Uuid;
+
+#[cfg(feature = "sqlite")]
+use crate::history::JobHistory;
+use crate::models::JobsQuery;
+use crate::websocket::{AckStatus, InsertTextPayload};
+
+/// Upper bound on how often finished records are swept, so short retentions stay accurate
+/// without long ones sweeping needlessly often.
+const MAX_EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Ok,
+    Retry,
+    Failed,
+    Timeout,
+    Cancelled,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Ok => "ok",
+            JobStatus::Retry => "retry",
+            JobStatus::Failed => "failed",
+            JobStatus::Timeout => "timeout",
+            JobStatus::Cancelled => "cancelled",
+        })
+    }
+}
+
+impl From<&AckStatus> for JobStatus {
+    fn from(status: &AckStatus) -> Self {
+        match status {
+            AckStatus::Ok => JobStatus::Ok,
+            AckStatus::Retry => JobStatus::Retry,
+            AckStatus::Failed => JobStatus::Failed,
+            AckStatus::Cancelled => JobStatus::Cancelled,
+        }
+    }
+}
+
+/// Insertion progress last reported by the sink for a job it inserts in chunks.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JobProgress {
+    pub inserted_chars: usize,
+    pub total_chars: usize,
+}
+
+/// Outcome of a dispatched job as served by `GET /v1/jobs/:id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub status: JobStatus,
+    /// Sink the job was sent to; unset while it waits for one or if none was available.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sink_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<JobProgress>,
+    /// The job's `target.provider`, if it named one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// The `source.client` that submitted the job.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Why the job did not succeed, as reported by the sink or the daemon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time from the start of dispatch to the final status; unset while pending.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Length of the job's text in characters.
+    #[serde(skip)]
+    text_chars: usize,
+    /// When the job was tracked, on the monotonic clock so stats windows ignore clock jumps.
+    #[serde(skip, default = "Instant::now")]
+    created: Instant,
+    /// When the job left `pending`, measured on the monotonic clock for eviction.
+    #[serde(skip)]
+    finished: Option<Instant>,
+    #[serde(skip)]
+    cancel: CancelSignal,
+}
+
+impl JobRecord {
+    pub fn summary(&self) -> JobSummary {
+        JobSummary {
+            id: self.id.clone(),
+            created_at: self.created_at,
+            status: self.status,
+            provider: self.provider.clone(),
+            source: self.source.clone(),
+            error: self.error.clone(),
+            // Sinks only report progress for chunked insertions; an ok job inserted the rest
+            inserted_chars: self
+                .progress
+                .map(|progress| progress.inserted_chars)
+                .or((self.status == JobStatus::Ok).then_some(self.text_chars)),
+            duration_ms: self.duration_ms,
+        }
+    }
+}
+
+/// A job as listed by `GET /v1/jobs` and kept in the SQLite job history.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JobSummary {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub status: JobStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inserted_chars: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+/// Aggregates over the jobs created within a rolling window, as served by `GET /v1/stats`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobStats {
+    pub window_secs: u64,
+    pub total_jobs: usize,
+    /// Share of the finished jobs that were acked `ok`; unset until one finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_rate: Option<f64>,
+    /// Mean and 95th percentile of `duration_ms` over the finished jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_duration_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p95_duration_ms: Option<u64>,
+    pub by_status: BTreeMap<String, usize>,
+    /// Jobs that named a target provider, by provider.
+    pub by_provider: BTreeMap<String, usize>,
+}
+
+/// In-memory record of recent jobs. Finished records are dropped once they are older than the
+/// retention passed to `spawn_eviction`; pending records are kept until they finish.
+///
+/// Uses a std lock since no guard is held across an await, which lets `TrackedJob` finish its
+/// record from `Drop`.
+#[derive(Debug, Clone, Default)]
+pub struct JobStore {
+    records: Arc<RwLock<HashMap<String, JobRecord>>>,
+    /// Receives every finished job, when `sqlite_path` is set.
+    #[cfg(feature = "sqlite")]
+    history: Option<JobHistory>,
+}
+
+/// Span that a job's log lines are emitted in. `provider` and `sink_id` are recorded once they
+/// are known; futures working on the job should be run with
+/// [`Instrument`](tracing::Instrument) rather than by entering the span.
+pub fn job_span(job_id: &str) -> Span {
+    info_span!(
+        "job",
+        job_id = %job_id,
+        provider = field::Empty,
+        sink_id = field::Empty
+    )
+}
+
+/// Set once a client cancels a pending job, so a dispatch that has not handed it to a sink yet
+/// withdraws it instead. Clones share the same flag.
+#[derive(Debug, Clone)]
+pub struct CancelSignal(Arc<watch::Sender<bool>>);
+
+impl Default for CancelSignal {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl CancelSignal {
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the job is cancelled.
+    pub async fn cancelled(&self) {
+        let mut rx = self.0.subscribe();
+        // The sender is kept alive by `self`, so this only returns once the flag is set
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Pending record for a job being dispatched. Dropping it unfinished, e.g. because the client
+/// went away mid-dispatch, marks the job failed so it does not stay pending forever. Either way
+/// a single `Job finished` event reports the final status.
+#[derive(Debug)]
+pub struct TrackedJob {
+    store: JobStore,
+    id: String,
+    started: Instant,
+    finished: bool,
+    cancel: CancelSignal,
+    /// Set once the job's frame is queued for a sink.
+    sent: watch::Sender<bool>,
+}
+
+impl TrackedJob {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Flag set by `JobStore::cancel`, checked before the job is handed to a sink.
+    pub fn cancel_signal(&self) -> &CancelSignal {
+        &self.cancel
+    }
+
+    /// Records that the job's frame was queued for `sink_id`.
+    pub fn assign_sink(&self, sink_id: Uuid) {
+        if let Some(record) = self.store.records.write().unwrap().get_mut(&self.id) {
+            record.sink_id = Some(sink_id);
+        }
+        self.sent.send_replace(true);
+    }
+
+    /// Watches whether the job reached a sink's outbound queue. The channel closes once the job
+    /// is finished or dropped, so a job that ended without being sent is seen as an error.
+    pub fn subscribe_sent(&self) -> watch::Receiver<bool> {
+        self.sent.subscribe()
+    }
+
+    /// Records what the job is: its target provider, its source and the length of its text.
+    pub fn describe(&self, payload: &InsertTextPayload) {
+        if let Some(record) = self.store.records.write().unwrap().get_mut(&self.id) {
+            record.provider = payload.target.as_ref().and_then(|t| t.provider.clone());
+            record.source = Some(payload.source.client.clone());
+            record.text_chars = payload.text.chars().count();
+        }
+    }
+
+    pub fn set_error(&self, error: impl Into<String>) {
+        if let Some(record) = self.store.records.write().unwrap().get_mut(&self.id) {
+            record.error = Some(error.into());
+        }
+    }
+
+    pub fn finish(mut self, status: JobStatus) {
+        self.settle(status);
+    }
+
+    fn settle(&mut self, status: JobStatus) {
+        self.finished = true;
+        let elapsed = self.started.elapsed();
+        self.store.finish(&self.id, status, elapsed);
+        info!(
+            job_id = %self.id,
+            status = %status,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "Job finished"
+        );
+    }
+}
+
+impl Drop for TrackedJob {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.settle(JobStatus::Failed);
+        }
+    }
+}
+
+impl JobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `id` as pending until the returned handle is finished or dropped.
+    pub fn track(&self, id: &str) -> TrackedJob {
+        let mut records = self.records.write().unwrap();
+        self.insert_pending(&mut records, id)
+    }
+
+    /// Like [`track`](Self::track), but returns `None` instead of replacing a job with the same
+    /// id that is still pending, so concurrent submissions cannot both claim an id.
+    pub fn try_track(&self, id: &str) -> Option<TrackedJob> {
+        let mut records = self.records.write().unwrap();
+        if records
+            .get(id)
+            .is_some_and(|record| record.status == JobStatus::Pending)
+        {
+            return None;
+        }
+        Some(self.insert_pending(&mut records, id))
+    }
+
+    fn insert_pending(&self, records: &mut HashMap<String, JobRecord>, id: &str) -> TrackedJob {
+        let record = JobRecord {
+            id: id.to_string(),
+            created_at: Utc::now(),
+            status: JobStatus::Pending,
+            sink_id: None,
+            progress: None,
+            provider: None,
+            source: None,
+            error: None,
+            duration_ms: None,
+            text_chars: 0,
+            created: Instant::now(),
+            finished: None,
+            cancel: CancelSignal::default(),
+        };
+        let cancel = record.cancel.clone();
+        records.insert(id.to_string(), record);
+
+        TrackedJob {
+            store: self.clone(),
+            id: id.to_string(),
+            started: Instant::now(),
+            finished: false,
+            cancel,
+            sent: watch::channel(false).0,
+        }
+    }
+
+    /// Sends every job that finishes from now on to `history` as well.
+    #[cfg(feature = "sqlite")]
+    pub fn with_history(mut self, history: JobHistory) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn history(&self) -> Option<&JobHistory> {
+        self.history.as_ref()
+    }
+
+    fn finish(&self, id: &str, status: JobStatus, elapsed: Duration) {
+        let mut records = self.records.write().unwrap();
+        let Some(record) = records.get_mut(id) else {
+            return;
+        };
+        record.status = status;
+        record.duration_ms = Some(elapsed.as_millis() as u64);
+        record.finished = Some(Instant::now());
+
+        #[cfg(feature = "sqlite")]
+        if let Some(history) = &self.history {
+            history.record(record.summary());
+        }
+    }
+
+    /// Records `progress` for a pending job. Reports for finished or unknown jobs are ignored,
+    /// returning `false`, since they arrive after the ack settled the job.
+    pub fn set_progress(&self, id: &str, progress: JobProgress) -> bool {
+        let mut records = self.records.write().unwrap();
+        match records.get_mut(id) {
+            Some(record) if record.status == JobStatus::Pending => {
+                record.progress = Some(progress);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Number of jobs still being dispatched.
+    pub fn pending(&self) -> usize {
+        self.records
+            .read()
+            .unwrap()
+            .values()
+            .filter(|record| record.status == JobStatus::Pending)
+            .count()
+    }
+
+    /// Flags `id` as cancelled if it is still pending, so its dispatch withdraws it before it
+    /// reaches a sink. Returns the status the job had, or `None` if it is unknown.
+    pub fn cancel(&self, id: &str) -> Option<JobStatus> {
+        let records = self.records.read().unwrap();
+        let record = records.get(id)?;
+        if record.status == JobStatus::Pending {
+            record.cancel.cancel();
+        }
+        Some(record.status)
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobRecord> {
+        self.records.read().unwrap().get(id).cloned()
+    }
+
+    /// Recent jobs matching `query`, newest first.
+    pub fn list(&self, query: &JobsQuery) -> Vec<JobSummary> {
+        let records = self.records.read().unwrap();
+        let mut matching: Vec<&JobRecord> = records
+            .values()
+            .filter(|record| query.status.is_none_or(|status| record.status == status))
+            .filter(|record| {
+                query
+                    .provider
+                    .as_ref()
+                    .is_none_or(|provider| record.provider.as_ref() == Some(provider))
+            })
+            .collect();
+        matching.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+        matching
+            .into_iter()
+            .take(query.limit)
+            .map(JobRecord::summary)
+            .collect()
+    }
+
+    /// Aggregates the jobs created within the last `window`. Only records that have not been
+    /// evicted yet are counted, so the window is effectively capped at the retention.
+    pub fn stats(&self, window: Duration) -> JobStats {
+        let records = self.records.read().unwrap();
+        let recent: Vec<&JobRecord> = records
+            .values()
+            .filter(|record| record.created.elapsed() <= window)
+            .collect();
+
+        let mut by_status = BTreeMap::new();
+        let mut by_provider = BTreeMap::new();
+        for record in &recent {
+            *by_status.entry(record.status.to_string()).or_insert(0) += 1;
+            if let Some(provider) = &record.provider {
+                *by_provider.entry(provider.clone()).or_insert(0) += 1;
+            }
+        }
+
+        let mut durations: Vec<u64> = recent.iter().filter_map(|r| r.duration_ms).collect();
+        durations.sort_unstable();
+        let ok = by_status.get("ok").copied().unwrap_or_default();
+        let finished = durations.len();
+        let (success_rate, avg_duration_ms, p95_duration_ms) = if finished == 0 {
+            (None, None, None)
+        } else {
+            // Nearest-rank percentile
+            let p95_rank = (finished * 95).div_ceil(100);
+            (
+                Some(ok as f64 / finished as f64),
+                Some(durations.iter().sum::<u64>() as f64 / finished as f64),
+                Some(durations[p95_rank - 1]),
+            )
+        };
+
+        JobStats {
+            window_secs: window.as_secs(),
+            total_jobs: recent.len(),
+            success_rate,
+            avg_duration_ms,
+            p95_duration_ms,
+            by_status,
+            by_provider,
+        }
+    }
+
+    /// Drops finished records older than `retention`, returning how many were removed.
+    pub fn evict_finished(&self, retention: Duration) -> usize {
+        let mut records = self.records.write().unwrap();
+        let before = records.len();
+        records.retain(|_, record| {
+            record
+                .finished
+                .is_none_or(|finished| finished.elapsed() < retention)
+        });
+        before - records.len()
+    }
+
+    pub fn spawn_eviction(&self, retention: Duration) -> JoinHandle<()> {
+        let store = self.clone();
+        let period = retention.clamp(Duration::from_millis(1), MAX_EVICTION_INTERVAL);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                ticker.tick().await;
+                store.evict_finished(retention);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_lifecycle() {
+        let store = JobStore::new();
+        let sink_id = Uuid::new_v4();
+
+        let job = store.track("job-1");
+        assert_eq!(store.get("job-1").unwrap().status, JobStatus::Pending);
+        assert_eq!(store.pending(), 1);
+
+        job.assign_sink(sink_id);
+        job.finish(JobStatus::from(&AckStatus::Retry));
+
+        let record = store.get("job-1").unwrap();
+        assert_eq!(record.status, JobStatus::Retry);
+        assert_eq!(store.pending(), 0);
+        assert_eq!(record.sink_id, Some(sink_id));
+        assert!(store.get("job-2").is_none());
+    }
+
+    #[test]
+    fn test_progress_is_kept_only_while_pending() {
+        let store = JobStore::new();
+        let progress = JobProgress {
+            inserted_chars: 40,
+            total_chars: 100,
+        };
+
+        let job = store.track("job-1");
+        assert!(store.set_progress("job-1", progress));
+        assert!(!store.set_progress("job-2", progress));
+        job.finish(JobStatus::Ok);
+
+        let late = JobProgress {
+            inserted_chars: 80,
+            ..progress
+        };
+        assert!(!store.set_progress("job-1", late));
+        assert_eq!(store.get("job-1").unwrap().progress, Some(progress));
+    }
+
+    #[test]
+    fn test_dropped_job_is_marked_failed() {
+        let store = JobStore::new();
+
+        drop(store.track("job-1"));
+
+        assert_eq!(store.get("job-1").unwrap().status, JobStatus::Failed);
+    }
+
+    #[test]
+    fn test_stats_window_is_measured_on_monotonic_clock() {
+        let store = JobStore::new();
+        store.track("job-1").finish(JobStatus::Ok);
+        std::thread::sleep(Duration::from_millis(5));
+
+        // Far longer than any wall-clock date can go back
+        assert_eq!(store.stats(Duration::MAX).total_jobs, 1);
+        assert_eq!(store.stats(Duration::ZERO).total_jobs, 0);
+    }
+
+    #[test]
+    fn test_eviction_drops_only_expired_finished_records() {
+        let store = JobStore::new();
+        let _pending = store.track("pending");
+        store.track("finished").finish(JobStatus::Ok);
+
+        assert_eq!(store.evict_finished(Duration::from_secs(60)), 0);
+        assert!(store.get("finished").is_some());
+
+        assert_eq!(store.evict_finished(Duration::ZERO), 1);
+        assert!(store.get("finished").is_none());
+        assert!(store.get("pending").is_some());
+    }
+}
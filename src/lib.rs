@@ -1,5 +1,15 @@
+pub mod callback;
+pub mod client;
 pub mod config;
 pub mod error;
 pub mod handlers;
+#[cfg(feature = "sqlite")]
+pub mod history;
+pub mod idempotency;
+pub mod jobs;
+pub mod log_socket;
+pub mod metrics;
 pub mod models;
+pub mod queue;
+pub mod rate_limit;
 pub mod websocket;
@@ -0,0 +1,116 @@
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Log writer that sends each formatted record as a datagram to a local Unix socket.
+///
+/// The socket is connected lazily and dropped on any send error, so a log shipper that starts
+/// after the daemon (or restarts) is picked up on the next record. Records that cannot be
+/// delivered are discarded rather than blocking or failing the caller.
+#[derive(Debug)]
+pub struct LogSocketWriter {
+    path: PathBuf,
+    socket: Mutex<Option<UnixDatagram>>,
+}
+
+impl LogSocketWriter {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            socket: Mutex::new(None),
+        }
+    }
+
+    fn connect(&self) -> io::Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&self.path)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    fn send(&self, buf: &[u8]) {
+        let mut guard = match self.socket.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if guard.is_none() {
+            *guard = self.connect().ok();
+        }
+
+        if let Some(socket) = guard.as_ref() {
+            match socket.send(buf) {
+                Ok(_) => {}
+                // The reader is slow; drop this record but keep the connection
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => *guard = None,
+            }
+        }
+    }
+}
+
+impl Write for &LogSocketWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogSocketWriter {
+    type Writer = &'a LogSocketWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn recv_line(socket: &UnixDatagram) -> serde_json::Value {
+        let mut buf = vec![0u8; 64 * 1024];
+        let len = socket.recv(&mut buf).unwrap();
+        let line = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(line.ends_with('\n'));
+        serde_json::from_str(line.trim_end()).unwrap()
+    }
+
+    #[test]
+    fn test_json_records_reach_socket_once_it_appears() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sock");
+
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(LogSocketWriter::new(&path)),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            // Nobody is listening yet; the record is dropped without failing
+            tracing::info!("before listener");
+
+            let listener = UnixDatagram::bind(&path).unwrap();
+            listener
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+
+            tracing::info!(job_id = "job-1", "after listener");
+
+            let record = recv_line(&listener);
+            assert_eq!(record["fields"]["message"], "after listener");
+            assert_eq!(record["fields"]["job_id"], "job-1");
+        });
+    }
+}
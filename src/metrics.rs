@@ -0,0 +1,339 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::models::SinkInfo;
+use crate::websocket::AckStatus;
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Upper bounds, in seconds, of the buckets for time spent in the job queue, which lasts as long
+/// as no sink is connected.
+const QUEUE_WAIT_BUCKETS: [f64; 11] = [
+    1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 21600.0, 86400.0,
+];
+
+const ACK_STATUSES: [AckStatus; 4] = [
+    AckStatus::Ok,
+    AckStatus::Retry,
+    AckStatus::Failed,
+    AckStatus::Cancelled,
+];
+
+/// Daemon counters served by `GET /metrics` in the Prometheus text exposition format.
+#[derive(Debug)]
+pub struct Metrics {
+    jobs_received: AtomicU64,
+    /// Indexed like `ACK_STATUSES`.
+    jobs_dispatched: [AtomicU64; ACK_STATUSES.len()],
+    dispatch_latency: Histogram,
+    sink_connections: AtomicU64,
+    ping_rtt: Histogram,
+    queue_depth: AtomicU64,
+    /// Highest `queue_depth` since startup.
+    queue_max_depth: AtomicU64,
+    jobs_queued: AtomicU64,
+    jobs_dequeued: AtomicU64,
+    /// Queued jobs pushed out of a full queue before a sink took them.
+    queued_jobs_dropped: AtomicU64,
+    queue_wait: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            jobs_received: AtomicU64::default(),
+            jobs_dispatched: Default::default(),
+            dispatch_latency: Histogram::new(&LATENCY_BUCKETS),
+            sink_connections: AtomicU64::default(),
+            ping_rtt: Histogram::new(&LATENCY_BUCKETS),
+            queue_depth: AtomicU64::default(),
+            queue_max_depth: AtomicU64::default(),
+            jobs_queued: AtomicU64::default(),
+            jobs_dequeued: AtomicU64::default(),
+            queued_jobs_dropped: AtomicU64::default(),
+            queue_wait: Histogram::new(&QUEUE_WAIT_BUCKETS),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Histogram {
+    /// Upper bounds of the buckets, in seconds.
+    bounds: &'static [f64],
+    /// Per-bucket counts, not cumulative; the `+Inf` bucket is `count`.
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::default()).collect(),
+            count: AtomicU64::default(),
+            sum_micros: AtomicU64::default(),
+        }
+    }
+
+    fn observe(&self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|&le| seconds <= le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (le, bucket) in self.bounds.iter().zip(self.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+impl Metrics {
+    pub fn job_received(&self) {
+        self.jobs_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a job the sink acked, with the time from dispatch to ack.
+    pub fn job_dispatched(&self, status: &AckStatus, latency: Duration) {
+        let i = ACK_STATUSES.iter().position(|s| s == status).unwrap_or(0);
+        self.jobs_dispatched[i].fetch_add(1, Ordering::Relaxed);
+        self.dispatch_latency.observe(latency);
+    }
+
+    pub fn set_sink_connections(&self, count: usize) {
+        self.sink_connections.store(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn ping_rtt(&self, rtt: Duration) {
+        self.ping_rtt.observe(rtt);
+    }
+
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+        self.queue_max_depth
+            .fetch_max(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn job_queued(&self) {
+        self.jobs_queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a job taken off the queue for a sink, with the time it spent queued.
+    pub fn job_dequeued(&self, waited: Duration) {
+        self.jobs_dequeued.fetch_add(1, Ordering::Relaxed);
+        self.queue_wait.observe(waited);
+    }
+
+    pub fn queued_jobs_dropped(&self, count: usize) {
+        self.queued_jobs_dropped
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Renders the daemon counters, plus per-sink counters for the connected `sinks`.
+    pub fn render(&self, sinks: &[SinkInfo]) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP promptivd_jobs_received_total Insert requests received.\n");
+        out.push_str("# TYPE promptivd_jobs_received_total counter\n");
+        let _ = writeln!(
+            out,
+            "promptivd_jobs_received_total {}",
+            self.jobs_received.load(Ordering::Relaxed)
+        );
+
+        out.push_str(
+            "# HELP promptivd_jobs_dispatched_total Jobs acked by a sink, by ack status.\n",
+        );
+        out.push_str("# TYPE promptivd_jobs_dispatched_total counter\n");
+        for (status, count) in ACK_STATUSES.iter().zip(&self.jobs_dispatched) {
+            let _ = writeln!(
+                out,
+                "promptivd_jobs_dispatched_total{{status=\"{}\"}} {}",
+                status,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        self.dispatch_latency.render(
+            &mut out,
+            "promptivd_dispatch_duration_seconds",
+            "Time from dispatch to sink ack.",
+        );
+
+        out.push_str("# HELP promptivd_sink_connections Registered sink connections.\n");
+        out.push_str("# TYPE promptivd_sink_connections gauge\n");
+        let _ = writeln!(
+            out,
+            "promptivd_sink_connections {}",
+            self.sink_connections.load(Ordering::Relaxed)
+        );
+
+        self.ping_rtt.render(
+            &mut out,
+            "promptivd_ping_rtt_seconds",
+            "Round-trip time of heartbeat pings to sinks.",
+        );
+
+        out.push_str("# HELP promptivd_queue_depth Jobs waiting in the job queue.\n");
+        out.push_str("# TYPE promptivd_queue_depth gauge\n");
+        let _ = writeln!(
+            out,
+            "promptivd_queue_depth {}",
+            self.queue_depth.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP promptivd_queue_max_depth Highest job queue depth since startup.\n");
+        out.push_str("# TYPE promptivd_queue_max_depth gauge\n");
+        let _ = writeln!(
+            out,
+            "promptivd_queue_max_depth {}",
+            self.queue_max_depth.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP promptivd_jobs_queued_total Jobs added to the job queue.\n");
+        out.push_str("# TYPE promptivd_jobs_queued_total counter\n");
+        let _ = writeln!(
+            out,
+            "promptivd_jobs_queued_total {}",
+            self.jobs_queued.load(Ordering::Relaxed)
+        );
+
+        out.push_str(
+            "# HELP promptivd_jobs_dequeued_total Jobs taken off the job queue for a sink.\n",
+        );
+        out.push_str("# TYPE promptivd_jobs_dequeued_total counter\n");
+        let _ = writeln!(
+            out,
+            "promptivd_jobs_dequeued_total {}",
+            self.jobs_dequeued.load(Ordering::Relaxed)
+        );
+
+        out.push_str(
+            "# HELP promptivd_queued_jobs_dropped_total Queued jobs dropped because the queue was full.\n",
+        );
+        out.push_str("# TYPE promptivd_queued_jobs_dropped_total counter\n");
+        let _ = writeln!(
+            out,
+            "promptivd_queued_jobs_dropped_total {}",
+            self.queued_jobs_dropped.load(Ordering::Relaxed)
+        );
+
+        self.queue_wait.render(
+            &mut out,
+            "promptivd_queue_wait_seconds",
+            "Time jobs spent in the job queue before a sink took them.",
+        );
+
+        render_per_sink(
+            &mut out,
+            sinks,
+            "promptivd_sink_orphan_acks_total",
+            "counter",
+            "Acks for unknown or already resolved jobs, by sink.",
+            |sink| sink.orphan_acks.to_string(),
+        );
+        render_per_sink(
+            &mut out,
+            sinks,
+            "promptivd_sink_bytes_sent_total",
+            "counter",
+            "Payload bytes of frames sent to each sink.",
+            |sink| sink.bytes_sent.to_string(),
+        );
+        render_per_sink(
+            &mut out,
+            sinks,
+            "promptivd_sink_bytes_received_total",
+            "counter",
+            "Payload bytes of frames received from each sink.",
+            |sink| sink.bytes_received.to_string(),
+        );
+        render_per_sink(
+            &mut out,
+            sinks,
+            "promptivd_sink_connected_since_seconds",
+            "gauge",
+            "Unix time each sink connected.",
+            |sink| sink.connected_since.timestamp().to_string(),
+        );
+        render_per_sink(
+            &mut out,
+            sinks,
+            "promptivd_sink_reconnects",
+            "gauge",
+            "Earlier registrations by a sink with the same version and providers.",
+            |sink| sink.reconnect_count.to_string(),
+        );
+
+        out
+    }
+}
+
+/// Renders a metric of type `kind` with one sample per sink, labelled by sink id.
+fn render_per_sink(
+    out: &mut String,
+    sinks: &[SinkInfo],
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl Fn(&SinkInfo) -> String,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for sink in sinks {
+        let _ = writeln!(
+            out,
+            "{}{{sink_id=\"{}\"}} {}",
+            name,
+            sink.connection.id,
+            value(sink)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counts_and_cumulative_buckets() {
+        let metrics = Metrics::default();
+        metrics.job_received();
+        metrics.job_received();
+        metrics.job_dispatched(&AckStatus::Retry, Duration::from_millis(20));
+        metrics.job_dispatched(&AckStatus::Ok, Duration::from_secs(60));
+        metrics.set_sink_connections(2);
+
+        let text = metrics.render(&[]);
+
+        assert!(text.contains("promptivd_jobs_received_total 2\n"));
+        assert!(text.contains("promptivd_jobs_dispatched_total{status=\"ok\"} 1\n"));
+        assert!(text.contains("promptivd_jobs_dispatched_total{status=\"retry\"} 1\n"));
+        assert!(text.contains("promptivd_jobs_dispatched_total{status=\"failed\"} 0\n"));
+        assert!(text.contains("promptivd_dispatch_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("promptivd_dispatch_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(text.contains("promptivd_dispatch_duration_seconds_bucket{le=\"30\"} 1\n"));
+        assert!(text.contains("promptivd_dispatch_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("promptivd_dispatch_duration_seconds_sum 60.02\n"));
+        assert!(text.contains("promptivd_sink_connections 2\n"));
+        assert!(text.contains("promptivd_ping_rtt_seconds_count 0\n"));
+    }
+}
@@ -1,23 +1,135 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Protocol schema version implemented by this build, sent in every relay message.
+pub const SCHEMA_VERSION: &str = "1.0";
+
+/// A `major.minor` protocol schema version. Minor versions only add optional fields, so peers
+/// are accepted whatever their minor version, as long as the major version matches ours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SchemaVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl SchemaVersion {
+    pub fn current() -> Self {
+        SCHEMA_VERSION
+            .parse()
+            .expect("SCHEMA_VERSION is major.minor")
+    }
+
+    /// Parses a peer's `version`, rejecting it unless its major version matches ours.
+    pub fn check_compatible(version: &str) -> crate::error::ValidationResult<Self> {
+        let parsed: Self = version.parse()?;
+        if parsed.major != Self::current().major {
+            return Err(crate::error::ValidationError::InvalidSchemaVersion {
+                version: version.to_string(),
+            });
+        }
+        Ok(parsed)
+    }
+}
+
+impl FromStr for SchemaVersion {
+    type Err = crate::error::ValidationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || crate::error::ValidationError::InvalidSchemaVersion {
+            version: s.to_string(),
+        };
+        let (major, minor) = s.split_once('.').ok_or_else(invalid)?;
+        Ok(Self {
+            major: major.parse().map_err(|_| invalid())?,
+            minor: minor.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SourceInfo {
     pub client: String,
     pub label: Option<String>,
     pub path: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct InsertTextRequest {
     pub schema_version: String,
     pub source: SourceInfo,
     pub text: String,
     pub placement: Option<Placement>,
+    /// How the text combines with the content at `placement`. Defaults to `append`.
+    #[serde(default, skip_serializing_if = "InsertMode::is_default")]
+    pub mode: InsertMode,
     pub target: Option<TargetSpec>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<serde_json::Value>,
+    /// Dispatch priority; higher values are written to the sink first. Defaults to 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// URL that receives the job outcome as a JSON POST once dispatch completes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+    /// MIME type describing the text, e.g. `text/markdown`; see [`ALLOWED_CONTENT_TYPES`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Capability the sink must support for the target provider, e.g. `cursor`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_capability: Option<String>,
+    /// Time after which the sink must drop the job instead of inserting it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Dispatch timeout for this job in milliseconds, overriding `server.dispatch_timeout`.
+    /// Capped at `server.max_dispatch_timeout`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Sends the job to every sink serving the target provider instead of just one.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub broadcast: bool,
+}
+
+/// Content types a job may declare. Sinks can use these to render snippets appropriately.
+pub const ALLOWED_CONTENT_TYPES: &[&str] = &[
+    "text/plain",
+    "text/markdown",
+    "text/html",
+    "application/json",
+    "text/x-c",
+    "text/x-c++",
+    "text/x-go",
+    "text/x-java",
+    "text/javascript",
+    "text/x-python",
+    "text/x-rust",
+    "text/x-shellscript",
+    "text/x-typescript",
+    "text/x-yaml",
+];
+
+/// Metadata key listing the server-side text transforms applied to a job, in order.
+pub const TRANSFORMS_METADATA_KEY: &str = "transforms";
+
+/// Code fence language for a content type, if it names a specific language.
+pub fn code_fence_language(content_type: &str) -> Option<&str> {
+    match content_type {
+        "text/plain" => None,
+        "text/x-c++" => Some("cpp"),
+        "text/x-shellscript" => Some("sh"),
+        other => other
+            .strip_prefix("text/x-")
+            .or_else(|| other.strip_prefix("text/"))
+            .or_else(|| other.strip_prefix("application/")),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -25,7 +137,54 @@ pub struct InsertTextRequest {
 pub enum Placement {
     Top,
     Bottom,
-    Cursor,
+    /// At the caret, or at `offset` characters into the input when given. With
+    /// `replace_selection` the text replaces the current selection instead.
+    Cursor {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<usize>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        replace_selection: bool,
+    },
+}
+
+/// How inserted text combines with the existing content at its [`Placement`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InsertMode {
+    /// After the existing content.
+    #[default]
+    Append,
+    /// Before the existing content.
+    Prepend,
+    /// In place of the existing content; requires a sink advertising `replace`.
+    Replace,
+    /// Spliced in at the placement without separating it from the surrounding content.
+    Insert,
+}
+
+impl InsertMode {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Capability a sink must advertise to accept this mode, if any.
+    pub fn required_capability(self) -> Option<&'static str> {
+        match self {
+            InsertMode::Replace => Some("replace"),
+            InsertMode::Append | InsertMode::Prepend | InsertMode::Insert => None,
+        }
+    }
+}
+
+impl std::fmt::Display for InsertMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            InsertMode::Append => "append",
+            InsertMode::Prepend => "prepend",
+            InsertMode::Replace => "replace",
+            InsertMode::Insert => "insert",
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -44,11 +203,142 @@ pub enum SessionPolicy {
 
 impl InsertTextRequest {
     pub fn validate(&self) -> crate::error::ValidationResult<()> {
-        if self.schema_version != "1.0" {
-            return Err(crate::error::ValidationError::InvalidSchemaVersion {
-                version: self.schema_version.clone(),
+        self.check(false).map(|_| ())
+    }
+
+    /// Like [`validate`](Self::validate), but tolerates deviations seen from legacy clients: an
+    /// empty `target.provider`, which is cleared so the sink picks its default. Returns the
+    /// tolerated errors as warnings.
+    pub fn validate_lenient(
+        &mut self,
+    ) -> crate::error::ValidationResult<Vec<crate::error::ValidationError>> {
+        let warnings = self.check(true)?;
+
+        if let Some(target) = &mut self.target {
+            if target
+                .provider
+                .as_ref()
+                .is_some_and(|p| p.trim().is_empty())
+            {
+                target.provider = None;
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// Rejects metadata whose serialized form exceeds `max_bytes`. Kept apart from
+    /// [`validate`](Self::validate) since the limit is server configuration.
+    pub fn validate_metadata_size(&self, max_bytes: usize) -> crate::error::ValidationResult<()> {
+        let Some(metadata) = &self.metadata else {
+            return Ok(());
+        };
+        let size = serde_json::to_vec(metadata).map_or(0, |bytes| bytes.len());
+        if size > max_bytes {
+            return Err(crate::error::ValidationError::InvalidMetadata {
+                reason: format!("{} bytes exceeds the {} byte limit", size, max_bytes),
             });
         }
+        Ok(())
+    }
+
+    /// Deserializes a request body, also returning the paths of any fields it did not recognize,
+    /// e.g. `placment` or `source.lable`.
+    pub fn from_json_value(
+        value: serde_json::Value,
+    ) -> Result<(Self, Vec<String>), serde_json::Error> {
+        let mut unknown_fields = Vec::new();
+        let request =
+            serde_ignored::deserialize(value, |path| unknown_fields.push(path.to_string()))?;
+        Ok((request, unknown_fields))
+    }
+
+    /// Fills in metadata keys the caller did not set, taking each layer of `defaults` in order
+    /// of precedence. Non-object caller metadata is left untouched.
+    pub fn merge_default_metadata(
+        &mut self,
+        defaults: &[&serde_json::Map<String, serde_json::Value>],
+    ) {
+        if defaults.iter().all(|layer| layer.is_empty()) {
+            return;
+        }
+
+        let metadata = self
+            .metadata
+            .get_or_insert_with(|| serde_json::Value::Object(Default::default()));
+        let Some(object) = metadata.as_object_mut() else {
+            return;
+        };
+
+        for layer in defaults {
+            for (key, value) in layer.iter() {
+                object.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    }
+
+    /// Applies the configured text transforms in their defined order and records the names of
+    /// those applied under [`TRANSFORMS_METADATA_KEY`]. Non-object caller metadata is left
+    /// untouched.
+    pub fn apply_transforms(&mut self, transforms: &crate::config::TextTransforms) {
+        let mut applied = Vec::new();
+
+        if transforms.wrap_code_fence {
+            // Use a fence longer than any backtick run in the text so it cannot close early
+            let longest_run = self
+                .text
+                .split(|c| c != '`')
+                .map(str::len)
+                .max()
+                .unwrap_or(0);
+            let fence = "`".repeat(longest_run.max(2) + 1);
+            let language = self
+                .content_type
+                .as_deref()
+                .and_then(code_fence_language)
+                .unwrap_or("");
+            let newline = if self.text.ends_with('\n') { "" } else { "\n" };
+            self.text = format!("{fence}{language}\n{}{newline}{fence}", self.text);
+            applied.push("wrap_code_fence");
+        }
+
+        if let Some(prefix) = &transforms.prepend {
+            self.text.insert_str(0, prefix);
+            applied.push("prepend");
+        }
+
+        if let Some(suffix) = &transforms.append {
+            self.text.push_str(suffix);
+            applied.push("append");
+        }
+
+        if applied.is_empty() {
+            return;
+        }
+
+        let metadata = self
+            .metadata
+            .get_or_insert_with(|| serde_json::Value::Object(Default::default()));
+        if let Some(object) = metadata.as_object_mut() {
+            object.insert(TRANSFORMS_METADATA_KEY.to_string(), applied.into());
+        }
+    }
+
+    fn check(
+        &self,
+        lenient: bool,
+    ) -> crate::error::ValidationResult<Vec<crate::error::ValidationError>> {
+        let mut warnings = Vec::new();
+        let mut tolerate = |error: crate::error::ValidationError, recoverable: bool| {
+            if lenient && recoverable {
+                warnings.push(error);
+                Ok(())
+            } else {
+                Err(error)
+            }
+        };
+
+        SchemaVersion::check_compatible(&self.schema_version)?;
 
         if self.source.client.is_empty() {
             return Err(crate::error::ValidationError::MissingField {
@@ -60,27 +350,58 @@ impl InsertTextRequest {
             return Err(crate::error::ValidationError::EmptySnippet);
         }
 
+        if let Some(expires_at) = self.expires_at {
+            if expires_at <= Utc::now() {
+                return Err(crate::error::ValidationError::Expired { expires_at });
+            }
+        }
+
+        if self.metadata.as_ref().is_some_and(|m| !m.is_object()) {
+            return Err(crate::error::ValidationError::InvalidMetadata {
+                reason: "metadata must be a JSON object".to_string(),
+            });
+        }
+
+        if self.timeout_ms == Some(0) {
+            return Err(crate::error::ValidationError::ZeroTimeout);
+        }
+
+        if let Some(content_type) = &self.content_type {
+            if !ALLOWED_CONTENT_TYPES.contains(&content_type.as_str()) {
+                return Err(crate::error::ValidationError::UnsupportedContentType {
+                    content_type: content_type.clone(),
+                });
+            }
+        }
+
         if let Some(target) = &self.target {
             if let Some(provider) = &target.provider {
                 if provider.trim().is_empty() {
-                    return Err(crate::error::ValidationError::MissingField {
-                        field: "target.provider".to_string(),
-                    });
+                    tolerate(
+                        crate::error::ValidationError::MissingField {
+                            field: "target.provider".to_string(),
+                        },
+                        true,
+                    )?;
                 }
             }
         }
 
-        Ok(())
+        Ok(warnings)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SinkConnection {
     pub id: Uuid,
+    /// Wall-clock registration time, for display only. Anything measuring elapsed time (ping
+    /// deadlines, dispatch timeouts) uses `tokio::time::Instant` so clock steps cannot trip it.
     pub registered_at: DateTime<Utc>,
     pub capabilities: Vec<String>,
     pub providers: Vec<String>,
     pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_capabilities: Option<HashMap<String, Vec<String>>>,
 }
 
 impl SinkConnection {
@@ -91,12 +412,31 @@ impl SinkConnection {
             capabilities,
             providers,
             version,
+            provider_capabilities: None,
         }
     }
 
+    pub fn with_provider_capabilities(
+        mut self,
+        capabilities: HashMap<String, Vec<String>>,
+    ) -> Self {
+        self.provider_capabilities = Some(capabilities);
+        self
+    }
+
     pub fn has_capability(&self, capability: &str) -> bool {
         self.capabilities.contains(&capability.to_string())
     }
+
+    /// Checks `capability` against the provider's own list when the sink advertised one, and
+    /// against the flat capability list otherwise.
+    pub fn supports(&self, provider: Option<&str>, capability: &str) -> bool {
+        let per_provider = provider.and_then(|p| self.provider_capabilities.as_ref()?.get(p));
+        match per_provider {
+            Some(capabilities) => capabilities.iter().any(|c| c == capability),
+            None => self.has_capability(capability),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -104,6 +444,11 @@ pub struct HealthResponse {
     pub ok: bool,
     pub timestamp: DateTime<Utc>,
     pub version: String,
+    pub sink_connected: bool,
+    pub sink_count: usize,
+    /// Milliseconds since a sink last answered a heartbeat ping; unset until one has.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_pong_age_ms: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -111,6 +456,205 @@ pub struct ProvidersResponse {
     pub providers: Vec<String>,
 }
 
+/// Query parameters accepted by `GET /v1/providers`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProvidersQuery {
+    /// Report whether this provider is served instead of listing every provider.
+    pub name: Option<String>,
+}
+
+/// Query parameters accepted by `GET /v1/jobs`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobsQuery {
+    pub status: Option<crate::jobs::JobStatus>,
+    pub provider: Option<String>,
+    /// Most jobs to list, newest first.
+    pub limit: usize,
+}
+
+impl Default for JobsQuery {
+    fn default() -> Self {
+        Self {
+            status: None,
+            provider: None,
+            limit: 100,
+        }
+    }
+}
+
+/// Whether a provider is served, as returned by `GET /v1/providers?name=...`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderAvailability {
+    pub name: String,
+    pub available: bool,
+    /// Sinks advertising the provider, oldest first.
+    pub sink_ids: Vec<Uuid>,
+}
+
+/// Authorization a route requires.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteAuth {
+    None,
+    /// Requires `auth_token` when one is configured.
+    Client,
+    Admin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteInfo {
+    pub method: String,
+    pub path: String,
+    pub auth: RouteAuth,
+    /// Whether the active config actually enforces `auth`. Client routes without a configured
+    /// auth token are open; admin routes without an admin token are unprotected but reject
+    /// every request.
+    pub protected: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoutesResponse {
+    pub routes: Vec<RouteInfo>,
+}
+
+/// Query parameters accepted by `POST /v1/insert`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InsertQuery {
+    /// Relay the job as an echo probe that the sink acks without inserting.
+    pub echo: bool,
+    /// Block until the sink acks. When false, respond `202` at once and dispatch in the
+    /// background; the outcome is then read from `GET /v1/jobs/:id`.
+    pub wait: bool,
+}
+
+impl Default for InsertQuery {
+    fn default() -> Self {
+        Self {
+            echo: false,
+            wait: true,
+        }
+    }
+}
+
+/// Query parameters accepted by `POST /v1/insert/batch`: those of `POST /v1/insert`, applied to
+/// every job, plus `dedupe`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchQuery {
+    pub echo: bool,
+    pub wait: bool,
+    /// Dispatch entries with the same target provider and text once, and give each of them
+    /// that dispatch's result.
+    pub dedupe: bool,
+}
+
+impl Default for BatchQuery {
+    fn default() -> Self {
+        Self {
+            echo: false,
+            wait: true,
+            dedupe: false,
+        }
+    }
+}
+
+impl BatchQuery {
+    pub fn insert_query(&self) -> InsertQuery {
+        InsertQuery {
+            echo: self.echo,
+            wait: self.wait,
+        }
+    }
+}
+
+/// Entry in the `GET /v1/sinks` listing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SinkInfo {
+    #[serde(flatten)]
+    pub connection: SinkConnection,
+    /// False while the sink has paused dispatch with `busy`.
+    pub ready: bool,
+    pub awaiting_pong: bool,
+    /// Jobs sent to the sink that have not been acked yet.
+    pub inflight_jobs: usize,
+    /// Acks the sink sent for unknown or already resolved jobs.
+    pub orphan_acks: u64,
+    /// When the sink's WebSocket was accepted, which may be shortly before `registered_at`.
+    pub connected_since: DateTime<Utc>,
+    /// Payload bytes of the text and binary frames exchanged with the sink.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Earlier registrations by a sink with the same version and providers since the daemon
+    /// started.
+    pub reconnect_count: u64,
+}
+
+/// Placement substituted for a job whose required capability the sink lacks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityDowngrade {
+    pub capability: String,
+    pub downgraded_from: Option<Placement>,
+    pub downgraded_to: Placement,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusResponse {
+    pub sink_connected: bool,
+    /// False while no sink is connected or the sink has reported itself busy.
+    pub sink_ready: bool,
+    pub inflight_bytes: usize,
+    pub max_inflight_bytes: usize,
+    /// True once a drain has started; new jobs are rejected from then on.
+    pub draining: bool,
+    /// Jobs accepted so far that are still waiting for an ack.
+    pub pending_jobs: usize,
+    /// Time left before a shutdown drain gives up on `pending_jobs`. Unset outside a shutdown,
+    /// since a drain started through the API waits for as long as it takes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drain_remaining_ms: Option<u64>,
+    /// True while maintenance mode holds new jobs in the queue.
+    pub maintenance: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance_banner: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DrainResponse {
+    pub draining: bool,
+    /// Jobs accepted before the drain that are still waiting for an ack.
+    pub pending_jobs: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    /// Replaces `server.maintenance_banner` for this maintenance window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaintenanceResponse {
+    pub maintenance: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// Jobs in the queue, which are held until maintenance ends.
+    pub queued_jobs: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogLevelRequest {
+    pub level: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogLevelResponse {
+    pub level: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,6 +672,7 @@ mod tests {
             placement: None,
             target: None,
             metadata: Some(serde_json::json!({})),
+            ..Default::default()
         };
 
         assert!(request.validate().is_ok());
@@ -144,5 +689,327 @@ mod tests {
             request.validate(),
             Err(crate::error::ValidationError::MissingField { field }) if field == "target.provider"
         ));
+
+        request.target = None;
+        request.content_type = Some("text/markdown".to_string());
+        assert!(request.validate().is_ok());
+
+        request.content_type = Some("application/x-msdownload".to_string());
+        assert!(matches!(
+            request.validate(),
+            Err(crate::error::ValidationError::UnsupportedContentType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_expired_request_rejected() {
+        let mut request = InsertTextRequest {
+            schema_version: "1.0".to_string(),
+            source: SourceInfo {
+                client: "test".to_string(),
+                ..Default::default()
+            },
+            text: "hello".to_string(),
+            expires_at: Some(Utc::now() + chrono::Duration::minutes(5)),
+            ..Default::default()
+        };
+        assert!(request.validate().is_ok());
+
+        request.expires_at = Some(Utc::now() - chrono::Duration::seconds(1));
+        assert!(matches!(
+            request.validate(),
+            Err(crate::error::ValidationError::Expired { .. })
+        ));
+        assert!(request.validate_lenient().is_err());
+    }
+
+    #[test]
+    fn test_metadata_must_be_bounded_object() {
+        let mut request = InsertTextRequest {
+            schema_version: "1.0".to_string(),
+            source: SourceInfo {
+                client: "test".to_string(),
+                ..Default::default()
+            },
+            text: "hello".to_string(),
+            metadata: Some(serde_json::json!({"note": "x".repeat(32)})),
+            ..Default::default()
+        };
+        assert!(request.validate().is_ok());
+        assert!(request.validate_metadata_size(64).is_ok());
+        assert!(matches!(
+            request.validate_metadata_size(16),
+            Err(crate::error::ValidationError::InvalidMetadata { .. })
+        ));
+
+        request.metadata = Some(serde_json::json!(["a", "b"]));
+        assert!(matches!(
+            request.validate(),
+            Err(crate::error::ValidationError::InvalidMetadata { .. })
+        ));
+        assert!(request.validate_lenient().is_err());
+    }
+
+    #[test]
+    fn test_zero_timeout_rejected() {
+        let mut request = InsertTextRequest {
+            schema_version: "1.0".to_string(),
+            source: SourceInfo {
+                client: "test".to_string(),
+                ..Default::default()
+            },
+            text: "hello".to_string(),
+            timeout_ms: Some(5000),
+            ..Default::default()
+        };
+        assert!(request.validate().is_ok());
+
+        request.timeout_ms = Some(0);
+        assert!(matches!(
+            request.validate(),
+            Err(crate::error::ValidationError::ZeroTimeout)
+        ));
+    }
+
+    #[test]
+    fn test_placement_serialization() {
+        let cases = [
+            (Placement::Top, serde_json::json!({"type": "top"})),
+            (
+                Placement::Cursor {
+                    offset: None,
+                    replace_selection: false,
+                },
+                serde_json::json!({"type": "cursor"}),
+            ),
+            (
+                Placement::Cursor {
+                    offset: Some(12),
+                    replace_selection: true,
+                },
+                serde_json::json!({"type": "cursor", "offset": 12, "replace_selection": true}),
+            ),
+        ];
+        for (placement, value) in cases {
+            assert_eq!(serde_json::to_value(&placement).unwrap(), value);
+            assert_eq!(
+                serde_json::from_value::<Placement>(value).unwrap(),
+                placement
+            );
+        }
+
+        let placement: Placement = serde_json::from_value(
+            serde_json::json!({"type": "cursor", "replace_selection": true}),
+        )
+        .unwrap();
+        assert_eq!(
+            placement,
+            Placement::Cursor {
+                offset: None,
+                replace_selection: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_insert_mode_defaults_to_append() {
+        let request: InsertTextRequest = serde_json::from_value(serde_json::json!({
+            "schema_version": "1.0",
+            "source": {"client": "test"},
+            "text": "hello",
+        }))
+        .unwrap();
+        assert_eq!(request.mode, InsertMode::Append);
+        assert!(serde_json::to_value(&request)
+            .unwrap()
+            .get("mode")
+            .is_none());
+
+        let request = InsertTextRequest {
+            mode: InsertMode::Replace,
+            ..request
+        };
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["mode"], "replace");
+        assert_eq!(
+            serde_json::from_value::<InsertTextRequest>(value)
+                .unwrap()
+                .mode,
+            InsertMode::Replace
+        );
+        assert_eq!(InsertMode::Replace.required_capability(), Some("replace"));
+        assert_eq!(InsertMode::Prepend.required_capability(), None);
+    }
+
+    #[test]
+    fn test_sink_connection_supports_falls_back_to_flat_capabilities() {
+        let connection = SinkConnection::new(
+            vec!["append".to_string()],
+            vec!["chatgpt".to_string(), "claude".to_string()],
+            "1.0.0".to_string(),
+        )
+        .with_provider_capabilities(HashMap::from([(
+            "chatgpt".to_string(),
+            vec!["cursor".to_string()],
+        )]));
+
+        assert!(connection.supports(Some("chatgpt"), "cursor"));
+        assert!(!connection.supports(Some("chatgpt"), "append"));
+        assert!(connection.supports(Some("claude"), "append"));
+        assert!(!connection.supports(Some("claude"), "cursor"));
+        assert!(connection.supports(None, "append"));
+    }
+
+    #[test]
+    fn test_schema_version_accepts_any_minor_of_same_major() {
+        assert_eq!(
+            SchemaVersion::check_compatible("1.1").unwrap(),
+            SchemaVersion { major: 1, minor: 1 }
+        );
+        assert!(SchemaVersion::check_compatible("1.10").unwrap() > SchemaVersion::current());
+        for version in ["2.0", "0.9", "1", "1.0.0", "v1.0", ""] {
+            assert!(
+                SchemaVersion::check_compatible(version).is_err(),
+                "{:?} should be rejected",
+                version
+            );
+        }
+
+        let mut request = InsertTextRequest {
+            schema_version: "1.1".to_string(),
+            source: SourceInfo {
+                client: "newer".to_string(),
+                ..Default::default()
+            },
+            text: "content".to_string(),
+            ..Default::default()
+        };
+        assert!(request.validate().is_ok());
+        request.schema_version = "2.0".to_string();
+        assert!(matches!(
+            request.validate(),
+            Err(crate::error::ValidationError::InvalidSchemaVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_lenient_validation_downgrades_recoverable_errors() {
+        let mut request = InsertTextRequest {
+            schema_version: "1.0".to_string(),
+            source: SourceInfo {
+                client: "legacy".to_string(),
+                ..Default::default()
+            },
+            text: "content".to_string(),
+            target: Some(TargetSpec {
+                provider: Some(" ".to_string()),
+                session_policy: None,
+            }),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            request.validate(),
+            Err(crate::error::ValidationError::MissingField { .. })
+        ));
+
+        let warnings = request.validate_lenient().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(request.target.as_ref().unwrap().provider, None);
+
+        // Incompatible versions and empty text are never tolerated
+        request.schema_version = "2.0".to_string();
+        assert!(request.validate_lenient().is_err());
+        request.schema_version = "1.0".to_string();
+        request.text = String::new();
+        assert!(matches!(
+            request.validate_lenient(),
+            Err(crate::error::ValidationError::EmptySnippet)
+        ));
+    }
+
+    #[test]
+    fn test_merge_default_metadata_precedence() {
+        let mut request = InsertTextRequest {
+            metadata: Some(serde_json::json!({"max_tokens": 100})),
+            ..Default::default()
+        };
+        let provider = serde_json::json!({"max_tokens": 8192, "model": "opus"});
+        let global = serde_json::json!({"model": "default", "team": "docs"});
+
+        request
+            .merge_default_metadata(&[provider.as_object().unwrap(), global.as_object().unwrap()]);
+
+        assert_eq!(
+            request.metadata,
+            Some(serde_json::json!({"max_tokens": 100, "model": "opus", "team": "docs"}))
+        );
+    }
+
+    #[test]
+    fn test_apply_transforms_individually() {
+        let request = InsertTextRequest {
+            text: "fn main() {}".to_string(),
+            content_type: Some("text/x-rust".to_string()),
+            ..Default::default()
+        };
+
+        let mut fenced = request.clone();
+        fenced.apply_transforms(&crate::config::TextTransforms {
+            wrap_code_fence: true,
+            ..Default::default()
+        });
+        assert_eq!(fenced.text, "```rust\nfn main() {}\n```");
+
+        let mut prepended = request.clone();
+        prepended.apply_transforms(&crate::config::TextTransforms {
+            prepend: Some("Review this:\n".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(prepended.text, "Review this:\nfn main() {}");
+
+        let mut appended = request.clone();
+        appended.apply_transforms(&crate::config::TextTransforms {
+            append: Some("\nThanks.".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(appended.text, "fn main() {}\nThanks.");
+        assert_eq!(
+            appended.metadata,
+            Some(serde_json::json!({"transforms": ["append"]}))
+        );
+
+        let mut untouched = request;
+        untouched.apply_transforms(&crate::config::TextTransforms::default());
+        assert_eq!(untouched.text, "fn main() {}");
+        assert_eq!(untouched.metadata, None);
+    }
+
+    #[test]
+    fn test_apply_transforms_composition_order() {
+        let mut request = InsertTextRequest {
+            text: "see ```inner```\n".to_string(),
+            content_type: Some("text/plain".to_string()),
+            metadata: Some(serde_json::json!({"team": "docs"})),
+            ..Default::default()
+        };
+
+        request.apply_transforms(&crate::config::TextTransforms {
+            wrap_code_fence: true,
+            prepend: Some("Explain:\n".to_string()),
+            append: Some("\nBe brief.".to_string()),
+        });
+
+        assert_eq!(
+            request.text,
+            "Explain:\n````\nsee ```inner```\n````\nBe brief."
+        );
+        assert_eq!(
+            request.metadata,
+            Some(serde_json::json!({
+                "team": "docs",
+                "transforms": ["wrap_code_fence", "prepend", "append"]
+            }))
+        );
     }
 }
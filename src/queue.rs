@@ -0,0 +1,339 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::metrics::Metrics;
+use crate::websocket::InsertTextPayload;
+
+/// A job accepted while no sink was connected, held until one registers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedJob {
+    pub id: String,
+    pub payload: InsertTextPayload,
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_capability: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Jobs queued before this was recorded count from when the queue was loaded.
+    #[serde(default = "Utc::now")]
+    pub queued_at: DateTime<Utc>,
+}
+
+/// Durable queue of undelivered jobs, stored as one JSON line per job in arrival order. Jobs leave
+/// it by descending priority, first come first served within a priority. Jobs are appended as
+/// they arrive; the file is rewritten only when jobs leave the queue.
+#[derive(Debug)]
+pub struct JobQueue {
+    path: PathBuf,
+    max_entries: usize,
+    entries: Mutex<VecDeque<QueuedJob>>,
+    metrics: Arc<Metrics>,
+}
+
+impl JobQueue {
+    /// Opens the queue at `path`, loading any backlog left by a previous run. Lines that no
+    /// longer parse are skipped with a warning.
+    pub fn open(path: impl Into<PathBuf>, max_entries: usize) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut entries = VecDeque::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for (i, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(&line) {
+                        Ok(job) => entries.push_back(job),
+                        Err(e) => warn!(
+                            path = %path.display(),
+                            line = i + 1,
+                            error = %e,
+                            "Skipping unreadable queued job"
+                        ),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let queue = Self {
+            path,
+            max_entries,
+            entries: Mutex::new(entries),
+            metrics: Arc::default(),
+        };
+        {
+            let mut entries = queue.entries.lock().unwrap();
+            if queue.trim(&mut entries) > 0 {
+                queue.rewrite(entries.iter())?;
+            }
+        }
+        Ok(queue)
+    }
+
+    /// Reports the queue's depth and traffic to `metrics` from now on.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.set_queue_depth(self.len());
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether a job with `id` is waiting in the queue.
+    pub fn contains(&self, id: &str) -> bool {
+        self.entries.lock().unwrap().iter().any(|job| job.id == id)
+    }
+
+    /// Appends `job`, dropping the oldest jobs with a warning once the queue is full. Fails with
+    /// [`io::ErrorKind::AlreadyExists`] if a job with the same id is already queued.
+    pub fn push(&self, job: QueuedJob) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.iter().any(|queued| queued.id == job.id) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("job {} is already queued", job.id),
+            ));
+        }
+
+        // Written before the queue changes, so a failed write leaves it and its metrics as
+        // they were
+        let excess = (entries.len() + 1).saturating_sub(self.max_entries);
+        if excess > 0 {
+            self.rewrite(entries.iter().skip(excess).chain([&job]))?;
+        } else {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{}", serde_json::to_string(&job)?)?;
+        }
+
+        entries.push_back(job);
+        self.metrics.job_queued();
+        let dropped = self.trim(&mut entries);
+        self.metrics.set_queue_depth(entries.len());
+        if dropped > 0 {
+            self.metrics.queued_jobs_dropped(dropped);
+        }
+        Ok(())
+    }
+
+    /// The job to deliver next: the oldest of those with the highest priority.
+    pub fn peek(&self) -> Option<QueuedJob> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, job)| (job.priority, Reverse(*i)))
+            .map(|(_, job)| job.clone())
+    }
+
+    /// Removes the job with `id` once it has been handed to a sink.
+    pub fn remove(&self, id: &str) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(i) = entries.iter().position(|job| job.id == id) {
+            self.rewrite(entries.iter().filter(|job| job.id != id))?;
+            let job = entries.remove(i).expect("position is in range");
+            let waited = (Utc::now() - job.queued_at).to_std().unwrap_or_default();
+            self.metrics.job_dequeued(waited);
+            self.metrics.set_queue_depth(entries.len());
+        }
+        Ok(())
+    }
+
+    fn trim(&self, entries: &mut VecDeque<QueuedJob>) -> usize {
+        let excess = entries.len().saturating_sub(self.max_entries);
+        for job in entries.drain(..excess) {
+            warn!(job_id = %job.id, max = self.max_entries, "Job queue full, dropping oldest job");
+        }
+        excess
+    }
+
+    /// Replaces the file with `entries`, via a rename so a crash never leaves it half-written.
+    fn rewrite<'a>(&self, entries: impl IntoIterator<Item = &'a QueuedJob>) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            for job in entries {
+                writeln!(file, "{}", serde_json::to_string(job)?)?;
+            }
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: &str) -> QueuedJob {
+        job_with_priority(id, 0)
+    }
+
+    fn job_with_priority(id: &str, priority: u8) -> QueuedJob {
+        QueuedJob {
+            id: id.to_string(),
+            payload: InsertTextPayload {
+                text: format!("text of {}", id),
+                ..Default::default()
+            },
+            priority,
+            required_capability: None,
+            timeout_ms: None,
+            queued_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_queue_survives_reopen_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let queue = JobQueue::open(&path, 10).unwrap();
+        queue.push(job("a")).unwrap();
+        queue.push(job("b")).unwrap();
+        queue.push(job("c")).unwrap();
+        queue.remove("a").unwrap();
+        drop(queue);
+
+        let queue = JobQueue::open(&path, 10).unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.peek().unwrap().id, "b");
+        queue.remove("b").unwrap();
+        assert_eq!(queue.peek().unwrap().id, "c");
+    }
+
+    #[test]
+    fn test_queue_delivers_by_priority_then_arrival() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let queue = JobQueue::open(&path, 10).unwrap();
+        for (id, priority) in [
+            ("bulk-1", 0),
+            ("urgent-1", 9),
+            ("bulk-2", 0),
+            ("urgent-2", 9),
+        ] {
+            queue.push(job_with_priority(id, priority)).unwrap();
+        }
+        drop(queue);
+
+        let queue = JobQueue::open(&path, 10).unwrap();
+        let mut order = Vec::new();
+        while let Some(next) = queue.peek() {
+            queue.remove(&next.id).unwrap();
+            order.push(next.id);
+        }
+        assert_eq!(order, vec!["urgent-1", "urgent-2", "bulk-1", "bulk-2"]);
+    }
+
+    #[test]
+    fn test_queue_reports_depth_and_traffic_to_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = Arc::new(Metrics::default());
+        let queue = JobQueue::open(dir.path().join("queue.jsonl"), 2)
+            .unwrap()
+            .with_metrics(Arc::clone(&metrics));
+
+        for id in ["a", "b", "c"] {
+            queue.push(job(id)).unwrap();
+        }
+        let text = metrics.render(&[]);
+        assert!(text.contains("promptivd_queue_depth 2\n"));
+        assert!(text.contains("promptivd_queue_max_depth 2\n"));
+        assert!(text.contains("promptivd_jobs_queued_total 3\n"));
+        assert!(text.contains("promptivd_queued_jobs_dropped_total 1\n"));
+        assert!(text.contains("promptivd_jobs_dequeued_total 0\n"));
+
+        queue.remove("b").unwrap();
+        queue.remove("c").unwrap();
+        let text = metrics.render(&[]);
+        assert!(text.contains("promptivd_queue_depth 0\n"));
+        assert!(text.contains("promptivd_queue_max_depth 2\n"));
+        assert!(text.contains("promptivd_jobs_dequeued_total 2\n"));
+        assert!(text.contains("promptivd_queue_wait_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(text.contains("promptivd_queue_wait_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn test_queue_rejects_duplicate_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(dir.path().join("queue.jsonl"), 10).unwrap();
+
+        queue.push(job("a")).unwrap();
+        let error = queue.push(job("a")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert!(queue.contains("a"));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_failed_write_leaves_queue_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+        let metrics = Arc::new(Metrics::default());
+        let queue = JobQueue::open(&path, 2)
+            .unwrap()
+            .with_metrics(Arc::clone(&metrics));
+        queue.push(job("a")).unwrap();
+
+        // Neither appending to nor renaming over a directory succeeds
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        assert!(queue.push(job("b")).is_err());
+        assert_eq!(queue.len(), 1);
+
+        queue.entries.lock().unwrap().push_back(job("b"));
+        assert!(queue.push(job("c")).is_err());
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.peek().unwrap().id, "a");
+
+        let text = metrics.render(&[]);
+        assert!(text.contains("promptivd_jobs_queued_total 1\n"));
+        assert!(text.contains("promptivd_queued_jobs_dropped_total 0\n"));
+    }
+
+    #[test]
+    fn test_full_queue_drops_oldest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+
+        let queue = JobQueue::open(&path, 2).unwrap();
+        for id in ["a", "b", "c"] {
+            queue.push(job(id)).unwrap();
+        }
+        assert_eq!(queue.peek().unwrap().id, "b");
+
+        let reopened = JobQueue::open(&path, 2).unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(reopened.peek().unwrap().id, "b");
+    }
+}
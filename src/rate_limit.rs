@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Once this many clients are tracked, buckets that have refilled completely are dropped, since
+/// they hold no state a fresh bucket would not.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Token-bucket limiter keyed by client. Each client may spend `burst` requests at once, refilled
+/// at `per_sec` requests per second.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_sec: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Returns `None` when either value is zero, which disables limiting.
+    pub fn new(per_sec: u32, burst: u32) -> Option<Self> {
+        if per_sec == 0 || burst == 0 {
+            return None;
+        }
+        Some(Self {
+            per_sec: per_sec.into(),
+            burst: burst.into(),
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Takes a token for `client`, or returns how long until one is available.
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_sec,
+            ))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_sec).min(self.burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_disables_limiting() {
+        assert!(RateLimiter::new(0, 5).is_none());
+        assert!(RateLimiter::new(5, 0).is_none());
+    }
+
+    #[test]
+    fn test_bucket_refills_per_client() {
+        let limiter = RateLimiter::new(2, 3).unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("a", start).is_ok());
+        }
+        let wait = limiter.check_at("a", start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert!(limiter.check_at("b", start).is_ok());
+
+        let later = start + wait;
+        assert!(limiter.check_at("a", later).is_ok());
+        assert!(limiter.check_at("a", later).is_err());
+    }
+}
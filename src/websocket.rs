@@ -1,21 +1,50 @@
-use std::collections::HashMap;
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
 };
 
-use axum::extract::ws::{Message, WebSocket};
-use futures_util::{SinkExt, StreamExt};
+use arc_swap::ArcSwap;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use chrono::{DateTime, Utc};
+use futures_util::{Sink, SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, oneshot, RwLock};
-use tokio::time::{interval, Instant};
-use tracing::{error, info, warn};
+use tokio::sync::{mpsc, oneshot, watch, Notify, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, interval_at, Instant, Interval};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+use uuid::Uuid;
 
-use crate::config::ServerConfig;
+use crate::config::{ServerConfig, SharedConfig};
 use crate::error::{AppError, AppResult};
-use crate::models::{Placement, SinkConnection, SourceInfo, TargetSpec};
+use crate::handlers::token_matches;
+use crate::jobs::{self, JobProgress, JobStatus, JobStore, TrackedJob};
+use crate::metrics::Metrics;
+use crate::models::{
+    CapabilityDowngrade, InsertMode, InsertTextRequest, Placement, SchemaVersion, SessionPolicy,
+    SinkConnection, SinkInfo, SourceInfo, TargetSpec, SCHEMA_VERSION,
+};
+use crate::queue::{JobQueue, QueuedJob};
+
+const SUPERSEDED_REASON: &str = "Superseded by new sink";
+
+/// Close code sent to a sink whose registration was refused, e.g. for an unsupported schema
+/// version. The close reason carries the error message.
+pub const REGISTRATION_FAILED_CLOSE_CODE: u16 = 4001;
 
-const SCHEMA_VERSION: &str = "1.0";
+/// Longest close reason the WebSocket protocol allows, in bytes.
+const MAX_CLOSE_REASON_BYTES: usize = 123;
+
+/// Extra attempts made for a frame whose send failed with a transient error.
+const SEND_RETRY_ATTEMPTS: u32 = 2;
+const SEND_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+/// Most sink fingerprints whose reconnects are counted. Any client can register with a new
+/// version or provider list, so the least recently registered fingerprints are forgotten first.
+const MAX_RECONNECT_FINGERPRINTS: usize = 1024;
+/// Longest wait between redispatches of a job the sink keeps acking `retry`.
+const MAX_RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_secs(10);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -25,16 +54,59 @@ pub enum SinkMessage {
         version: String,
         capabilities: Vec<String>,
         providers: Vec<String>,
+        /// Capabilities advertised per provider; takes precedence over `capabilities` for
+        /// providers it lists.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provider_capabilities: Option<HashMap<String, Vec<String>>>,
+        /// Asks for MessagePack binary frames once the `policy` reply confirms them.
+        #[serde(default)]
+        binary_frames: bool,
     },
     Ack {
         schema_version: String,
         id: String,
         status: AckStatus,
         error: Option<String>,
+        /// Payload summary returned for echo jobs, which the sink acks without inserting.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        echo: Option<serde_json::Value>,
+        /// Non-fatal notes about the insertion, e.g. that the provider truncated the prompt.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<String>,
+        /// Whether the sink now holds a conversation it can reuse for the job's target
+        /// provider. Only sinks reported to hold one receive `reuse_only` jobs.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_active: Option<bool>,
     },
     Pong {
         schema_version: String,
     },
+    /// Partial progress on a job the sink inserts in chunks. Informational only: the job is
+    /// still settled by its `Ack`.
+    Progress {
+        schema_version: String,
+        id: String,
+        inserted_chars: usize,
+        total_chars: usize,
+    },
+    /// The sink is connected but temporarily cannot insert, e.g. while reloading. Jobs are held
+    /// until `Ready` or their dispatch timeout.
+    Busy {
+        schema_version: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_hint_ms: Option<u64>,
+    },
+    Ready {
+        schema_version: String,
+    },
+    /// Answers a `reauth_challenge` with its `nonce` and the daemon's `auth_token`.
+    ReauthResponse {
+        schema_version: String,
+        nonce: String,
+        token: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,26 +115,138 @@ pub enum RelayMessage {
     InsertText {
         schema_version: String,
         id: String,
-        payload: InsertTextPayload,
+        payload: Box<InsertTextPayload>,
     },
     Ping {
         schema_version: String,
     },
+    /// Always sent as a JSON text frame; when `binary_frames` is set, every later frame in
+    /// either direction may be MessagePack binary.
     Policy {
         schema_version: String,
+        /// Schema version the daemon implements. Sinks may speak any minor version of its major
+        /// version, and can use this to decide which optional fields the daemon understands.
+        #[serde(default)]
+        server_schema_version: String,
         supersede_on_register: bool,
         max_job_bytes: usize,
+        #[serde(default)]
+        binary_frames: bool,
+    },
+    /// Sent to a sink displaced by a newer registration, just before its socket is closed.
+    Superseded {
+        schema_version: String,
+        reason: String,
+    },
+    /// Withdraws a job the client cancelled. The daemon no longer waits for its ack; a sink
+    /// that has not inserted it yet should drop it, and may ack it `cancelled`.
+    Cancel {
+        schema_version: String,
+        id: String,
+    },
+    /// Asks the sink to prove it still holds the `auth_token` it connected with, every
+    /// `sink_reauth_interval`. A sink that answers wrongly, or not before the next challenge
+    /// is due, is disconnected.
+    ReauthChallenge {
+        schema_version: String,
+        nonce: String,
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Encodes a protocol message for a binary frame. Structs are written as maps so the `type`
+/// tag survives, matching the JSON shape.
+pub fn encode_binary<T: Serialize>(message: &T) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    rmp_serde::to_vec_named(message)
+}
+
+pub fn decode_binary<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, rmp_serde::decode::Error> {
+    rmp_serde::from_slice(bytes)
+}
+
+impl RelayMessage {
+    /// Wire name of the message, matching its serialized `type` tag.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RelayMessage::InsertText { .. } => "insert_text",
+            RelayMessage::Ping { .. } => "ping",
+            RelayMessage::Policy { .. } => "policy",
+            RelayMessage::Superseded { .. } => "superseded",
+            RelayMessage::Cancel { .. } => "cancel",
+            RelayMessage::ReauthChallenge { .. } => "reauth_challenge",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct InsertTextPayload {
     pub text: String,
     pub placement: Option<Placement>,
+    #[serde(default, skip_serializing_if = "InsertMode::is_default")]
+    pub mode: InsertMode,
     pub source: SourceInfo,
     pub target: Option<TargetSpec>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Time after which the sink must ack `failed` with `expired` instead of inserting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Set for `start_fresh` jobs: the sink must open a new conversation instead of reusing one.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub new_session: bool,
+}
+
+/// Ack error reported by sinks for jobs processed after their `expires_at`.
+pub const EXPIRED_ACK_ERROR: &str = "expired";
+
+/// Metadata key marking a job as an echo probe; sinks must ack it without inserting.
+pub const ECHO_METADATA_KEY: &str = "__echo";
+
+impl InsertTextPayload {
+    pub fn is_echo(&self) -> bool {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.get(ECHO_METADATA_KEY))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Describes the parsed payload without the text itself, for acking echo jobs.
+    pub fn echo_summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "text_bytes": self.text.len(),
+            "placement": self.placement,
+            "mode": self.mode,
+            "source": self.source,
+            "target": self.target,
+            "content_type": self.content_type,
+        })
+    }
+}
+
+impl From<InsertTextRequest> for InsertTextPayload {
+    fn from(request: InsertTextRequest) -> Self {
+        let new_session = request
+            .target
+            .as_ref()
+            .is_some_and(|t| t.session_policy == Some(SessionPolicy::StartFresh));
+        Self {
+            new_session,
+            text: request.text,
+            placement: request.placement,
+            mode: request.mode,
+            source: request.source,
+            target: request.target,
+            metadata: request.metadata,
+            content_type: request.content_type,
+            expires_at: request.expires_at,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -71,6 +255,9 @@ pub enum AckStatus {
     Ok,
     Retry,
     Failed,
+    /// The job was withdrawn before the sink inserted it, either by `DELETE /v1/jobs/:id` or by
+    /// the sink itself.
+    Cancelled,
 }
 
 impl std::fmt::Display for AckStatus {
@@ -79,165 +266,1374 @@ impl std::fmt::Display for AckStatus {
             AckStatus::Ok => write!(f, "ok"),
             AckStatus::Retry => write!(f, "retry"),
             AckStatus::Failed => write!(f, "failed"),
+            AckStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Result of a broadcast job on one sink. Sinks the job could not be delivered to are reported
+/// as `failed`, with the reason in `error`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkOutcome {
+    pub sink_id: Uuid,
+    pub status: AckStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SinkOutcome {
+    fn new(sink_id: Uuid, result: AppResult<AckResponse>) -> Self {
+        match result {
+            Ok(ack) => Self {
+                sink_id,
+                status: ack.status,
+                error: ack.error,
+            },
+            Err(e) => Self {
+                sink_id,
+                status: match e {
+                    AppError::SinkDisconnectedMidJob { .. } => AckStatus::Retry,
+                    _ => AckStatus::Failed,
+                },
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Ack error reported for jobs cancelled through the API.
+pub const CANCELLED_ACK_ERROR: &str = "Cancelled by client";
+
+/// Frames queued for the outbound half of a sink socket.
+#[derive(Debug)]
+enum Outbound {
+    Relay(RelayMessage),
+    Job {
+        priority: u8,
+        message: RelayMessage,
+    },
+    /// WebSocket `Ping` or `Pong` control frame.
+    Control(Message),
+    Close(Option<CloseFrame<'static>>),
+}
+
+impl Outbound {
+    /// Close frame with `reason`, cut at a character boundary to fit the protocol's limit.
+    fn close(code: u16, reason: &str) -> Self {
+        let mut end = reason.len().min(MAX_CLOSE_REASON_BYTES);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        Outbound::Close(Some(CloseFrame {
+            code,
+            reason: reason[..end].to_string().into(),
+        }))
+    }
+}
+
+/// Rejects `mode` when `sink` lacks the capability it needs. Unlike `required_capability`, no
+/// downgrade applies, since merging differently would change what the user ends up with.
+fn check_mode(sink: &SinkConnection, provider: Option<&str>, mode: InsertMode) -> AppResult<()> {
+    match mode.required_capability() {
+        Some(capability) if !sink.supports(provider, capability) => {
+            Err(AppError::UnsupportedCapability {
+                capability: capability.to_string(),
+                provider: provider.map(str::to_string),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether a send error is worth retrying: an I/O error anywhere in its source chain that
+/// signals a momentary condition rather than a dead socket.
+fn is_transient_send_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(e) = current {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io.kind(),
+                std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+            );
+        }
+        current = e.source();
+    }
+    false
+}
+
+/// Sends a frame, retrying transient failures a bounded number of times before giving up.
+async fn send_with_retry<S>(sink: &mut S, message: Message) -> Result<(), S::Error>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error + 'static,
+{
+    let mut attempt = 0;
+    loop {
+        match sink.send(message.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < SEND_RETRY_ATTEMPTS && is_transient_send_error(&e) => {
+                attempt += 1;
+                warn!(attempt, error = %e, "Transient send error; retrying");
+                tokio::time::sleep(SEND_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
         }
     }
 }
 
+/// Frames waiting to be written to the socket, ordered so control frames go first, then jobs
+/// by descending priority. Frames of equal rank keep their submission order.
+#[derive(Debug, Default)]
+struct PendingFrames {
+    heap: BinaryHeap<PendingFrame>,
+    next_seq: u64,
+}
+
+#[derive(Debug)]
+struct PendingFrame {
+    rank: u16,
+    seq: u64,
+    frame: Outbound,
+}
+
+impl PendingFrames {
+    const CONTROL_RANK: u16 = u8::MAX as u16 + 1;
+
+    fn push(&mut self, frame: Outbound) {
+        let rank = match &frame {
+            Outbound::Job { priority, .. } => u16::from(*priority),
+            Outbound::Relay(_) | Outbound::Control(_) | Outbound::Close(_) => Self::CONTROL_RANK,
+        };
+        self.heap.push(PendingFrame {
+            rank,
+            seq: self.next_seq,
+            frame,
+        });
+        self.next_seq += 1;
+    }
+
+    fn pop(&mut self) -> Option<Outbound> {
+        self.heap.pop().map(|pending| pending.frame)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+impl Ord for PendingFrame {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.rank
+            .cmp(&other.rank)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for PendingFrame {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PendingFrame {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for PendingFrame {}
+
 #[derive(Debug)]
 pub struct SinkManager {
-    active_sink: Arc<RwLock<Option<ActiveSink>>>,
-    config: ServerConfig,
+    sinks: Arc<RwLock<SinkRegistry>>,
+    /// Swapped when the configuration is reloaded; read it afresh rather than holding on to it.
+    config: SharedConfig,
     connected: Arc<AtomicBool>,
+    inflight_bytes: Arc<AtomicUsize>,
+    jobs: JobStore,
+    metrics: Arc<Metrics>,
+    /// Holds jobs accepted while no sink is connected, when `persist_queue` is on.
+    queue: Option<Arc<JobQueue>>,
+    /// Wakes the queue replay task whenever a sink registers.
+    sink_registered: Arc<Notify>,
+    /// Banner of the maintenance window in progress, if any. New jobs are queued rather than
+    /// dispatched meanwhile, and the queue is not replayed.
+    maintenance: Arc<Mutex<Option<String>>>,
+    /// When any sink last answered a heartbeat ping.
+    last_pong: Arc<Mutex<Option<Instant>>>,
+}
+
+/// Share of the in-flight byte budget held by one dispatch, returned when dropped.
+struct InflightReservation {
+    total: Arc<AtomicUsize>,
+    bytes: usize,
+}
+
+impl Drop for InflightReservation {
+    fn drop(&mut self) {
+        self.total.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Registered sinks keyed by connection id. Each sink serves the providers it advertised.
+#[derive(Debug, Default)]
+struct SinkRegistry {
+    sinks: HashMap<Uuid, ActiveSink>,
+    next_order: u64,
+    /// Shared with `SinkManager::jobs`, so sink messages can update the records of their jobs.
+    jobs: JobStore,
+    /// Registrations seen per sink fingerprint beyond the first, with the registration sequence
+    /// number at which each was last seen. Sinks get a fresh id on every connection, so a
+    /// reconnecting sink is recognized by its version and providers instead.
+    reconnects: HashMap<String, (u64, u64)>,
+    next_registration: u64,
+}
+
+impl SinkRegistry {
+    fn insert(&mut self, mut sink: ActiveSink) {
+        sink.order = self.next_order;
+        self.next_order += 1;
+        self.sinks.insert(sink.connection.id, sink);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Records a registration by `connection`, returning how many registrations with the same
+    /// version and providers came before it. Once `MAX_RECONNECT_FINGERPRINTS` are tracked, the
+    /// least recently registered one is forgotten to make room for a new one.
+    fn count_registration(&mut self, connection: &SinkConnection) -> u64 {
+        let mut providers = connection.providers.clone();
+        providers.sort();
+        let fingerprint = format!("{}|{}", connection.version, providers.join(","));
+        let seq = self.next_registration;
+        self.next_registration += 1;
+
+        if let Some((count, last_seen)) = self.reconnects.get_mut(&fingerprint) {
+            *count += 1;
+            *last_seen = seq;
+            return *count;
+        }
+
+        if self.reconnects.len() >= MAX_RECONNECT_FINGERPRINTS {
+            let oldest = self
+                .reconnects
+                .iter()
+                .min_by_key(|(_, (_, last_seen))| *last_seen)
+                .map(|(fingerprint, _)| fingerprint.clone());
+            if let Some(oldest) = oldest {
+                self.reconnects.remove(&oldest);
+            }
+        }
+        self.reconnects.insert(fingerprint, (0, seq));
+        0
+    }
+
+    /// The sink registered by the connection writing to `channel`, if it is still registered.
+    fn owned_by(&self, channel: &mpsc::UnboundedSender<Outbound>) -> Option<&ActiveSink> {
+        self.sinks
+            .values()
+            .find(|sink| sink.message_sender.same_channel(channel))
+    }
+
+    fn remove_owned_by(&mut self, channel: &mpsc::UnboundedSender<Outbound>) -> Option<ActiveSink> {
+        let id = self.owned_by(channel)?.connection.id;
+        self.sinks.remove(&id)
+    }
+
+    /// The longest-registered sink advertising `provider`, or the longest-registered sink
+    /// overall when no provider is given.
+    fn select(&self, provider: Option<&str>) -> AppResult<&ActiveSink> {
+        self.matching(provider).map(|sinks| sinks[0])
+    }
+
+    /// Like [`select`](Self::select), except that a `reuse_only` job only goes to a sink holding
+    /// a session for `provider`.
+    fn select_for(
+        &self,
+        provider: Option<&str>,
+        policy: Option<&SessionPolicy>,
+    ) -> AppResult<&ActiveSink> {
+        if policy != Some(&SessionPolicy::ReuseOnly) {
+            return self.select(provider);
+        }
+        self.matching(provider)?
+            .into_iter()
+            .find(|sink| sink.has_session(provider))
+            .ok_or_else(|| AppError::NoReusableSession {
+                provider: provider.map(str::to_string),
+            })
+    }
+
+    /// Every sink advertising `provider`, or every sink when no provider is given, oldest
+    /// first. Never empty on success.
+    fn matching(&self, provider: Option<&str>) -> AppResult<Vec<&ActiveSink>> {
+        if self.sinks.is_empty() {
+            return Err(AppError::NoSink);
+        }
+
+        let mut sinks: Vec<_> = self
+            .sinks
+            .values()
+            .filter(|sink| {
+                provider.is_none_or(|p| sink.connection.providers.iter().any(|q| q == p))
+            })
+            .collect();
+        if sinks.is_empty() {
+            return Err(AppError::NoMatchingSink {
+                provider: provider.unwrap_or_default().to_string(),
+            });
+        }
+        sinks.sort_by_key(|sink| sink.order);
+        Ok(sinks)
+    }
+
+    /// Sinks a new registration for `providers` would replace: those sharing a provider with
+    /// it, or every provider-less sink when it advertises none either.
+    fn conflicting(&self, providers: &[String]) -> Vec<Uuid> {
+        self.sinks
+            .values()
+            .filter(|sink| {
+                let existing = &sink.connection.providers;
+                if providers.is_empty() || existing.is_empty() {
+                    providers.is_empty() && existing.is_empty()
+                } else {
+                    existing.iter().any(|p| providers.contains(p))
+                }
+            })
+            .map(|sink| sink.connection.id)
+            .collect()
+    }
 }
 
 #[derive(Debug)]
 struct ActiveSink {
     connection: SinkConnection,
-    message_sender: mpsc::UnboundedSender<RelayMessage>,
+    /// Registration sequence number, used to prefer the longest-connected sink.
+    order: u64,
+    message_sender: mpsc::UnboundedSender<Outbound>,
     ack_waiters: Arc<RwLock<HashMap<String, oneshot::Sender<AckResponse>>>>,
+    /// False while the sink has reported `Busy`.
+    ready: watch::Sender<bool>,
+    gate: Arc<DispatchGate>,
+    socket: Arc<SocketState>,
+    /// Earlier registrations with the same version and providers, counted when this one
+    /// registered.
+    reconnect_count: u64,
+    orphan_acks: OrphanAcks,
+    /// Target providers the sink last reported holding a reusable conversation for, as set by
+    /// `session_active` in its acks. `None` stands for jobs without a target provider.
+    sessions: Mutex<HashSet<Option<String>>>,
+}
+
+impl ActiveSink {
+    fn has_session(&self, provider: Option<&str>) -> bool {
+        self.sessions
+            .lock()
+            .unwrap()
+            .contains(&provider.map(str::to_string))
+    }
+
+    fn set_session(&self, provider: Option<&str>, active: bool) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let provider = provider.map(str::to_string);
+        if active {
+            sessions.insert(provider);
+        } else {
+            sessions.remove(&provider);
+        }
+    }
+}
+
+/// Jobs held while a sink is busy. Once it is ready they are let through one at a time, highest
+/// priority first and in submission order within a priority, so the sink sees them in that order.
+#[derive(Debug, Default)]
+struct DispatchGate {
+    parked: Mutex<BTreeSet<ParkedKey>>,
+    next_seq: AtomicU64,
+    /// Signalled whenever a parked job leaves, handing the turn to the next one.
+    turn: Notify,
+}
+
+/// Orders parked jobs so the first key is the next one to admit.
+type ParkedKey = (Reverse<u8>, u64);
+
+impl DispatchGate {
+    /// Whether it is the turn of the `parked` job, or of a job that never parked. The latter
+    /// only go once no job is parked.
+    fn admits(&self, parked: Option<&ParkedJob>) -> bool {
+        self.parked.lock().unwrap().first() == parked.map(|p| &p.key)
+    }
+
+    fn park(self: &Arc<Self>, priority: u8) -> ParkedJob {
+        let key = (
+            Reverse(priority),
+            self.next_seq.fetch_add(1, Ordering::Relaxed),
+        );
+        self.parked.lock().unwrap().insert(key);
+        ParkedJob {
+            gate: Arc::clone(self),
+            key,
+        }
+    }
+}
+
+/// When a job's dispatch gives up, shared by every attempt at it.
+#[derive(Debug, Clone, Copy)]
+struct DispatchDeadline {
+    at: Instant,
+    /// The job's dispatch timeout, as reported once it runs out.
+    timeout: std::time::Duration,
+}
+
+impl DispatchDeadline {
+    fn after(timeout: std::time::Duration) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+            timeout,
+        }
+    }
+
+    fn timed_out(&self) -> AppError {
+        AppError::DispatchTimeout {
+            timeout_ms: self.timeout.as_millis() as u64,
+        }
+    }
+}
+
+/// A job's place in a [`DispatchGate`]. Dropping it hands the turn to the next parked job.
+#[derive(Debug)]
+struct ParkedJob {
+    gate: Arc<DispatchGate>,
+    key: ParkedKey,
+}
+
+impl Drop for ParkedJob {
+    fn drop(&mut self) {
+        self.gate.parked.lock().unwrap().remove(&self.key);
+        self.gate.turn.notify_waiters();
+    }
+}
+
+/// State of a sink's WebSocket, shared by the connection's tasks and, once it registers, the sink.
+#[derive(Debug)]
+struct SocketState {
+    /// Wall-clock time the socket was accepted, for display only.
+    connected_since: DateTime<Utc>,
+    /// Set while a ping is outstanding; owned by the connection's receive loop.
+    awaiting_pong: AtomicBool,
+    /// Payload bytes of the text and binary frames sent and received so far.
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl Default for SocketState {
+    fn default() -> Self {
+        Self {
+            connected_since: Utc::now(),
+            awaiting_pong: AtomicBool::new(false),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Acks a sink sent for jobs that were unknown or already resolved, e.g. timed out.
+#[derive(Debug, Default)]
+struct OrphanAcks {
+    total: AtomicU64,
+    /// Start of the current counting window and the orphan acks seen in it.
+    window: Mutex<Option<(Instant, u32)>>,
+}
+
+impl OrphanAcks {
+    /// Counts one orphan ack, returning how many have arrived in the current `window`.
+    fn record(&self, window: std::time::Duration) -> u32 {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut current = self.window.lock().unwrap();
+        match &mut *current {
+            Some((start, count)) if now.duration_since(*start) < window => {
+                *count += 1;
+                *count
+            }
+            _ => {
+                *current = Some((now, 1));
+                1
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct AckResponse {
     pub status: AckStatus,
     pub error: Option<String>,
+    pub echo: Option<serde_json::Value>,
+    pub warnings: Vec<String>,
+    pub session_active: Option<bool>,
+    /// Set by the daemon, not the sink, when the job was dispatched with a fallback placement.
+    pub downgrade: Option<CapabilityDowngrade>,
+    /// Times the daemon redispatched the job after a `retry` ack before this one.
+    pub retries: u32,
+}
+
+impl AckResponse {
+    /// Resolution of a job cancelled through the API, whether or not a sink had it yet.
+    fn cancelled() -> Self {
+        AckResponse {
+            status: AckStatus::Cancelled,
+            error: Some(CANCELLED_ACK_ERROR.to_string()),
+            echo: None,
+            warnings: Vec::new(),
+            session_active: None,
+            downgrade: None,
+            retries: 0,
+        }
+    }
 }
 
 impl SinkManager {
     pub fn new(config: ServerConfig) -> Self {
+        let jobs = JobStore::new();
+        let registry = SinkRegistry {
+            jobs: jobs.clone(),
+            ..Default::default()
+        };
+
         Self {
-            active_sink: Arc::new(RwLock::new(None)),
-            config,
+            sinks: Arc::new(RwLock::new(registry)),
+            config: Arc::new(ArcSwap::from_pointee(config)),
             connected: Arc::new(AtomicBool::new(false)),
+            inflight_bytes: Arc::new(AtomicUsize::new(0)),
+            jobs,
+            metrics: Arc::new(Metrics::default()),
+            queue: None,
+            sink_registered: Arc::new(Notify::new()),
+            maintenance: Arc::new(Mutex::new(None)),
+            last_pong: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Settings in effect, shared with the HTTP handlers and swapped when the configuration is
+    /// reloaded.
+    pub fn config(&self) -> &SharedConfig {
+        &self.config
+    }
+
+    /// Keeps a SQLite record of every finished job in `history`. Must be called before the
+    /// manager is shared.
+    #[cfg(feature = "sqlite")]
+    pub fn with_job_history(mut self, history: crate::history::JobHistory) -> Self {
+        self.jobs = self.jobs.clone().with_history(history);
+        Arc::get_mut(&mut self.sinks)
+            .expect("job history is attached before sinks connect")
+            .get_mut()
+            .jobs = self.jobs.clone();
+        self
+    }
+
+    pub fn with_queue(mut self, queue: JobQueue) -> Self {
+        self.queue = Some(Arc::new(queue.with_metrics(Arc::clone(&self.metrics))));
+        self
+    }
+
+    pub fn queue(&self) -> Option<&JobQueue> {
+        self.queue.as_deref()
+    }
+
+    /// Queues a job for replay once a sink registers. Fails with
+    /// [`AppError::RequestIdInUse`] if a job with the same id is pending or already queued.
+    pub fn enqueue(&self, job: QueuedJob) -> AppResult<()> {
+        let queue = self.queue.as_ref().ok_or(AppError::NoSink)?;
+        let id = job.id.clone();
+        let in_use = || AppError::RequestIdInUse { id: id.clone() };
+        if self
+            .jobs
+            .get(&id)
+            .is_some_and(|record| record.status == JobStatus::Pending)
+        {
+            return Err(in_use());
+        }
+        queue.push(job).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => in_use(),
+            _ => e.into(),
+        })?;
+        // A sink may have registered since the caller saw none; make sure it gets the job
+        if self.has_active_sink() {
+            self.sink_registered.notify_one();
+        }
+        Ok(())
+    }
+
+    /// The banner of the maintenance window in progress, if any.
+    pub fn maintenance_banner(&self) -> Option<String> {
+        self.maintenance.lock().unwrap().clone()
+    }
+
+    /// Starts maintenance with `banner`, or ends it with `None`. Ending it replays the jobs
+    /// queued meanwhile to the connected sink.
+    pub fn set_maintenance(&self, banner: Option<String>) {
+        let ended = banner.is_none();
+        *self.maintenance.lock().unwrap() = banner;
+        if ended && self.has_active_sink() {
+            self.sink_registered.notify_one();
+        }
+    }
+
+    /// Records `id` as a new pending job. Fails with [`AppError::RequestIdInUse`] while a job
+    /// with the same id is pending or waits in the queue, so a client-supplied id is only ever
+    /// claimed once.
+    pub fn track_new_job(&self, id: &str) -> AppResult<TrackedJob> {
+        let in_use = || AppError::RequestIdInUse { id: id.to_string() };
+        if self.queue.as_ref().is_some_and(|queue| queue.contains(id)) {
+            return Err(in_use());
+        }
+        self.jobs.try_track(id).ok_or_else(in_use)
+    }
+
+    /// Whether a new job would be queued rather than dispatched: when a queue is configured and
+    /// there is no sink to take it, or maintenance is on. Broadcasts are never queued.
+    pub fn would_queue(&self, broadcast: bool) -> bool {
+        !broadcast
+            && self.queue.is_some()
+            && (!self.has_active_sink() || self.maintenance_banner().is_some())
+    }
+
+    /// Replays queued jobs by priority, one at a time, whenever a sink registers.
+    pub fn spawn_queue_replay(self: &Arc<Self>) -> JoinHandle<()> {
+        let manager = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                manager.sink_registered.notified().await;
+                manager.replay_queue().await;
+            }
+        })
+    }
+
+    async fn replay_queue(&self) {
+        let Some(queue) = &self.queue else { return };
+
+        while let Some(job) = queue.peek() {
+            if self.maintenance_banner().is_some() {
+                return;
+            }
+            let id = job.id.clone();
+            let span = jobs::job_span(&id);
+            if let Some(provider) = job
+                .payload
+                .target
+                .as_ref()
+                .and_then(|t| t.provider.as_deref())
+            {
+                span.record("provider", provider);
+            }
+            match self
+                .dispatch_job(
+                    job.id,
+                    job.payload,
+                    job.priority,
+                    job.required_capability.as_deref(),
+                    job.timeout_ms.map(std::time::Duration::from_millis),
+                )
+                .instrument(span)
+                .await
+            {
+                Ok(ack) => info!(job_id = %id, status = %ack.status, "Replayed queued job"),
+                // Keep it for the next sink
+                Err(AppError::NoSink | AppError::SinkDisconnectedMidJob { .. }) => return,
+                Err(e) => {
+                    warn!(job_id = %id, error = %e, "Dropping queued job that failed to replay")
+                }
+            }
+            if let Err(e) = queue.remove(&id) {
+                error!(error = %e, "Failed to update job queue");
+                return;
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Outcomes of recently dispatched jobs.
+    pub fn jobs(&self) -> &JobStore {
+        &self.jobs
+    }
+
     pub fn has_active_sink(&self) -> bool {
         self.connected.load(Ordering::Relaxed)
     }
 
+    pub async fn sink_count(&self) -> usize {
+        self.sinks.read().await.sinks.len()
+    }
+
+    /// Time since any sink last answered a heartbeat ping, or `None` if none has yet.
+    pub fn last_pong_age(&self) -> Option<std::time::Duration> {
+        self.last_pong.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    /// Whether any connected sink is accepting jobs, i.e. not paused with `Busy`.
+    pub async fn sink_ready(&self) -> bool {
+        self.sinks
+            .read()
+            .await
+            .sinks
+            .values()
+            .any(|sink| *sink.ready.borrow())
+    }
+
+    /// Total text bytes of jobs dispatched but not yet acked or timed out.
+    pub fn inflight_bytes(&self) -> usize {
+        self.inflight_bytes.load(Ordering::Relaxed)
+    }
+
+    fn reserve_inflight(&self, bytes: usize) -> AppResult<InflightReservation> {
+        let max = self.config.load().max_inflight_bytes;
+        self.inflight_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                current.checked_add(bytes).filter(|total| *total <= max)
+            })
+            .map_err(|current| AppError::MemoryPressure {
+                inflight: current,
+                max,
+            })?;
+
+        Ok(InflightReservation {
+            total: Arc::clone(&self.inflight_bytes),
+            bytes,
+        })
+    }
+
+    /// Describes every registered sink, oldest first.
+    pub async fn list_sinks(&self) -> Vec<SinkInfo> {
+        let registry = self.sinks.read().await;
+        let mut sinks: Vec<_> = registry.sinks.values().collect();
+        sinks.sort_by_key(|sink| sink.order);
+
+        let mut infos = Vec::with_capacity(sinks.len());
+        for sink in sinks {
+            let inflight_jobs = sink.ack_waiters.read().await.len();
+            infos.push(SinkInfo {
+                connection: sink.connection.clone(),
+                ready: *sink.ready.borrow(),
+                awaiting_pong: sink.socket.awaiting_pong.load(Ordering::Relaxed),
+                inflight_jobs,
+                orphan_acks: sink.orphan_acks.total.load(Ordering::Relaxed),
+                connected_since: sink.socket.connected_since,
+                bytes_sent: sink.socket.bytes_sent.load(Ordering::Relaxed),
+                bytes_received: sink.socket.bytes_received.load(Ordering::Relaxed),
+                reconnect_count: sink.reconnect_count,
+            });
+        }
+        infos
+    }
+
+    /// Providers served by any connected sink, oldest sink first, or `None` without sinks.
     pub async fn active_providers(&self) -> Option<Vec<String>> {
-        let sink_guard = self.active_sink.read().await;
-        sink_guard
-            .as_ref()
-            .map(|sink| sink.connection.providers.clone())
+        let registry = self.sinks.read().await;
+        if registry.is_empty() {
+            return None;
+        }
+
+        let mut sinks: Vec<_> = registry.sinks.values().collect();
+        sinks.sort_by_key(|sink| sink.order);
+        let mut providers: Vec<String> = Vec::new();
+        for provider in sinks.iter().flat_map(|sink| &sink.connection.providers) {
+            if !providers.contains(provider) {
+                providers.push(provider.clone());
+            }
+        }
+        Some(providers)
+    }
+
+    /// Sinks advertising `provider`, oldest first, or `None` without sinks.
+    pub async fn sinks_for_provider(&self, provider: &str) -> Option<Vec<Uuid>> {
+        let registry = self.sinks.read().await;
+        match registry.matching(Some(provider)) {
+            Ok(sinks) => Some(sinks.iter().map(|sink| sink.connection.id).collect()),
+            Err(AppError::NoSink) => None,
+            Err(_) => Some(Vec::new()),
+        }
     }
 
     #[cfg(test)]
     pub async fn set_test_sink(&self, connection: crate::models::SinkConnection) {
+        self.insert_test_sink(connection, true).await;
+    }
+
+    /// Like [`set_test_sink`](Self::set_test_sink), but with the sink's outbound channel already
+    /// closed, as if its socket task had exited before the sink was unregistered.
+    #[cfg(test)]
+    pub async fn set_closed_test_sink(&self, connection: crate::models::SinkConnection) {
+        self.insert_test_sink(connection, false).await;
+    }
+
+    #[cfg(test)]
+    async fn insert_test_sink(&self, connection: crate::models::SinkConnection, open: bool) {
         let (message_sender, receiver) = mpsc::unbounded_channel();
-        std::mem::forget(receiver);
+        if open {
+            std::mem::forget(receiver);
+        }
 
-        let mut active = self.active_sink.write().await;
-        *active = Some(ActiveSink {
+        self.sinks.write().await.insert(ActiveSink {
             connection,
+            order: 0,
             message_sender,
             ack_waiters: Arc::new(RwLock::new(HashMap::new())),
+            ready: watch::channel(true).0,
+            gate: Arc::default(),
+            socket: Arc::default(),
+            reconnect_count: 0,
+            orphan_acks: OrphanAcks::default(),
+            sessions: Mutex::new(HashSet::new()),
         });
 
         self.connected.store(true, Ordering::Relaxed);
     }
 
+    /// Picks the sink a job for `provider` would be dispatched to, without dispatching it. Fails
+    /// as a dispatch would when no sink matches, when a `reuse_only` job finds no session to
+    /// reuse, when the sink lacks `required_capability` and no downgrade is configured for it, or
+    /// when it lacks the capability `mode` needs.
+    pub async fn route(
+        &self,
+        provider: Option<&str>,
+        session_policy: Option<&SessionPolicy>,
+        required_capability: Option<&str>,
+        mode: InsertMode,
+    ) -> AppResult<Uuid> {
+        let registry = self.sinks.read().await;
+        let sink = registry.select_for(provider, session_policy)?;
+        if let Some(capability) = required_capability {
+            if !sink.connection.supports(provider, capability)
+                && !self
+                    .config
+                    .load()
+                    .capability_downgrades
+                    .contains_key(capability)
+            {
+                return Err(AppError::UnsupportedCapability {
+                    capability: capability.to_string(),
+                    provider: provider.map(str::to_string),
+                });
+            }
+        }
+        check_mode(&sink.connection, provider, mode)?;
+        Ok(sink.connection.id)
+    }
+
     pub async fn dispatch_job(
         &self,
         job_id: String,
-        text: String,
-        placement: Option<Placement>,
-        source: SourceInfo,
-        target: Option<TargetSpec>,
-        metadata: Option<serde_json::Value>,
+        payload: InsertTextPayload,
+        priority: u8,
+        required_capability: Option<&str>,
+        timeout: Option<std::time::Duration>,
+    ) -> AppResult<AckResponse> {
+        let job = self.jobs.track(&job_id);
+        self.dispatch_tracked_job(job, payload, priority, required_capability, timeout)
+            .await
+    }
+
+    /// Like [`dispatch_job`](Self::dispatch_job), for a job the caller has already recorded in
+    /// [`jobs`](Self::jobs), e.g. so it is queryable before a background dispatch starts.
+    ///
+    /// A `retry` ack sends the job again after `dispatch_retry_backoff`, doubling the wait each
+    /// time up to [`MAX_RETRY_BACKOFF`], up to `dispatch_max_retries` times. All attempts share
+    /// the dispatch timeout, so a retry that could not start before it runs out is not made.
+    pub async fn dispatch_tracked_job(
+        &self,
+        job: TrackedJob,
+        payload: InsertTextPayload,
+        priority: u8,
+        required_capability: Option<&str>,
+        timeout: Option<std::time::Duration>,
+    ) -> AppResult<AckResponse> {
+        job.describe(&payload);
+        let deadline = DispatchDeadline::after(self.effective_timeout(timeout));
+        let (max_retries, mut backoff) = {
+            let config = self.config.load();
+            (
+                config.dispatch_max_retries,
+                config.dispatch_retry_backoff.min(MAX_RETRY_BACKOFF),
+            )
+        };
+
+        let mut retries = 0;
+        let result = loop {
+            let started = Instant::now();
+            let result = self
+                .dispatch_to_sink(
+                    &job,
+                    None,
+                    payload.clone(),
+                    priority,
+                    required_capability,
+                    deadline,
+                )
+                .await;
+            if let Ok(ack) = &result {
+                self.metrics.job_dispatched(&ack.status, started.elapsed());
+            }
+
+            match result {
+                Ok(ack)
+                    if ack.status == AckStatus::Retry
+                        && retries < max_retries
+                        && Instant::now() + backoff < deadline.at =>
+                {
+                    retries += 1;
+                    info!(
+                        job_id = %job.id(),
+                        attempt = retries,
+                        backoff_ms = backoff.as_millis() as u64,
+                        "Sink asked to retry; dispatching again after backoff"
+                    );
+                    // A cancel during the backoff is picked up by the next attempt
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => {}
+                        _ = job.cancel_signal().cancelled() => {}
+                    }
+                    backoff = backoff.saturating_mul(2).min(MAX_RETRY_BACKOFF);
+                }
+                result => break result.map(|ack| AckResponse { retries, ..ack }),
+            }
+        };
+
+        match &result {
+            Ok(AckResponse {
+                error: Some(error), ..
+            }) => job.set_error(error.as_str()),
+            Err(e) => job.set_error(e.to_string()),
+            Ok(_) => {}
+        }
+        job.finish(match &result {
+            Ok(ack) => JobStatus::from(&ack.status),
+            Err(AppError::DispatchTimeout { .. }) => JobStatus::Timeout,
+            Err(_) => JobStatus::Failed,
+        });
+        result
+    }
+
+    /// Sends `job` to every sink serving its target provider at once, and waits for all of their
+    /// acks. Sinks that cannot take the job, e.g. for lack of `required_capability`, are
+    /// reported as failed rather than failing the whole broadcast. The job counts as `ok` once
+    /// any sink inserted it.
+    pub async fn broadcast_job(
+        &self,
+        job: TrackedJob,
+        payload: InsertTextPayload,
+        priority: u8,
+        required_capability: Option<&str>,
+        timeout: Option<std::time::Duration>,
+    ) -> AppResult<Vec<SinkOutcome>> {
+        let deadline = DispatchDeadline::after(self.effective_timeout(timeout));
+        let provider = payload.target.as_ref().and_then(|t| t.provider.as_deref());
+        job.describe(&payload);
+        let sink_ids: Vec<Uuid> = match self.sinks.read().await.matching(provider) {
+            Ok(sinks) => sinks.iter().map(|sink| sink.connection.id).collect(),
+            Err(e) => {
+                job.set_error(e.to_string());
+                job.finish(JobStatus::Failed);
+                return Err(e);
+            }
+        };
+
+        let started = Instant::now();
+        let tracked = &job;
+        let dispatches = sink_ids.into_iter().map(|sink_id| {
+            let payload = payload.clone();
+            async move {
+                let result = self
+                    .dispatch_to_sink(
+                        tracked,
+                        Some(sink_id),
+                        payload,
+                        priority,
+                        required_capability,
+                        deadline,
+                    )
+                    .await;
+                if let Ok(ack) = &result {
+                    self.metrics.job_dispatched(&ack.status, started.elapsed());
+                }
+                SinkOutcome::new(sink_id, result)
+            }
+            .instrument(info_span!("broadcast_target", sink_id = %sink_id))
+        });
+        let outcomes = futures_util::future::join_all(dispatches).await;
+
+        let status = if outcomes.iter().any(|o| o.status == AckStatus::Ok) {
+            JobStatus::Ok
+        } else if outcomes.iter().all(|o| o.status == AckStatus::Cancelled) {
+            JobStatus::Cancelled
+        } else {
+            JobStatus::Failed
+        };
+        if status != JobStatus::Ok {
+            if let Some(error) = outcomes.iter().find_map(|o| o.error.as_deref()) {
+                job.set_error(error);
+            }
+        }
+        job.finish(status);
+        Ok(outcomes)
+    }
+
+    /// The dispatch timeout for a job asking for `timeout`, capped at `max_dispatch_timeout`.
+    fn effective_timeout(&self, timeout: Option<std::time::Duration>) -> std::time::Duration {
+        let config = self.config.load();
+        timeout.map_or(config.dispatch_timeout, |timeout| {
+            timeout.min(config.max_dispatch_timeout)
+        })
+    }
+
+    /// Sends `job` to the `pinned` sink, or to the sink selected for its target provider when
+    /// unset. A job cancelled before it is sent resolves as [`AckStatus::Cancelled`] without
+    /// reaching the sink.
+    async fn dispatch_to_sink(
+        &self,
+        job: &TrackedJob,
+        pinned: Option<Uuid>,
+        mut payload: InsertTextPayload,
+        priority: u8,
+        required_capability: Option<&str>,
+        deadline: DispatchDeadline,
     ) -> AppResult<AckResponse> {
-        let sink_guard = self.active_sink.read().await;
-        let sink = match sink_guard.as_ref() {
-            Some(sink) => sink,
-            None => return Err(AppError::NoSink),
+        let job_id = job.id().to_string();
+        let mut reservation = None;
+        let mut parked: Option<ParkedJob> = None;
+
+        // Hold the job while the sink reports busy, then until the jobs parked ahead of it have
+        // gone; the sink may also be replaced meanwhile
+        let (registry, sink_id, downgrade) = loop {
+            if job.cancel_signal().is_cancelled() {
+                return Ok(AckResponse::cancelled());
+            }
+
+            let registry = self.sinks.read().await;
+            let provider = payload.target.as_ref().and_then(|t| t.provider.as_deref());
+            let policy = payload
+                .target
+                .as_ref()
+                .and_then(|t| t.session_policy.as_ref());
+            let sink = match pinned {
+                // A broadcast target that went away is not replaced by another sink
+                Some(id) => {
+                    let sink = registry.sinks.get(&id).ok_or(AppError::NoSink)?;
+                    if policy == Some(&SessionPolicy::ReuseOnly) && !sink.has_session(provider) {
+                        return Err(AppError::NoReusableSession {
+                            provider: provider.map(str::to_string),
+                        });
+                    }
+                    sink
+                }
+                None => registry.select_for(provider, policy)?,
+            };
+
+            // Re-evaluated on every pass since a replacement sink may support the capability
+            let mut downgrade = None;
+            if let Some(capability) = required_capability {
+                if !sink.connection.supports(provider, capability) {
+                    let config = self.config.load();
+                    let Some(fallback) = config.capability_downgrades.get(capability) else {
+                        return Err(AppError::UnsupportedCapability {
+                            capability: capability.to_string(),
+                            provider: provider.map(str::to_string),
+                        });
+                    };
+                    downgrade = Some(CapabilityDowngrade {
+                        capability: capability.to_string(),
+                        downgraded_from: payload.placement.clone(),
+                        downgraded_to: fallback.clone(),
+                    });
+                }
+            }
+            check_mode(&sink.connection, provider, payload.mode)?;
+
+            if reservation.is_none() {
+                reservation = Some(self.reserve_inflight(payload.text.len())?);
+            }
+
+            // A place held at a sink that has since been replaced no longer counts
+            if parked
+                .as_ref()
+                .is_some_and(|p| !Arc::ptr_eq(&p.gate, &sink.gate))
+            {
+                parked = None;
+            }
+
+            // Both are watched before checking so no change in between goes unnoticed
+            let mut ready = sink.ready.subscribe();
+            let gate = Arc::clone(&sink.gate);
+            let turn = gate.turn.notified();
+            if *ready.borrow_and_update() && gate.admits(parked.as_ref()) {
+                let sink_id = sink.connection.id;
+                Span::current().record("sink_id", tracing::field::display(sink_id));
+                break (registry, sink_id, downgrade);
+            }
+
+            drop(registry);
+            parked.get_or_insert_with(|| gate.park(priority));
+            let changed = async {
+                tokio::select! {
+                    _ = ready.changed() => {}
+                    _ = turn => {}
+                    _ = job.cancel_signal().cancelled() => {}
+                }
+            };
+            if tokio::time::timeout_at(deadline.at, changed).await.is_err() {
+                return Err(deadline.timed_out());
+            }
         };
+        let sink = &registry.sinks[&sink_id];
 
+        if let Some(downgrade) = &downgrade {
+            info!(
+                job_id = %job_id,
+                capability = %downgrade.capability,
+                placement = ?downgrade.downgraded_to,
+                "Sink lacks capability; dispatching with fallback placement"
+            );
+            payload.placement = Some(downgrade.downgraded_to.clone());
+        }
+
+        // The payload moves into the job frame, but the sink's session report is keyed by it
+        let session_provider = payload.target.as_ref().and_then(|t| t.provider.clone());
         let (response_tx, response_rx) = oneshot::channel();
+        let frame = Outbound::Job {
+            priority,
+            message: RelayMessage::InsertText {
+                schema_version: SCHEMA_VERSION.to_string(),
+                id: job_id.clone(),
+                payload: Box::new(payload),
+            },
+        };
 
         {
+            // `cancel_job` flags the job before it looks for its waiter, so checking the flag
+            // and sending under the same lock leaves no window where a cancel is missed or
+            // overtakes the job frame
             let mut waiters = sink.ack_waiters.write().await;
+            if job.cancel_signal().is_cancelled() {
+                return Ok(AckResponse::cancelled());
+            }
+            let max = self.config.load().max_inflight_per_sink;
+            if max > 0 && waiters.len() >= max {
+                return Err(AppError::SinkBusy {
+                    inflight: waiters.len(),
+                });
+            }
+            if sink.message_sender.send(frame).is_err() {
+                return Err(AppError::NoSink);
+            }
             waiters.insert(job_id.clone(), response_tx);
         }
+        job.assign_sink(sink_id);
+        // Its frame is queued ahead of any job admitted after it
+        drop(parked);
+
+        let ack_waiters = Arc::clone(&sink.ack_waiters);
+        drop(registry);
+
+        match tokio::time::timeout_at(deadline.at, response_rx).await {
+            Ok(Ok(response)) => {
+                if let Some(active) = response.session_active {
+                    if let Some(sink) = self.sinks.read().await.sinks.get(&sink_id) {
+                        sink.set_session(session_provider.as_deref(), active);
+                    }
+                }
+                Ok(AckResponse {
+                    downgrade,
+                    ..response
+                })
+            }
+            // The sink went away with the job in flight; resubmitting may well succeed
+            Ok(Err(_)) => Err(AppError::SinkDisconnectedMidJob { sink_id }),
+            Err(_) => {
+                ack_waiters.write().await.remove(&job_id);
+                Err(deadline.timed_out())
+            }
+        }
+    }
+
+    /// Cancels a job that has not been acked yet. A sink that was sent the job is sent a
+    /// `cancel`, and the dispatch resolves as [`AckStatus::Cancelled`] without waiting for its
+    /// ack; a job still waiting for a sink or for a retry is withdrawn before it is sent.
+    pub async fn cancel_job(&self, job_id: &str) -> AppResult<()> {
+        let status = self
+            .jobs
+            .cancel(job_id)
+            .ok_or_else(|| AppError::JobNotFound {
+                job_id: job_id.to_string(),
+            })?;
+        if status != JobStatus::Pending {
+            return Err(AppError::JobNotCancellable {
+                job_id: job_id.to_string(),
+                reason: format!("it already finished as {status}"),
+            });
+        }
+
+        // A broadcast job waits on several sinks, so withdraw it from each of them
+        let mut cancelled = false;
+        for sink in self.sinks.read().await.sinks.values() {
+            let Some(waiter) = sink.ack_waiters.write().await.remove(job_id) else {
+                continue;
+            };
+            let _ = sink
+                .message_sender
+                .send(Outbound::Relay(RelayMessage::Cancel {
+                    schema_version: SCHEMA_VERSION.to_string(),
+                    id: job_id.to_string(),
+                }));
+            let _ = waiter.send(AckResponse::cancelled());
+            info!(job_id = %job_id, sink_id = %sink.connection.id, "Cancelled job");
+            cancelled = true;
+        }
 
-        let job_msg = RelayMessage::InsertText {
-            schema_version: SCHEMA_VERSION.to_string(),
-            id: job_id.clone(),
-            payload: InsertTextPayload {
-                text,
-                placement,
-                source,
-                target,
-                metadata,
-            },
-        };
+        if !cancelled {
+            info!(job_id = %job_id, "Cancelled job before it reached a sink");
+        }
+        Ok(())
+    }
 
-        if sink.message_sender.send(job_msg).is_err() {
-            let mut waiters = sink.ack_waiters.write().await;
-            waiters.remove(&job_id);
-            return Err(AppError::NoSink);
+    /// Waits up to `timeout` for every job being dispatched to be acked or to fail, returning
+    /// whether none are left.
+    pub async fn wait_for_idle(&self, timeout: std::time::Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.jobs.pending() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
+        true
+    }
 
-        let timeout = self.config.dispatch_timeout;
-        drop(sink_guard);
+    /// Asks every connected sink to close, waiting up to the close grace window for them to go
+    /// away.
+    pub async fn close_all(&self, reason: &str) {
+        for sink in self.sinks.read().await.sinks.values() {
+            let _ = sink
+                .message_sender
+                .send(Outbound::close(close_code::AWAY, reason));
+        }
 
-        match tokio::time::timeout(timeout, response_rx).await {
-            Ok(Ok(response)) => Ok(response),
-            Ok(Err(_)) => Err(AppError::NoSink),
-            Err(_) => {
-                if let Some(active) = self.active_sink.read().await.as_ref() {
-                    let mut waiters = active.ack_waiters.write().await;
-                    waiters.remove(&job_id);
-                }
-                Err(AppError::DispatchTimeout {
-                    timeout_ms: timeout.as_millis() as u64,
-                })
-            }
+        let deadline = Instant::now() + self.config.load().websocket_close_grace;
+        while self.has_active_sink() && Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
     }
 
     pub async fn handle_websocket(&self, socket: WebSocket) -> AppResult<()> {
         let (mut sink_tx, mut sink_rx) = socket.split();
-        let (message_tx, mut message_rx) = mpsc::unbounded_channel::<RelayMessage>();
+        let (message_tx, mut message_rx) = mpsc::unbounded_channel::<Outbound>();
+        let outbound = message_tx.clone();
 
         // Handle incoming messages from sink
-        let active_sink_clone = Arc::clone(&self.active_sink);
-        let config = self.config.clone();
+        let sinks = Arc::clone(&self.sinks);
+        let shared_config = Arc::clone(&self.config);
         let connected = Arc::clone(&self.connected);
+        let metrics = Arc::clone(&self.metrics);
+        let sink_registered = Arc::clone(&self.sink_registered);
+        let last_pong = Arc::clone(&self.last_pong);
+        // Negotiated on register; read by both halves of the connection
+        let binary_frames = Arc::new(AtomicBool::new(false));
+        let send_binary = Arc::clone(&binary_frames);
+        // Shared with the registered sink so `GET /v1/sinks` can report it
+        let socket = Arc::new(SocketState::default());
+        let send_socket = Arc::clone(&socket);
 
-        let receive_task = tokio::spawn(async move {
+        let mut receive_task = tokio::spawn(async move {
+            // Fixed for the connection's lifetime; other settings are re-read as they are used
+            let config = shared_config.load_full();
             let mut ping_interval = interval(config.websocket_ping_interval);
             let mut missed_pings = 0u32;
             let mut registered = false;
-            let mut awaiting_pong = false;
+            let awaiting_pong = &socket.awaiting_pong;
             let mut last_ping: Option<Instant> = None;
+            // Pings only start after registration, so stalled clients are reaped here instead
+            let registration_deadline = tokio::time::sleep(config.registration_timeout);
+            tokio::pin!(registration_deadline);
+            // Re-authentication only applies when a token guards the socket
+            let mut reauth_timer = config
+                .auth_token
+                .as_ref()
+                .map(|_| config.sink_reauth_interval)
+                .filter(|period| !period.is_zero())
+                .map(|period| interval_at(Instant::now() + period, period));
+            let mut reauth_nonce: Option<String> = None;
 
             loop {
+                let config = shared_config.load_full();
                 tokio::select! {
                     // Handle incoming WebSocket messages
                     msg = sink_rx.next() => {
                         match msg {
-                            Some(Ok(Message::Text(text))) => {
-                                match serde_json::from_str::<SinkMessage>(&text) {
+                            Some(Ok(frame @ (Message::Text(_) | Message::Binary(_)))) => {
+                                let bytes = match &frame {
+                                    Message::Text(text) => text.len(),
+                                    Message::Binary(data) => data.len(),
+                                    _ => 0,
+                                };
+                                socket.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+                                let parsed = match frame {
+                                    Message::Text(text) => serde_json::from_str::<SinkMessage>(&text).map_err(|e| e.to_string()),
+                                    Message::Binary(bytes) if binary_frames.load(Ordering::Relaxed) => decode_binary(&bytes).map_err(|e| e.to_string()),
+                                    _ => Err("binary frames were not negotiated".to_string()),
+                                };
+                                match parsed {
                                     Ok(sink_msg) => {
+                                        if let SinkMessage::ReauthResponse { nonce, token, .. } = &sink_msg {
+                                            let answered = reauth_nonce.take().is_some_and(|pending| pending == *nonce)
+                                                && config.auth_token.as_deref().is_some_and(|expected| token_matches(token, expected));
+                                            if !answered {
+                                                warn!("Sink failed re-authentication, disconnecting");
+                                                let _ = message_tx.send(Outbound::close(close_code::POLICY, "Re-authentication failed"));
+                                                break;
+                                            }
+                                            debug!("Sink re-authenticated");
+                                        }
+                                        let was_registered = registered;
+                                        let binary_requested = matches!(sink_msg, SinkMessage::Register { binary_frames: true, .. });
+                                        // Measured up front since handling a pong clears `awaiting_pong`
+                                        let pong_rtt = match (&sink_msg, last_ping) {
+                                            (SinkMessage::Pong { .. }, Some(lp)) if awaiting_pong.load(Ordering::Relaxed) => Some(lp.elapsed()),
+                                            _ => None,
+                                        };
                                         match Self::handle_sink_message(
                                             sink_msg,
-                                            &active_sink_clone,
+                                            &sinks,
                                             &message_tx,
                                             &config,
                                             &mut registered,
                                             &mut missed_pings,
-                                            &mut awaiting_pong,
+                                            &socket,
                                         ).await {
                                             Ok(()) => {
                                                 if registered {
                                                     connected.store(true, Ordering::Relaxed);
                                                 }
+                                                if registered && !was_registered {
+                                                    // Mirrors the policy just queued; frames sent before this are text, which sinks always accept
+                                                    binary_frames.store(binary_requested && config.allow_binary_frames, Ordering::Relaxed);
+                                                    metrics.set_sink_connections(sinks.read().await.sinks.len());
+                                                    sink_registered.notify_one();
+                                                }
+                                                if let Some(rtt) = pong_rtt {
+                                                    metrics.ping_rtt(rtt);
+                                                    *last_pong.lock().unwrap() = Some(Instant::now());
+                                                }
                                                 // Treat any inbound valid message as liveness if awaiting and within timeout
-                                                if awaiting_pong {
+                                                if awaiting_pong.load(Ordering::Relaxed) {
                                                     if let Some(lp) = last_ping {
                                                         if lp.elapsed() <= config.websocket_pong_timeout {
-                                                            awaiting_pong = false;
+                                                            awaiting_pong.store(false, Ordering::Relaxed);
                                                             missed_pings = 0;
                                                         }
                                                     }
@@ -245,6 +1641,10 @@ impl SinkManager {
                                             }
                                             Err(e) => {
                                                 error!("Failed to handle sink message: {}", e);
+                                                // Misbehaving sinks were sent their close frame already
+                                                if let AppError::SinkRegistrationFailed { .. } = e {
+                                                    let _ = message_tx.send(Outbound::close(REGISTRATION_FAILED_CLOSE_CODE, &e.to_string()));
+                                                }
                                                 break;
                                             }
                                         }
@@ -254,6 +1654,19 @@ impl SinkManager {
                                     }
                                 }
                             }
+                            Some(Ok(Message::Ping(payload))) => {
+                                if message_tx.send(Outbound::Control(Message::Pong(payload))).is_err() { break; }
+                            }
+                            Some(Ok(Message::Pong(_))) => {
+                                if config.use_control_ping && awaiting_pong.load(Ordering::Relaxed) {
+                                    if let Some(lp) = last_ping {
+                                        metrics.ping_rtt(lp.elapsed());
+                                    }
+                                    *last_pong.lock().unwrap() = Some(Instant::now());
+                                    awaiting_pong.store(false, Ordering::Relaxed);
+                                    missed_pings = 0;
+                                }
+                            }
                             Some(Ok(Message::Close(_))) => {
                                 info!("Sink closed connection");
                                 break;
@@ -266,9 +1679,6 @@ impl SinkManager {
                                 info!("Sink disconnected");
                                 break;
                             }
-                            _ => {
-                                // Ignore other message types (binary, ping, pong)
-                            }
                         }
                     }
 
@@ -276,17 +1686,19 @@ impl SinkManager {
                     _ = ping_interval.tick() => {
                         if registered {
                             // If awaiting pong, check timeout and possibly count as missed
-                            if awaiting_pong {
+                            if awaiting_pong.load(Ordering::Relaxed) {
                                 if let Some(lp) = last_ping {
                                     if lp.elapsed() >= config.websocket_pong_timeout {
                                         missed_pings += 1;
                                         warn!("PONG timeout, missed pings: {}", missed_pings);
                                         if missed_pings >= config.websocket_max_missed_pings {
                                             warn!("Sink missed {} pings, disconnecting", missed_pings);
+                                            let reason = format!("Missed {} pings", missed_pings);
+                                            let _ = message_tx.send(Outbound::close(close_code::ERROR, &reason));
                                             break;
                                         }
                                         // Allow sending next ping below
-                                        awaiting_pong = false;
+                                        awaiting_pong.store(false, Ordering::Relaxed);
                                     } else {
                                         // Still waiting within timeout; do not send another ping
                                         continue;
@@ -295,64 +1707,165 @@ impl SinkManager {
                             }
 
                             // Send a new ping only when not awaiting
-                            if !awaiting_pong {
-                                let ping_msg = RelayMessage::Ping { schema_version: SCHEMA_VERSION.to_string() };
-                                if message_tx.send(ping_msg).is_err() { break; }
-                                awaiting_pong = true;
+                            if !awaiting_pong.load(Ordering::Relaxed) {
+                                let ping = if config.use_control_ping {
+                                    Outbound::Control(Message::Ping(Vec::new()))
+                                } else {
+                                    Outbound::Relay(RelayMessage::Ping { schema_version: SCHEMA_VERSION.to_string() })
+                                };
+                                if message_tx.send(ping).is_err() { break; }
+                                awaiting_pong.store(true, Ordering::Relaxed);
                                 last_ping = Some(Instant::now());
                             }
                         }
                     }
 
+                    _ = tick_if_set(&mut reauth_timer), if registered => {
+                        if reauth_nonce.is_some() {
+                            warn!("Sink did not answer re-authentication challenge, disconnecting");
+                            let _ = message_tx.send(Outbound::close(close_code::POLICY, "Re-authentication not answered"));
+                            break;
+                        }
+                        let nonce = Uuid::new_v4().to_string();
+                        let challenge = RelayMessage::ReauthChallenge {
+                            schema_version: SCHEMA_VERSION.to_string(),
+                            nonce: nonce.clone(),
+                        };
+                        if message_tx.send(Outbound::Relay(challenge)).is_err() { break; }
+                        reauth_nonce = Some(nonce);
+                    }
+
+                    _ = &mut registration_deadline, if !registered => {
+                        warn!("Sink did not register within {:?}, closing", config.registration_timeout);
+                        let _ = message_tx.send(Outbound::close(close_code::PROTOCOL, "Registration timeout"));
+                        break;
+                    }
+
                     // No separate sleep_until timeout branch; timeout checked on tick
                 }
             }
-
-            // Cleanup on disconnect
-            let mut active_sink = active_sink_clone.write().await;
-            if let Some(sink) = active_sink.take() {
-                // Drain any pending waiters with Retry so dispatchers can react
-                sink.drain_waiters(AckStatus::Retry, "Sink disconnected")
-                    .await;
-                info!("Cleaned up sink connection: {}", sink.connection.id);
-            }
-            connected.store(false, Ordering::Relaxed);
         });
 
         // Handle outgoing messages to sink
-        let send_task = tokio::spawn(async move {
-            while let Some(msg) = message_rx.recv().await {
-                match serde_json::to_string(&msg) {
-                    Ok(json) => {
-                        if sink_tx.send(Message::Text(json)).await.is_err() {
+        let mut send_task = tokio::spawn(async move {
+            let mut pending = PendingFrames::default();
+            loop {
+                // Collect everything queued while the previous write was in flight so that
+                // higher-priority frames overtake lower-priority ones.
+                if pending.is_empty() {
+                    match message_rx.recv().await {
+                        Some(frame) => pending.push(frame),
+                        None => break,
+                    }
+                }
+                while let Ok(frame) = message_rx.try_recv() {
+                    pending.push(frame);
+                }
+                let Some(frame) = pending.pop() else { break };
+
+                match frame {
+                    Outbound::Relay(msg) | Outbound::Job { message: msg, .. } => {
+                        // The policy announcing binary frames must itself be readable as text
+                        let binary = send_binary.load(Ordering::Relaxed)
+                            && !matches!(msg, RelayMessage::Policy { .. });
+                        let encoded = if binary {
+                            encode_binary(&msg)
+                                .map(Message::Binary)
+                                .map_err(|e| e.to_string())
+                        } else {
+                            serde_json::to_string(&msg)
+                                .map(Message::Text)
+                                .map_err(|e| e.to_string())
+                        };
+                        match encoded {
+                            Ok(frame) => {
+                                let bytes = match &frame {
+                                    Message::Text(text) => text.len(),
+                                    Message::Binary(data) => data.len(),
+                                    _ => 0,
+                                };
+                                if let Err(e) = send_with_retry(&mut sink_tx, frame).await {
+                                    debug!(error = %e, "Sink socket closed while sending");
+                                    break;
+                                }
+                                send_socket
+                                    .bytes_sent
+                                    .fetch_add(bytes as u64, Ordering::Relaxed);
+                                // Deliberately excludes the payload; job text may be sensitive
+                                match &msg {
+                                    RelayMessage::InsertText { id, .. } => debug!(
+                                        message_type = msg.kind(),
+                                        job_id = %id,
+                                        bytes,
+                                        "Relayed message to sink"
+                                    ),
+                                    _ => debug!(
+                                        message_type = msg.kind(),
+                                        bytes, "Relayed message to sink"
+                                    ),
+                                }
+                            }
+                            Err(e) => {
+                                error!("Failed to serialize message: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    Outbound::Control(frame) => {
+                        if let Err(e) = send_with_retry(&mut sink_tx, frame).await {
+                            debug!(error = %e, "Sink socket closed while sending");
                             break;
                         }
                     }
-                    Err(e) => {
-                        error!("Failed to serialize message: {}", e);
+                    Outbound::Close(close) => {
+                        // Sending flushes the close frame before the socket is dropped
+                        let _ = sink_tx.send(Message::Close(close)).await;
                         break;
                     }
                 }
             }
         });
 
-        // Wait for either task to complete
+        // Whichever half finishes first, give the other a bounded grace window so the close
+        // handshake completes instead of the peer seeing an abrupt reset.
+        let grace = self.config.load().websocket_close_grace;
         tokio::select! {
-            _ = receive_task => {},
-            _ = send_task => {},
+            _ = &mut receive_task => {
+                let _ = outbound.send(Outbound::Close(None));
+                if tokio::time::timeout(grace, &mut send_task).await.is_err() {
+                    send_task.abort();
+                }
+            },
+            _ = &mut send_task => {
+                if tokio::time::timeout(grace, &mut receive_task).await.is_err() {
+                    receive_task.abort();
+                }
+            },
+        }
+
+        // Cleanup on disconnect, unless this connection was already superseded
+        let mut registry = self.sinks.write().await;
+        if let Some(sink) = registry.remove_owned_by(&outbound) {
+            // Dropping the waiters tells their dispatchers the sink went away mid-job; the map
+            // itself outlives the sink, since dispatchers hold it to clean up after a timeout
+            sink.ack_waiters.write().await.clear();
+            info!("Cleaned up sink connection: {}", sink.connection.id);
+            self.connected
+                .store(!registry.is_empty(), Ordering::Relaxed);
         }
+        self.metrics.set_sink_connections(registry.sinks.len());
 
         Ok(())
     }
 
     async fn handle_sink_message(
         message: SinkMessage,
-        active_sink: &Arc<RwLock<Option<ActiveSink>>>,
-        message_tx: &mpsc::UnboundedSender<RelayMessage>,
+        sinks: &Arc<RwLock<SinkRegistry>>,
+        message_tx: &mpsc::UnboundedSender<Outbound>,
         config: &ServerConfig,
         registered: &mut bool,
         missed_pings: &mut u32,
-        awaiting_pong: &mut bool,
+        socket: &Arc<SocketState>,
     ) -> AppResult<()> {
         match message {
             SinkMessage::Register {
@@ -360,80 +1873,217 @@ impl SinkManager {
                 version,
                 capabilities,
                 providers,
+                provider_capabilities,
+                binary_frames: binary_requested,
             } => {
-                if schema_version != SCHEMA_VERSION {
+                if *registered {
+                    return Err(AppError::SinkRegistrationFailed {
+                        reason: "Duplicate register on an already registered connection"
+                            .to_string(),
+                    });
+                }
+
+                // Newer minor versions only add fields, which serde tolerates
+                if SchemaVersion::check_compatible(&schema_version).is_err() {
                     return Err(AppError::SinkRegistrationFailed {
-                        reason: format!("Unsupported schema version: {}", schema_version),
+                        reason: format!(
+                            "Unsupported schema version {}; this daemon speaks {}.x",
+                            schema_version,
+                            SchemaVersion::current().major
+                        ),
                     });
                 }
 
-                let connection = SinkConnection::new(capabilities, providers, version);
+                let mut connection = SinkConnection::new(capabilities, providers, version);
+                if let Some(provider_capabilities) = provider_capabilities {
+                    connection = connection.with_provider_capabilities(provider_capabilities);
+                }
 
-                let sink = ActiveSink {
+                let mut sink = ActiveSink {
                     connection,
+                    order: 0,
                     message_sender: message_tx.clone(),
                     ack_waiters: Arc::new(RwLock::new(HashMap::new())),
+                    ready: watch::channel(true).0,
+                    gate: Arc::default(),
+                    socket: Arc::clone(socket),
+                    reconnect_count: 0,
+                    orphan_acks: OrphanAcks::default(),
+                    sessions: Mutex::new(HashSet::new()),
                 };
 
                 // Send policy message first; only publish sink after success
+                let binary = binary_requested && config.allow_binary_frames;
                 let policy_msg = RelayMessage::Policy {
                     schema_version: SCHEMA_VERSION.to_string(),
+                    server_schema_version: SCHEMA_VERSION.to_string(),
                     supersede_on_register: config.supersede_on_register,
                     max_job_bytes: config.max_job_bytes,
+                    binary_frames: binary,
                 };
-                message_tx
-                    .send(policy_msg)
-                    .map_err(|_| AppError::SinkRegistrationFailed {
+                message_tx.send(Outbound::Relay(policy_msg)).map_err(|_| {
+                    AppError::SinkRegistrationFailed {
                         reason: "Failed to deliver policy".into(),
-                    })?;
+                    }
+                })?;
 
-                let mut active = active_sink.write().await;
-                if active.is_some() && !config.supersede_on_register {
+                // Sinks for other providers stay registered alongside this one
+                let mut registry = sinks.write().await;
+                let conflicting = registry.conflicting(&sink.connection.providers);
+                if !conflicting.is_empty() && !config.supersede_on_register {
                     return Err(AppError::SinkRegistrationFailed {
-                        reason: "A sink is already registered".to_string(),
+                        reason: "A sink is already registered for these providers".to_string(),
                     });
                 }
 
-                // Drain existing waiters if superseding
-                if let Some(existing) = active.take() {
+                // Drain existing waiters if superseding, then tell the old sink to stop
+                for id in conflicting {
+                    let Some(existing) = registry.sinks.remove(&id) else {
+                        continue;
+                    };
                     existing
-                        .drain_waiters(AckStatus::Retry, "Superseded by new sink")
+                        .drain_waiters(AckStatus::Retry, SUPERSEDED_REASON)
                         .await;
+                    let _ =
+                        existing
+                            .message_sender
+                            .send(Outbound::Relay(RelayMessage::Superseded {
+                                schema_version: SCHEMA_VERSION.to_string(),
+                                reason: SUPERSEDED_REASON.to_string(),
+                            }));
+                    let _ = existing
+                        .message_sender
+                        .send(Outbound::close(close_code::NORMAL, SUPERSEDED_REASON));
                     info!("Superseded existing sink: {}", existing.connection.id);
                 }
 
-                *active = Some(sink);
-
-                info!("Registered new sink");
+                sink.reconnect_count = registry.count_registration(&sink.connection);
+                info!(
+                    sink_id = %sink.connection.id,
+                    providers = ?sink.connection.providers,
+                    schema_version = %schema_version,
+                    reconnect_count = sink.reconnect_count,
+                    "Registered new sink"
+                );
+                registry.insert(sink);
 
                 *registered = true;
             }
 
             SinkMessage::Ack {
-                id, status, error, ..
+                id,
+                status,
+                error,
+                echo,
+                warnings,
+                session_active,
+                ..
             } => {
-                let response = AckResponse { status, error };
+                let response = AckResponse {
+                    status,
+                    error,
+                    echo,
+                    warnings,
+                    session_active,
+                    downgrade: None,
+                    retries: 0,
+                };
 
-                if let Some(sink) = active_sink.read().await.as_ref() {
-                    let mut waiters = sink.ack_waiters.write().await;
-                    if let Some(sender) = waiters.remove(&id) {
-                        let _ = sender.send(response);
+                let registry = sinks.read().await;
+                let Some(sink) = registry.owned_by(message_tx) else {
+                    return Ok(());
+                };
+                let Some(sender) = sink.ack_waiters.write().await.remove(&id) else {
+                    // Expected after a cancel, which the daemon stops waiting on
+                    if response.status == AckStatus::Cancelled {
+                        debug!(job_id = %id, "Ignoring ack for a cancelled job");
+                        return Ok(());
+                    }
+                    let recent = sink.orphan_acks.record(config.orphan_ack_window);
+                    warn!(
+                        job_id = %id,
+                        sink_id = %sink.connection.id,
+                        status = %response.status,
+                        "Ack for unknown or already resolved job"
+                    );
+                    if config.max_orphan_acks > 0 && recent > config.max_orphan_acks {
+                        let reason = format!(
+                            "{} acks for unknown jobs within {:?}",
+                            recent, config.orphan_ack_window
+                        );
+                        let _ = message_tx.send(Outbound::close(close_code::POLICY, &reason));
+                        return Err(AppError::SinkMisbehaving { reason });
                     }
+                    return Ok(());
+                };
+                let _ = sender.send(response);
+            }
+
+            SinkMessage::Progress {
+                id,
+                inserted_chars,
+                total_chars,
+                ..
+            } => {
+                let registry = sinks.read().await;
+                let Some(sink) = registry.owned_by(message_tx) else {
+                    return Ok(());
+                };
+                // Only the sink a job is waiting on may report on it
+                let owned = sink.ack_waiters.read().await.contains_key(&id);
+                let progress = JobProgress {
+                    inserted_chars,
+                    total_chars,
+                };
+                if !owned || !registry.jobs.set_progress(&id, progress) {
+                    debug!(job_id = %id, "Ignoring progress for a job not pending on this sink");
+                }
+            }
+
+            SinkMessage::Busy {
+                reason,
+                resume_hint_ms,
+                ..
+            } => {
+                // Ignore a superseded connection so it cannot pause its replacement
+                if let Some(sink) = sinks.read().await.owned_by(message_tx) {
+                    warn!(?reason, ?resume_hint_ms, "Sink is busy, holding jobs");
+                    sink.ready.send_replace(false);
+                }
+            }
+
+            SinkMessage::Ready { .. } => {
+                if let Some(sink) = sinks.read().await.owned_by(message_tx) {
+                    info!("Sink is ready, resuming dispatch");
+                    sink.ready.send_replace(true);
                 }
             }
 
             SinkMessage::Pong { .. } => {
                 // Pong received - reset missed pings and clear awaiting state
                 *missed_pings = 0;
-                *awaiting_pong = false;
+                socket.awaiting_pong.store(false, Ordering::Relaxed);
                 info!("Received PONG from sink, reset missed ping counter");
             }
+
+            // Checked by the connection loop, which holds the outstanding challenge
+            SinkMessage::ReauthResponse { .. } => {}
         }
 
         Ok(())
     }
 }
 
+/// Waits for the next tick of `timer`, or forever when there is none.
+async fn tick_if_set(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
 impl ActiveSink {
     async fn drain_waiters(&self, status: AckStatus, reason: &str) {
         let mut waiters = self.ack_waiters.write().await;
@@ -443,6 +2093,11 @@ impl ActiveSink {
             let _ = sender.send(AckResponse {
                 status: status.clone(),
                 error: Some(reason.to_string()),
+                echo: None,
+                warnings: Vec::new(),
+                session_active: None,
+                downgrade: None,
+                retries: 0,
             });
         }
     }
@@ -453,14 +2108,34 @@ mod tests {
     use super::*;
     use crate::models::{SessionPolicy, SourceInfo, TargetSpec};
 
-    #[test]
-    fn test_sink_message_serialization() {
-        let register_msg = SinkMessage::Register {
+    /// Builds a `register` frame for `providers` with the default schema and capabilities.
+    fn register_message(providers: &[&str], version: &str) -> SinkMessage {
+        SinkMessage::Register {
             schema_version: "1.0".to_string(),
-            version: "1.0.0".to_string(),
+            version: version.to_string(),
             capabilities: vec!["insert".to_string()],
-            providers: vec!["chatgpt".to_string(), "claude".to_string()],
-        };
+            providers: providers.iter().map(|p| p.to_string()).collect(),
+            provider_capabilities: None,
+            binary_frames: false,
+        }
+    }
+
+    /// Builds a bare `ack` frame for `job_id` with no error, echo or warnings.
+    fn ack(job_id: &str, status: AckStatus) -> SinkMessage {
+        SinkMessage::Ack {
+            schema_version: "1.0".to_string(),
+            id: job_id.to_string(),
+            status,
+            error: None,
+            echo: None,
+            warnings: Vec::new(),
+            session_active: None,
+        }
+    }
+
+    #[test]
+    fn test_sink_message_serialization() {
+        let register_msg = register_message(&["chatgpt", "claude"], "1.0.0");
 
         let json = serde_json::to_string(&register_msg).unwrap();
         let deserialized: SinkMessage = serde_json::from_str(&json).unwrap();
@@ -476,14 +2151,797 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_policy_confirms_binary_frames_when_allowed() {
+        for (requested, allowed, expected) in [
+            (true, true, true),
+            (true, false, false),
+            (false, true, false),
+        ] {
+            let sinks = Arc::new(RwLock::new(SinkRegistry::default()));
+            let (message_tx, mut message_rx) = mpsc::unbounded_channel();
+            let config = ServerConfig {
+                allow_binary_frames: allowed,
+                ..Default::default()
+            };
+            let mut register = register_message(&["chatgpt"], "1.0.0");
+            if let SinkMessage::Register { binary_frames, .. } = &mut register {
+                *binary_frames = requested;
+            }
+            SinkManager::handle_sink_message(
+                register,
+                &sinks,
+                &message_tx,
+                &config,
+                &mut false,
+                &mut 0,
+                &Arc::default(),
+            )
+            .await
+            .unwrap();
+
+            let Some(Outbound::Relay(RelayMessage::Policy {
+                binary_frames: announced,
+                ..
+            })) = message_rx.recv().await
+            else {
+                panic!("expected policy");
+            };
+            assert_eq!(announced, expected);
+        }
+    }
+
+    #[test]
+    fn test_binary_frames_round_trip_tagged_messages() {
+        let message = RelayMessage::InsertText {
+            schema_version: "1.0".to_string(),
+            id: "job-1".to_string(),
+            payload: Box::new(InsertTextPayload {
+                text: "line one\nline two".to_string(),
+                metadata: Some(serde_json::json!({"lang": "rust"})),
+                ..Default::default()
+            }),
+        };
+
+        let bytes = encode_binary(&message).unwrap();
+        let RelayMessage::InsertText { id, payload, .. } = decode_binary(&bytes).unwrap() else {
+            panic!("wrong message type");
+        };
+        assert_eq!(id, "job-1");
+        assert_eq!(payload.text, "line one\nline two");
+        assert_eq!(payload.metadata.unwrap()["lang"], "rust");
+    }
+
+    #[tokio::test]
+    async fn test_superseded_sink_is_notified_before_close() {
+        let sinks = Arc::new(RwLock::new(SinkRegistry::default()));
+        let config = ServerConfig::default();
+        let (old_tx, mut old_rx) = mpsc::unbounded_channel();
+        let (new_tx, _new_rx) = mpsc::unbounded_channel();
+        for message_tx in [&old_tx, &new_tx] {
+            SinkManager::handle_sink_message(
+                register_message(&["chatgpt"], "1.0.0"),
+                &sinks,
+                message_tx,
+                &config,
+                &mut false,
+                &mut 0,
+                &Arc::default(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(matches!(
+            old_rx.recv().await,
+            Some(Outbound::Relay(RelayMessage::Policy { .. }))
+        ));
+        let Some(Outbound::Relay(RelayMessage::Superseded { reason, .. })) = old_rx.recv().await
+        else {
+            panic!("expected superseded notice");
+        };
+        assert_eq!(reason, SUPERSEDED_REASON);
+        assert!(matches!(
+            old_rx.recv().await,
+            Some(Outbound::Close(Some(_)))
+        ));
+        assert!(sinks.read().await.owned_by(&old_tx).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_register_on_same_socket_rejected() {
+        let sinks = Arc::new(RwLock::new(SinkRegistry::default()));
+        let (message_tx, _message_rx) = mpsc::unbounded_channel();
+        let config = ServerConfig::default();
+        let mut registered = false;
+        let mut missed_pings = 0;
+        let socket = Arc::new(SocketState::default());
+
+        SinkManager::handle_sink_message(
+            register_message(&["chatgpt"], "1.0.0"),
+            &sinks,
+            &message_tx,
+            &config,
+            &mut registered,
+            &mut missed_pings,
+            &socket,
+        )
+        .await
+        .unwrap();
+        assert!(registered);
+        let first_id = sinks
+            .read()
+            .await
+            .owned_by(&message_tx)
+            .unwrap()
+            .connection
+            .id;
+
+        let result = SinkManager::handle_sink_message(
+            register_message(&["chatgpt"], "1.0.0"),
+            &sinks,
+            &message_tx,
+            &config,
+            &mut registered,
+            &mut missed_pings,
+            &socket,
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(AppError::SinkRegistrationFailed { .. })
+        ));
+        // The original registration is left untouched
+        assert_eq!(
+            sinks
+                .read()
+                .await
+                .owned_by(&message_tx)
+                .unwrap()
+                .connection
+                .id,
+            first_id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_accepts_minor_schema_versions_only() {
+        let config = ServerConfig::default();
+        for (version, accepted) in [("1.1", true), ("1.0", true), ("2.0", false)] {
+            let mut register = register_message(&["chatgpt"], "1.0.0");
+            if let SinkMessage::Register { schema_version, .. } = &mut register {
+                *schema_version = version.to_string();
+            }
+            let sinks = Arc::new(RwLock::new(SinkRegistry::default()));
+            let (message_tx, mut message_rx) = mpsc::unbounded_channel();
+            let mut registered = false;
+            let result = SinkManager::handle_sink_message(
+                register,
+                &sinks,
+                &message_tx,
+                &config,
+                &mut registered,
+                &mut 0,
+                &Arc::default(),
+            )
+            .await;
+
+            if !accepted {
+                assert!(matches!(
+                    result,
+                    Err(AppError::SinkRegistrationFailed { .. })
+                ));
+                assert!(!registered);
+                continue;
+            }
+            result.unwrap();
+            assert!(registered);
+            assert!(matches!(
+                message_rx.try_recv(),
+                Ok(Outbound::Relay(RelayMessage::Policy { server_schema_version, .. }))
+                    if server_schema_version == SCHEMA_VERSION
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_orphan_acks_are_counted_then_disconnect() {
+        let sinks = Arc::new(RwLock::new(SinkRegistry::default()));
+        let (message_tx, mut message_rx) = mpsc::unbounded_channel();
+        let config = ServerConfig {
+            max_orphan_acks: 2,
+            ..Default::default()
+        };
+        let mut registered = false;
+        let mut missed_pings = 0;
+        let socket = Arc::new(SocketState::default());
+
+        let register = register_message(&["chatgpt"], "1.0.0");
+
+        let mut results = Vec::new();
+        for message in [
+            register,
+            ack("stale-1", AckStatus::Ok),
+            ack("cancelled", AckStatus::Cancelled),
+            ack("stale-2", AckStatus::Failed),
+            ack("stale-3", AckStatus::Ok),
+        ] {
+            results.push(
+                SinkManager::handle_sink_message(
+                    message,
+                    &sinks,
+                    &message_tx,
+                    &config,
+                    &mut registered,
+                    &mut missed_pings,
+                    &socket,
+                )
+                .await,
+            );
+        }
+
+        // Acks for cancelled jobs are expected and not counted
+        assert!(results[..4].iter().all(Result::is_ok));
+        assert!(matches!(results[4], Err(AppError::SinkMisbehaving { .. })));
+        let registry = sinks.read().await;
+        let sink = registry.owned_by(&message_tx).unwrap();
+        assert_eq!(sink.orphan_acks.total.load(Ordering::Relaxed), 3);
+
+        let mut closed = false;
+        while let Ok(outbound) = message_rx.try_recv() {
+            if let Outbound::Close(Some(frame)) = outbound {
+                closed = frame.code == close_code::POLICY;
+            }
+        }
+        assert!(closed);
+    }
+
+    #[tokio::test]
+    async fn test_progress_updates_job_until_acked() {
+        let jobs = JobStore::new();
+        let sinks = Arc::new(RwLock::new(SinkRegistry {
+            jobs: jobs.clone(),
+            ..Default::default()
+        }));
+        let (message_tx, _message_rx) = mpsc::unbounded_channel();
+        let config = ServerConfig::default();
+        let mut registered = false;
+        let mut missed_pings = 0;
+        let socket = Arc::new(SocketState::default());
+
+        let register = register_message(&["chatgpt"], "1.0.0");
+        SinkManager::handle_sink_message(
+            register,
+            &sinks,
+            &message_tx,
+            &config,
+            &mut registered,
+            &mut missed_pings,
+            &socket,
+        )
+        .await
+        .unwrap();
+
+        let _job = jobs.track("job-1");
+        let _other = jobs.track("job-2");
+        let (ack_tx, ack_rx) = oneshot::channel();
+        sinks
+            .read()
+            .await
+            .owned_by(&message_tx)
+            .unwrap()
+            .ack_waiters
+            .write()
+            .await
+            .insert("job-1".to_string(), ack_tx);
+
+        let progress = |id: &str, inserted_chars| SinkMessage::Progress {
+            schema_version: "1.0".to_string(),
+            id: id.to_string(),
+            inserted_chars,
+            total_chars: 100,
+        };
+        let ack = ack("job-1", AckStatus::Ok);
+        // job-2 was never sent to this sink, so its report is ignored
+        for message in [progress("job-1", 40), progress("job-2", 10), ack] {
+            SinkManager::handle_sink_message(
+                message,
+                &sinks,
+                &message_tx,
+                &config,
+                &mut registered,
+                &mut missed_pings,
+                &socket,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            jobs.get("job-1").unwrap().progress,
+            Some(JobProgress {
+                inserted_chars: 40,
+                total_chars: 100
+            })
+        );
+        assert_eq!(jobs.get("job-2").unwrap().progress, None);
+        assert_eq!(ack_rx.await.unwrap().status, AckStatus::Ok);
+    }
+
+    fn test_sink(providers: &[&str]) -> ActiveSink {
+        ActiveSink {
+            connection: SinkConnection::new(
+                Vec::new(),
+                providers.iter().map(|p| p.to_string()).collect(),
+                "1.0.0".to_string(),
+            ),
+            order: 0,
+            message_sender: mpsc::unbounded_channel().0,
+            ack_waiters: Arc::new(RwLock::new(HashMap::new())),
+            ready: watch::channel(true).0,
+            gate: Arc::default(),
+            socket: Arc::default(),
+            reconnect_count: 0,
+            orphan_acks: OrphanAcks::default(),
+            sessions: Mutex::new(HashSet::new()),
+        }
+    }
+
+    #[test]
+    fn test_registrations_are_counted_per_version_and_providers() {
+        let mut registry = SinkRegistry::default();
+        let connection = |providers: &[&str], version: &str| {
+            SinkConnection::new(
+                Vec::new(),
+                providers.iter().map(|p| p.to_string()).collect(),
+                version.to_string(),
+            )
+        };
+
+        assert_eq!(
+            registry.count_registration(&connection(&["a", "b"], "1.0")),
+            0
+        );
+        assert_eq!(
+            registry.count_registration(&connection(&["b", "a"], "1.0")),
+            1
+        );
+        assert_eq!(
+            registry.count_registration(&connection(&["a", "b"], "1.0")),
+            2
+        );
+        assert_eq!(
+            registry.count_registration(&connection(&["a", "b"], "1.1")),
+            0
+        );
+        assert_eq!(registry.count_registration(&connection(&["a"], "1.0")), 0);
+    }
+
+    #[test]
+    fn test_reconnect_fingerprints_are_capped() {
+        let mut registry = SinkRegistry::default();
+        let connection = |version: usize| {
+            SinkConnection::new(Vec::new(), vec!["a".to_string()], version.to_string())
+        };
+
+        for version in 0..MAX_RECONNECT_FINGERPRINTS {
+            registry.count_registration(&connection(version));
+        }
+        // Registering again keeps a fingerprint from being the next one forgotten
+        assert_eq!(registry.count_registration(&connection(0)), 1);
+
+        registry.count_registration(&connection(MAX_RECONNECT_FINGERPRINTS));
+        assert_eq!(registry.reconnects.len(), MAX_RECONNECT_FINGERPRINTS);
+        assert_eq!(registry.count_registration(&connection(0)), 2);
+        // The least recently registered fingerprint was forgotten, so it starts over
+        assert_eq!(registry.count_registration(&connection(1)), 0);
+    }
+
+    #[test]
+    fn test_registry_selects_sink_by_provider() {
+        let mut registry = SinkRegistry::default();
+        assert!(matches!(registry.select(None), Err(AppError::NoSink)));
+
+        let chatgpt = test_sink(&["chatgpt"]);
+        let claude = test_sink(&["claude", "gemini"]);
+        let (chatgpt_id, claude_id) = (chatgpt.connection.id, claude.connection.id);
+        registry.insert(chatgpt);
+        registry.insert(claude);
+
+        assert_eq!(registry.select(None).unwrap().connection.id, chatgpt_id);
+        assert_eq!(
+            registry.select(Some("gemini")).unwrap().connection.id,
+            claude_id
+        );
+        assert!(matches!(
+            registry.select(Some("mistral")),
+            Err(AppError::NoMatchingSink { provider }) if provider == "mistral"
+        ));
+
+        // Only sinks sharing a provider are replaced by a new registration
+        assert_eq!(
+            registry.conflicting(&["claude".to_string()]),
+            vec![claude_id]
+        );
+        assert!(registry.conflicting(&["mistral".to_string()]).is_empty());
+        assert!(registry.conflicting(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_checks_per_provider_capabilities() {
+        let config = ServerConfig {
+            dispatch_timeout: std::time::Duration::from_millis(50),
+            ..Default::default()
+        };
+        let manager = SinkManager::new(config);
+        let connection = SinkConnection::new(
+            vec!["append".to_string()],
+            vec!["chatgpt".to_string(), "claude".to_string()],
+            "1.0.0".to_string(),
+        )
+        .with_provider_capabilities(HashMap::from([
+            (
+                "chatgpt".to_string(),
+                vec!["append".to_string(), "cursor".to_string()],
+            ),
+            ("claude".to_string(), vec!["append".to_string()]),
+        ]));
+        manager.set_test_sink(connection).await;
+
+        let payload = |provider: &str| InsertTextPayload {
+            text: "hello".to_string(),
+            target: Some(TargetSpec {
+                provider: Some(provider.to_string()),
+                session_policy: None,
+            }),
+            ..Default::default()
+        };
+
+        let result = manager
+            .dispatch_job(
+                "job-1".to_string(),
+                payload("claude"),
+                0,
+                Some("cursor"),
+                None,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::UnsupportedCapability { provider: Some(p), .. }) if p == "claude"
+        ));
+
+        // The job is sent to the sink, which never acks in this test
+        let result = manager
+            .dispatch_job(
+                "job-2".to_string(),
+                payload("chatgpt"),
+                0,
+                Some("cursor"),
+                None,
+            )
+            .await;
+        assert!(matches!(result, Err(AppError::DispatchTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_replace_mode_requires_replace_capability() {
+        let config = ServerConfig {
+            dispatch_timeout: std::time::Duration::from_millis(50),
+            // A downgrade for `replace` must not let replace jobs through
+            capability_downgrades: HashMap::from([("replace".to_string(), Placement::Bottom)]),
+            ..Default::default()
+        };
+        let manager = SinkManager::new(config);
+        let connection = SinkConnection::new(
+            vec!["append".to_string()],
+            vec!["chatgpt".to_string(), "claude".to_string()],
+            "1.0.0".to_string(),
+        )
+        .with_provider_capabilities(HashMap::from([
+            (
+                "chatgpt".to_string(),
+                vec!["append".to_string(), "replace".to_string()],
+            ),
+            ("claude".to_string(), vec!["append".to_string()]),
+        ]));
+        manager.set_test_sink(connection).await;
+
+        let payload = |provider: &str, mode| InsertTextPayload {
+            text: "hello".to_string(),
+            mode,
+            target: Some(TargetSpec {
+                provider: Some(provider.to_string()),
+                session_policy: None,
+            }),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            manager
+                .route(Some("claude"), None, None, InsertMode::Replace)
+                .await,
+            Err(AppError::UnsupportedCapability { capability, .. }) if capability == "replace"
+        ));
+        assert!(manager
+            .route(Some("claude"), None, None, InsertMode::Prepend)
+            .await
+            .is_ok());
+        assert!(manager
+            .route(Some("chatgpt"), None, None, InsertMode::Replace)
+            .await
+            .is_ok());
+
+        let result = manager
+            .dispatch_job(
+                "job-1".to_string(),
+                payload("claude", InsertMode::Replace),
+                0,
+                None,
+                None,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::UnsupportedCapability { capability, .. }) if capability == "replace"
+        ));
+
+        // The job is sent to the sink, which never acks in this test
+        let result = manager
+            .dispatch_job(
+                "job-2".to_string(),
+                payload("chatgpt", InsertMode::Replace),
+                0,
+                None,
+                None,
+            )
+            .await;
+        assert!(matches!(result, Err(AppError::DispatchTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_timeout_override_is_capped() {
+        let config = ServerConfig {
+            max_dispatch_timeout: std::time::Duration::from_millis(80),
+            ..Default::default()
+        };
+        let manager = SinkManager::new(config);
+        manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.0.0".to_string()))
+            .await;
+
+        let payload = || InsertTextPayload {
+            text: "hello".to_string(),
+            ..Default::default()
+        };
+
+        let result = manager
+            .dispatch_job(
+                "job-1".to_string(),
+                payload(),
+                0,
+                None,
+                Some(std::time::Duration::from_millis(20)),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::DispatchTimeout { timeout_ms: 20 })
+        ));
+
+        let result = manager
+            .dispatch_job(
+                "job-2".to_string(),
+                payload(),
+                0,
+                None,
+                Some(std::time::Duration::from_secs(60)),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::DispatchTimeout { timeout_ms: 80 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_sheds_jobs_over_inflight_byte_budget() {
+        let config = ServerConfig {
+            max_inflight_bytes: 10,
+            dispatch_timeout: std::time::Duration::from_millis(100),
+            ..Default::default()
+        };
+        let manager = Arc::new(SinkManager::new(config));
+        manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.0.0".to_string()))
+            .await;
+
+        let payload = |text: &str| InsertTextPayload {
+            text: text.to_string(),
+            ..Default::default()
+        };
+
+        // The test sink never acks, so the first job holds its bytes until it times out
+        let first = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move {
+                manager
+                    .dispatch_job("job-1".to_string(), payload("12345678"), 0, None, None)
+                    .await
+            }
+        });
+        while manager.inflight_bytes() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(manager.inflight_bytes(), 8);
+
+        let result = manager
+            .dispatch_job("job-2".to_string(), payload("12345678"), 0, None, None)
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::MemoryPressure {
+                inflight: 8,
+                max: 10
+            })
+        ));
+
+        assert!(matches!(
+            first.await.unwrap(),
+            Err(AppError::DispatchTimeout { .. })
+        ));
+        assert_eq!(manager.inflight_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_rejects_jobs_over_per_sink_inflight_limit() {
+        let config = ServerConfig {
+            max_inflight_per_sink: 2,
+            dispatch_timeout: std::time::Duration::from_millis(200),
+            ..Default::default()
+        };
+        let manager = Arc::new(SinkManager::new(config));
+        manager
+            .set_test_sink(SinkConnection::new(vec![], vec![], "1.0.0".to_string()))
+            .await;
+
+        // The test sink never acks, so both jobs stay in flight until they time out
+        let held: Vec<_> = ["job-1", "job-2"]
+            .into_iter()
+            .map(|id| {
+                let manager = Arc::clone(&manager);
+                tokio::spawn(async move {
+                    manager
+                        .dispatch_job(id.to_string(), InsertTextPayload::default(), 0, None, None)
+                        .await
+                })
+            })
+            .collect();
+        while manager.list_sinks().await[0].inflight_jobs < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        let result = manager
+            .dispatch_job(
+                "job-3".to_string(),
+                InsertTextPayload::default(),
+                0,
+                None,
+                None,
+            )
+            .await;
+        assert!(matches!(result, Err(AppError::SinkBusy { inflight: 2 })));
+        assert_eq!(manager.jobs().get("job-3").unwrap().sink_id, None);
+
+        for job in held {
+            assert!(matches!(
+                job.await.unwrap(),
+                Err(AppError::DispatchTimeout { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_echo_marker_and_summary() {
+        let mut payload = InsertTextPayload {
+            text: "secret snippet".to_string(),
+            metadata: Some(serde_json::json!({"trace": "abc"})),
+            ..Default::default()
+        };
+        assert!(!payload.is_echo());
+
+        payload.metadata = Some(serde_json::json!({ ECHO_METADATA_KEY: true }));
+        assert!(payload.is_echo());
+
+        let summary = payload.echo_summary();
+        assert_eq!(summary["text_bytes"], 14);
+        assert!(!summary.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn test_payload_expiry_is_relayed_and_checked() {
+        let now = Utc::now();
+        let request = InsertTextRequest {
+            text: "stale soon".to_string(),
+            expires_at: Some(now + chrono::Duration::seconds(30)),
+            ..Default::default()
+        };
+        let payload = InsertTextPayload::from(request);
+
+        assert!(!payload.is_expired_at(now));
+        assert!(payload.is_expired_at(now + chrono::Duration::seconds(31)));
+        assert!(!InsertTextPayload::default().is_expired_at(now));
+
+        let json = serde_json::to_value(&payload).unwrap();
+        assert!(json["expires_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_busy_sink_receives_parked_jobs_by_priority() {
+        let config = ServerConfig {
+            dispatch_timeout: std::time::Duration::from_secs(5),
+            ..Default::default()
+        };
+        let manager = Arc::new(SinkManager::new(config));
+        let (message_tx, mut message_rx) = mpsc::unbounded_channel();
+        let mut sink = test_sink(&[]);
+        sink.message_sender = message_tx;
+        sink.ready.send_replace(false);
+        let gate = Arc::clone(&sink.gate);
+        manager.sinks.write().await.insert(sink);
+
+        // Submitted low priority first, each parking before the next is submitted
+        let mut dispatches = Vec::new();
+        for (id, priority) in [("bulk-1", 0), ("bulk-2", 0), ("urgent", 9)] {
+            let manager = Arc::clone(&manager);
+            dispatches.push(tokio::spawn(async move {
+                manager
+                    .dispatch_job(
+                        id.to_string(),
+                        InsertTextPayload::default(),
+                        priority,
+                        None,
+                        None,
+                    )
+                    .await
+            }));
+            while gate.parked.lock().unwrap().len() < dispatches.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // The sink reports ready again, as with a `ready` message
+        for sink in manager.sinks.read().await.sinks.values() {
+            sink.ready.send_replace(true);
+        }
+
+        let mut order = Vec::new();
+        while order.len() < 3 {
+            match message_rx.recv().await.unwrap() {
+                Outbound::Job {
+                    message: RelayMessage::InsertText { id, .. },
+                    ..
+                } => order.push(id),
+                other => panic!("Unexpected frame: {:?}", other),
+            }
+        }
+        assert_eq!(order, vec!["urgent", "bulk-1", "bulk-2"]);
+
+        for dispatch in dispatches {
+            dispatch.abort();
+        }
+    }
+
     #[test]
     fn test_relay_message_serialization() {
         let job_msg = RelayMessage::InsertText {
             schema_version: "1.0".to_string(),
             id: "test-job".to_string(),
-            payload: InsertTextPayload {
+            payload: Box::new(InsertTextPayload {
                 text: "test content".to_string(),
                 placement: Some(Placement::Bottom),
+                mode: InsertMode::Prepend,
                 source: SourceInfo {
                     client: "cli".to_string(),
                     label: Some("CLI".to_string()),
@@ -494,7 +2952,10 @@ mod tests {
                     session_policy: Some(SessionPolicy::ReuseOrCreate),
                 }),
                 metadata: Some(serde_json::json!({"key": "value"})),
-            },
+                content_type: Some("text/markdown".to_string()),
+                expires_at: None,
+                new_session: false,
+            }),
         };
 
         let json = serde_json::to_string(&job_msg).unwrap();
@@ -504,14 +2965,97 @@ mod tests {
             RelayMessage::InsertText { id, payload, .. } => {
                 assert_eq!(id, "test-job");
                 assert_eq!(payload.placement, Some(Placement::Bottom));
+                assert_eq!(payload.mode, InsertMode::Prepend);
                 assert_eq!(payload.source.client, "cli");
                 assert_eq!(
                     payload.target.as_ref().and_then(|t| t.provider.clone()),
                     Some("chatgpt".to_string())
                 );
                 assert_eq!(payload.metadata, Some(serde_json::json!({"key": "value"})));
+                assert_eq!(payload.content_type.as_deref(), Some("text/markdown"));
             }
             _ => panic!("Wrong message type"),
         }
     }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("send failed")]
+    struct TestSendError(#[source] std::io::Error);
+
+    /// Sink that fails the first sends with the given errors, then records what it receives.
+    struct FlakySink {
+        failures: Vec<std::io::ErrorKind>,
+        attempts: usize,
+        delivered: Vec<Message>,
+    }
+
+    impl Sink<Message> for FlakySink {
+        type Error = TestSendError;
+
+        fn poll_ready(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn start_send(
+            mut self: std::pin::Pin<&mut Self>,
+            item: Message,
+        ) -> Result<(), Self::Error> {
+            self.attempts += 1;
+            if self.failures.is_empty() {
+                self.delivered.push(item);
+                Ok(())
+            } else {
+                Err(TestSendError(self.failures.remove(0).into()))
+            }
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_recovers_from_transient_error() {
+        let mut sink = FlakySink {
+            failures: vec![std::io::ErrorKind::WouldBlock],
+            attempts: 0,
+            delivered: Vec::new(),
+        };
+
+        send_with_retry(&mut sink, Message::Text("hello".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(sink.attempts, 2);
+        assert_eq!(sink.delivered, vec![Message::Text("hello".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_gives_up_on_fatal_error() {
+        let mut sink = FlakySink {
+            failures: vec![std::io::ErrorKind::BrokenPipe],
+            attempts: 0,
+            delivered: Vec::new(),
+        };
+
+        assert!(
+            send_with_retry(&mut sink, Message::Text("hello".to_string()))
+                .await
+                .is_err()
+        );
+        assert_eq!(sink.attempts, 1);
+        assert!(sink.delivered.is_empty());
+    }
 }